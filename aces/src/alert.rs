@@ -0,0 +1,567 @@
+/// The severity of an alert.
+#[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Info,
+    #[default]
+    Warning,
+    Critical,
+}
+
+/// A condition on a snapshot that raises an alert.
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Condition {
+    /// Any cell voltage above `threshold` (mV).
+    CellVoltageAbove { threshold: f32 },
+    /// Any cell voltage below `threshold` (mV).
+    CellVoltageBelow { threshold: f32 },
+    /// Difference between the highest and lowest cell above `threshold` (mV).
+    CellDeltaAbove { threshold: f32 },
+    /// Any NTC temperature above `threshold` (°C).
+    NtcAbove { threshold: f32 },
+    /// Any NTC temperature below `threshold` (°C).
+    NtcBelow { threshold: f32 },
+    /// State of charge below `threshold` (%).
+    SocBelow { threshold: f32 },
+    /// The protection state is one of the named `states` (e.g. `cov`, see
+    /// `ProtectionOfState::NAMES`). An empty list matches any protection.
+    ProtectionSet {
+        #[serde(default)]
+        states: Vec<String>,
+    },
+    /// Any of the named `BatteryProtect` counters increased since the previous
+    /// snapshot. An empty list matches every counter.
+    ProtectCounterIncreased {
+        #[serde(default)]
+        counters: Vec<String>,
+    },
+    /// The charge MOSFET is off while no protection is active.
+    ChargeMosfetOff,
+    /// The discharge MOSFET is off while no protection is active.
+    DischargeMosfetOff,
+}
+
+/// A named condition with its severity, hysteresis and minimum duration.
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+    pub name: String,
+    #[serde(default)]
+    pub severity: Severity,
+    pub condition: Condition,
+    /// How far the value has to move back past the threshold before the
+    /// alert clears, in the unit of the condition.
+    #[serde(default)]
+    pub hysteresis: f32,
+    /// How long the condition has to hold before the alert fires (seconds).
+    #[serde(default)]
+    pub min_duration: u64,
+}
+
+/// The alert rules as stored in a configuration file.
+///
+/// ```toml
+/// [[rule]]
+/// name = "cell high"
+/// severity = "critical"
+/// condition = { type = "cell_voltage_above", threshold = 3650 }
+/// hysteresis = 50
+/// min_duration = 60
+/// ```
+#[derive(PartialEq, Clone, Debug, Default, Deserialize)]
+pub struct AlertRules {
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<AlertRule>,
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+    Fired,
+    Cleared,
+}
+
+/// An alert that fired or cleared.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize)]
+pub struct Alert {
+    pub timestamp: DateTime<FixedOffset>,
+    pub rule: String,
+    pub severity: Severity,
+    pub state: AlertState,
+    pub message: String,
+}
+
+/// Evaluates alert rules against consecutive snapshots.
+pub struct AlertEngine {
+    rules: Vec<(AlertRule, RuleState)>,
+    previous: Option<Snapshot>,
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+enum RuleState {
+    Inactive,
+    Pending(DateTime<FixedOffset>),
+    Firing,
+}
+
+enum Measurement {
+    Above { value: f32, threshold: f32 },
+    Below { value: f32, threshold: f32 },
+    Flag(bool),
+    Unavailable,
+}
+
+#[derive(Eq, PartialEq, Debug, thiserror::Error)]
+pub enum AlertError {
+    #[error("Unknown protection state {0}")]
+    UnknownProtection(String),
+    #[error("Unknown protect counter {0}")]
+    UnknownCounter(String),
+}
+
+impl AlertRules {
+    pub fn parse(s: &str) -> Result<Self> {
+        let rules: Self = toml::from_str(s)?;
+        rules.validate()?;
+        Ok(rules)
+    }
+
+    /// Checks that the protection states and counters named by the rules
+    /// exist.
+    pub fn validate(&self) -> std::result::Result<(), AlertError> {
+        for rule in &self.rules {
+            match &rule.condition {
+                Condition::ProtectionSet { states } => {
+                    if let Some(name) = states
+                        .iter()
+                        .find(|name| ProtectionOfState::from_name(name).is_none())
+                    {
+                        return Err(AlertError::UnknownProtection(name.clone()));
+                    }
+                }
+                Condition::ProtectCounterIncreased { counters } => {
+                    if let Some(name) = counters
+                        .iter()
+                        .find(|name| !BatteryProtect::FIELD_NAMES.contains(&name.as_str()))
+                    {
+                        return Err(AlertError::UnknownCounter(name.clone()));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+}
+
+impl Condition {
+    fn measure(&self, snapshot: &Snapshot, previous: Option<&Snapshot>) -> Measurement {
+        let detail = &snapshot.detail;
+        let no_protection = detail.protection_of_state == ProtectionOfState::NONE;
+
+        match self {
+            Self::CellVoltageAbove { threshold } => match snapshot.max_cell_voltage() {
+                Some(value) => Measurement::Above {
+                    value: value as f32,
+                    threshold: *threshold,
+                },
+                None => Measurement::Unavailable,
+            },
+            Self::CellVoltageBelow { threshold } => match snapshot.min_cell_voltage() {
+                Some(value) => Measurement::Below {
+                    value: value as f32,
+                    threshold: *threshold,
+                },
+                None => Measurement::Unavailable,
+            },
+            Self::CellDeltaAbove { threshold } => match snapshot.cell_delta() {
+                Some(value) => Measurement::Above {
+                    value: value as f32,
+                    threshold: *threshold,
+                },
+                None => Measurement::Unavailable,
+            },
+            Self::NtcAbove { threshold } => {
+                match snapshot.temperatures().into_iter().reduce(f32::max) {
+                    Some(value) => Measurement::Above {
+                        value,
+                        threshold: *threshold,
+                    },
+                    None => Measurement::Unavailable,
+                }
+            }
+            Self::NtcBelow { threshold } => {
+                match snapshot.temperatures().into_iter().reduce(f32::min) {
+                    Some(value) => Measurement::Below {
+                        value,
+                        threshold: *threshold,
+                    },
+                    None => Measurement::Unavailable,
+                }
+            }
+            Self::SocBelow { threshold } => Measurement::Below {
+                value: detail.residual_capacity_percent as f32,
+                threshold: *threshold,
+            },
+            Self::ProtectionSet { states } => Measurement::Flag(if states.is_empty() {
+                !no_protection
+            } else {
+                states.iter().any(|name| {
+                    ProtectionOfState::from_name(name) == Some(detail.protection_of_state)
+                })
+            }),
+            Self::ProtectCounterIncreased { counters } => match previous {
+                Some(previous) => Measurement::Flag(
+                    BatteryProtect::FIELD_NAMES
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, name)| {
+                            counters.is_empty() || counters.iter().any(|c| c == *name)
+                        })
+                        .any(|(idx, _)| {
+                            snapshot.protect.value_at(idx) > previous.protect.value_at(idx)
+                        }),
+                ),
+                None => Measurement::Unavailable,
+            },
+            Self::ChargeMosfetOff => Measurement::Flag(!detail.charge && no_protection),
+            Self::DischargeMosfetOff => Measurement::Flag(!detail.discharge && no_protection),
+        }
+    }
+
+    fn describe(&self, snapshot: &Snapshot) -> String {
+        match self {
+            Self::CellVoltageAbove { threshold } | Self::CellVoltageBelow { threshold } => {
+                format!(
+                    "cell voltages {:?} mV (limit {} mV)",
+                    snapshot.voltage, threshold
+                )
+            }
+            Self::CellDeltaAbove { threshold } => format!(
+                "cell delta {} mV (limit {} mV)",
+                snapshot.cell_delta().unwrap_or_default(),
+                threshold
+            ),
+            Self::NtcAbove { threshold } | Self::NtcBelow { threshold } => format!(
+                "temperatures {:?} °C (limit {} °C)",
+                snapshot.temperatures(),
+                threshold
+            ),
+            Self::SocBelow { threshold } => format!(
+                "state of charge {}% (limit {}%)",
+                snapshot.detail.residual_capacity_percent, threshold
+            ),
+            Self::ProtectionSet { .. } => {
+                let state = snapshot.detail.protection_of_state;
+                match ProtectionOfState::NAMES.get(state.0 as usize) {
+                    Some(name) => format!("protection state {}", name),
+                    None => format!("protection state {}", state.0),
+                }
+            }
+            Self::ProtectCounterIncreased { .. } => {
+                format!("protection counters {:?}", snapshot.protect)
+            }
+            Self::ChargeMosfetOff => "charge MOSFET off".to_string(),
+            Self::DischargeMosfetOff => "discharge MOSFET off".to_string(),
+        }
+    }
+}
+
+impl Measurement {
+    /// Whether the condition holds, taking hysteresis into account when the
+    /// alert is already firing.
+    fn is_active(&self, firing: bool, hysteresis: f32) -> bool {
+        let hysteresis = if firing { hysteresis } else { 0.0 };
+        match *self {
+            Self::Above { value, threshold } => value > threshold - hysteresis,
+            Self::Below { value, threshold } => value < threshold + hysteresis,
+            Self::Flag(active) => active,
+            Self::Unavailable => false,
+        }
+    }
+}
+
+impl AlertEngine {
+    pub fn new(rules: AlertRules) -> Self {
+        AlertEngine {
+            rules: rules
+                .rules
+                .into_iter()
+                .map(|rule| (rule, RuleState::Inactive))
+                .collect(),
+            previous: None,
+        }
+    }
+
+    /// Evaluates every rule against the snapshot, returning the alerts that
+    /// fired or cleared.
+    pub fn evaluate(&mut self, snapshot: &Snapshot) -> Vec<Alert> {
+        let now = snapshot.timestamp;
+        let mut alerts = Vec::new();
+
+        for (rule, state) in self.rules.iter_mut() {
+            let measurement = rule.condition.measure(snapshot, self.previous.as_ref());
+            let active = measurement.is_active(*state == RuleState::Firing, rule.hysteresis);
+
+            let next = match (*state, active) {
+                (RuleState::Firing, true) => RuleState::Firing,
+                (RuleState::Firing, false) => {
+                    alerts.push(alert(rule, snapshot, AlertState::Cleared));
+                    RuleState::Inactive
+                }
+                (_, false) => RuleState::Inactive,
+                (RuleState::Inactive, true) => RuleState::Pending(now),
+                (pending, true) => pending,
+            };
+
+            *state = match next {
+                RuleState::Pending(since)
+                    if (now - since).num_seconds() >= rule.min_duration as i64 =>
+                {
+                    alerts.push(alert(rule, snapshot, AlertState::Fired));
+                    RuleState::Firing
+                }
+                next => next,
+            };
+        }
+
+        self.previous = Some(snapshot.clone());
+        alerts
+    }
+}
+
+fn alert(rule: &AlertRule, snapshot: &Snapshot, state: AlertState) -> Alert {
+    Alert {
+        timestamp: snapshot.timestamp,
+        rule: rule.name.clone(),
+        severity: rule.severity,
+        state,
+        message: rule.condition.describe(snapshot),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_parse_rules() {
+        let rules = AlertRules::parse(
+            r#"
+            [[rule]]
+            name = "cell high"
+            severity = "critical"
+            condition = { type = "cell_voltage_above", threshold = 3650 }
+            hysteresis = 50
+            min_duration = 60
+
+            [[rule]]
+            name = "short circuit"
+            condition = { type = "protect_counter_increased", counters = ["short_circuit"] }
+
+            [[rule]]
+            name = "protection"
+            condition = { type = "protection_set" }
+
+            [[rule]]
+            name = "temperature"
+            condition = { type = "protection_set", states = ["otc", "OTD"] }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            rules.rules,
+            vec![
+                AlertRule {
+                    name: "cell high".to_string(),
+                    severity: Severity::Critical,
+                    condition: Condition::CellVoltageAbove { threshold: 3650.0 },
+                    hysteresis: 50.0,
+                    min_duration: 60,
+                },
+                AlertRule {
+                    name: "short circuit".to_string(),
+                    severity: Severity::Warning,
+                    condition: Condition::ProtectCounterIncreased {
+                        counters: vec!["short_circuit".to_string()]
+                    },
+                    hysteresis: 0.0,
+                    min_duration: 0,
+                },
+                AlertRule {
+                    name: "protection".to_string(),
+                    severity: Severity::Warning,
+                    condition: Condition::ProtectionSet { states: vec![] },
+                    hysteresis: 0.0,
+                    min_duration: 0,
+                },
+                AlertRule {
+                    name: "temperature".to_string(),
+                    severity: Severity::Warning,
+                    condition: Condition::ProtectionSet {
+                        states: vec!["otc".to_string(), "OTD".to_string()]
+                    },
+                    hysteresis: 0.0,
+                    min_duration: 0,
+                },
+            ]
+        );
+
+        assert!(
+            AlertRules::parse("[[rule]]\nname = \"x\"\ncondition = { type = \"nope\" }").is_err()
+        );
+        let err = AlertRules::parse(
+            "[[rule]]\nname = \"x\"\ncondition = { type = \"protection_set\", states = [\"hot\"] }",
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Unknown protection state hot");
+        let err = AlertRules::parse(
+            "[[rule]]\nname = \"x\"\ncondition = { type = \"protect_counter_increased\", counters = [\"short\"] }",
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Unknown protect counter short");
+    }
+
+    #[test]
+    fn test_hysteresis_and_min_duration() {
+        let mut engine = engine(AlertRule {
+            name: "cell high".to_string(),
+            severity: Severity::Critical,
+            condition: Condition::CellVoltageAbove { threshold: 3650.0 },
+            hysteresis: 50.0,
+            min_duration: 60,
+        });
+
+        assert_eq!(states(&mut engine, 0, 3660), vec![]);
+        assert_eq!(states(&mut engine, 30, 3660), vec![]);
+        assert_eq!(states(&mut engine, 60, 3660), vec![AlertState::Fired]);
+        // within the hysteresis band
+        assert_eq!(states(&mut engine, 90, 3620), vec![]);
+        assert_eq!(states(&mut engine, 120, 3600), vec![AlertState::Cleared]);
+        // too short to fire again
+        assert_eq!(states(&mut engine, 150, 3660), vec![]);
+        assert_eq!(states(&mut engine, 180, 3640), vec![]);
+        assert_eq!(states(&mut engine, 210, 3660), vec![]);
+    }
+
+    #[test]
+    fn test_protect_counter_increased() {
+        let mut engine = engine(AlertRule {
+            name: "short circuit".to_string(),
+            severity: Severity::Critical,
+            condition: Condition::ProtectCounterIncreased {
+                counters: vec!["short_circuit".to_string()],
+            },
+            hysteresis: 0.0,
+            min_duration: 0,
+        });
+
+        let mut s = snapshot(0, 3300);
+        assert!(engine.evaluate(&s).is_empty());
+
+        s.protect.cell_undervoltage += 1;
+        assert!(engine.evaluate(&s).is_empty());
+
+        s.protect.short_circuit += 1;
+        let alerts = engine.evaluate(&s);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].state, AlertState::Fired);
+        assert_eq!(alerts[0].severity, Severity::Critical);
+
+        assert_eq!(engine.evaluate(&s)[0].state, AlertState::Cleared);
+    }
+
+    #[test]
+    fn test_protection_set() {
+        let mut engine = engine(AlertRule {
+            name: "temperature".to_string(),
+            severity: Severity::Warning,
+            condition: Condition::ProtectionSet {
+                states: vec!["otc".to_string(), "otd".to_string()],
+            },
+            hysteresis: 0.0,
+            min_duration: 0,
+        });
+
+        // enumerated codes, not bits: UTC (6) shares a bit with OTC (5)
+        let mut s = snapshot(0, 3300);
+        s.detail.protection_of_state = ProtectionOfState::UTC;
+        assert!(engine.evaluate(&s).is_empty());
+
+        s.detail.protection_of_state = ProtectionOfState::OTD;
+        let alerts = engine.evaluate(&s);
+        assert_eq!(alerts[0].state, AlertState::Fired);
+        assert_eq!(alerts[0].message, "protection state otd");
+
+        s.detail.protection_of_state = ProtectionOfState::NONE;
+        assert_eq!(engine.evaluate(&s)[0].state, AlertState::Cleared);
+    }
+
+    #[test]
+    fn test_mosfet_off() {
+        let mut engine = engine(AlertRule {
+            name: "charge off".to_string(),
+            severity: Severity::Warning,
+            condition: Condition::ChargeMosfetOff,
+            hysteresis: 0.0,
+            min_duration: 0,
+        });
+
+        let mut s = snapshot(0, 3300);
+        s.detail.charge = false;
+        s.detail.protection_of_state = ProtectionOfState::COV;
+        assert!(engine.evaluate(&s).is_empty());
+
+        s.detail.protection_of_state = ProtectionOfState::NONE;
+        assert_eq!(engine.evaluate(&s)[0].state, AlertState::Fired);
+    }
+
+    fn engine(rule: AlertRule) -> AlertEngine {
+        AlertEngine::new(AlertRules { rules: vec![rule] })
+    }
+
+    fn states(engine: &mut AlertEngine, seconds: i64, cell: i16) -> Vec<AlertState> {
+        engine
+            .evaluate(&snapshot(seconds, cell))
+            .into_iter()
+            .map(|alert| alert.state)
+            .collect()
+    }
+
+    fn snapshot(seconds: i64, cell: i16) -> Snapshot {
+        SnapshotBuilder::at(seconds)
+            .cells(&[3300, cell, 3300, 3300])
+            .detail(BatteryDetail {
+                total_voltage: 1336,
+                residual_capacity: 5980,
+                standard_capacity: 10206,
+                cycles: 9,
+                date_of_production: 11156,
+                software_version: 32,
+                residual_capacity_percent: 59,
+                control_state: 3,
+                charge: true,
+                discharge: true,
+                battery_number: 4,
+                list_ntc: vec![212, 193, 190],
+                ..Default::default()
+            })
+            .build()
+    }
+
+    use super::*;
+    use crate::snapshot::SnapshotBuilder;
+    use crate::BatteryDetail;
+}
+
+use crate::{BatteryProtect, ProtectionOfState, Result, Snapshot};
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
@@ -0,0 +1,351 @@
+//! Import of btsnoop files, e.g. Android HCI snoop logs.
+//!
+//! Only ATT writes to the `TX_UUID` characteristic and notifications from the
+//! `RX_UUID` characteristic are kept. Their handles are taken from the GATT
+//! discovery when the log contains it, and otherwise from the first write or
+//! notification starting a frame.
+
+#[derive(Eq, PartialEq, Debug, thiserror::Error)]
+pub enum BtsnoopError {
+    #[error("Not a btsnoop file")]
+    InvalidHeader,
+    #[error("Unsupported btsnoop datalink type {0}")]
+    UnsupportedDatalink(u32),
+}
+
+/// Un-encapsulated HCI (H1), the packet type follows from the flags.
+const DATALINK_H1: u32 = 1001;
+/// HCI UART (H4), every packet starts with its type (as Android writes them).
+const DATALINK_H4: u32 = 1002;
+
+const H4_ACL: u8 = 0x02;
+const FLAG_RECEIVED: u32 = 0x01;
+const FLAG_COMMAND_OR_EVENT: u32 = 0x02;
+
+/// Packet boundary flag of an ACL packet continuing an L2CAP frame.
+const PB_CONTINUATION: u16 = 0b01;
+const L2CAP_ATT: u16 = 0x0004;
+
+const ATT_FIND_INFORMATION_RESPONSE: u8 = 0x05;
+const ATT_READ_BY_TYPE_RESPONSE: u8 = 0x09;
+const ATT_WRITE_REQUEST: u8 = 0x12;
+const ATT_WRITE_COMMAND: u8 = 0x52;
+const ATT_NOTIFICATION: u8 = 0x1b;
+const ATT_INDICATION: u8 = 0x1d;
+
+/// The Bluetooth base UUID, little endian without the 16-bit part (bytes 12
+/// and 13).
+const BASE_UUID: [u8; 16] = [
+    0xfb, 0x34, 0x9b, 0x5f, 0x80, 0x00, 0x00, 0x80, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+impl Capture {
+    /// Extracts the chunks written to and notified by the battery from a
+    /// btsnoop file.
+    pub fn from_btsnoop(bytes: &[u8]) -> std::result::Result<Self, BtsnoopError> {
+        if bytes.len() < 16 || &bytes[..8] != b"btsnoop\0" {
+            return Err(BtsnoopError::InvalidHeader);
+        }
+        let datalink = u32::from_be_bytes(bytes[12..16].try_into().unwrap());
+        if datalink != DATALINK_H1 && datalink != DATALINK_H4 {
+            return Err(BtsnoopError::UnsupportedDatalink(datalink));
+        }
+
+        let mut importer = Importer::default();
+        let mut rest = &bytes[16..];
+        while rest.len() >= 24 {
+            let included = u32::from_be_bytes(rest[4..8].try_into().unwrap()) as usize;
+            let flags = u32::from_be_bytes(rest[8..12].try_into().unwrap());
+            let timestamp = i64::from_be_bytes(rest[16..24].try_into().unwrap());
+            let Some(packet) = rest.get(24..24 + included) else {
+                log::warn!("btsnoop file ends with a truncated record");
+                break;
+            };
+            rest = &rest[24 + included..];
+
+            let acl = match datalink {
+                DATALINK_H4 => match packet.split_first() {
+                    Some((&H4_ACL, acl)) => acl,
+                    _ => continue,
+                },
+                _ if flags & FLAG_COMMAND_OR_EVENT == 0 => packet,
+                _ => continue,
+            };
+            importer.acl(timestamp, flags & FLAG_RECEIVED != 0, acl);
+        }
+
+        Ok(importer.capture)
+    }
+
+    /// Reads a btsnoop file, see `from_btsnoop`.
+    pub fn load_btsnoop<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self::from_btsnoop(&fs::read(path)?)?)
+    }
+}
+
+#[derive(Default)]
+struct Importer {
+    capture: Capture,
+    /// The timestamp of the first packet (µs).
+    start: Option<i64>,
+    /// Incomplete L2CAP frames, by connection handle and direction.
+    fragments: HashMap<(u16, bool), Vec<u8>>,
+    tx: Option<u16>,
+    rx: Option<u16>,
+}
+
+impl Importer {
+    fn acl(&mut self, timestamp: i64, received: bool, acl: &[u8]) {
+        if acl.len() < 4 {
+            return;
+        }
+        let header = u16::from_le_bytes([acl[0], acl[1]]);
+        let connection = header & 0x0fff;
+        let boundary = (header >> 12) & 0b11;
+        let data = &acl[4..];
+
+        let key = (connection, received);
+        if boundary == PB_CONTINUATION {
+            let Some(fragment) = self.fragments.get_mut(&key) else {
+                return;
+            };
+            fragment.extend_from_slice(data);
+        } else {
+            self.fragments.insert(key, data.to_vec());
+        }
+
+        let l2cap = &self.fragments[&key];
+        if l2cap.len() < 4 {
+            return;
+        }
+        let length = u16::from_le_bytes([l2cap[0], l2cap[1]]) as usize;
+        if l2cap.len() < 4 + length {
+            return;
+        }
+        let channel = u16::from_le_bytes([l2cap[2], l2cap[3]]);
+        let l2cap = self.fragments.remove(&key).unwrap();
+        if channel == L2CAP_ATT {
+            self.att(timestamp, &l2cap[4..4 + length]);
+        }
+    }
+
+    fn att(&mut self, timestamp: i64, pdu: &[u8]) {
+        let Some((&opcode, params)) = pdu.split_first() else {
+            return;
+        };
+        match opcode {
+            ATT_FIND_INFORMATION_RESPONSE => self.find_information(params),
+            ATT_READ_BY_TYPE_RESPONSE => self.read_by_type(params),
+            ATT_WRITE_REQUEST | ATT_WRITE_COMMAND if params.len() >= 2 => {
+                let handle = u16::from_le_bytes([params[0], params[1]]);
+                let value = &params[2..];
+                if self.tx.is_none() && is_request_start(value) {
+                    self.tx = Some(handle);
+                }
+                if self.tx == Some(handle) {
+                    self.record(timestamp, Direction::Write, value);
+                }
+            }
+            ATT_NOTIFICATION | ATT_INDICATION if params.len() >= 2 => {
+                let handle = u16::from_le_bytes([params[0], params[1]]);
+                let value = &params[2..];
+                if self.rx.is_none() && value.first() == Some(&Frame::START) {
+                    self.rx = Some(handle);
+                }
+                if self.rx == Some(handle) {
+                    self.record(timestamp, Direction::Notify, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Handles and types of attributes, including characteristic values.
+    fn find_information(&mut self, params: &[u8]) {
+        let size = match params.first() {
+            Some(1) => 2,
+            Some(2) => 16,
+            _ => return,
+        };
+        for entry in params[1..].chunks_exact(2 + size) {
+            let handle = u16::from_le_bytes([entry[0], entry[1]]);
+            self.discover(handle, &entry[2..]);
+        }
+    }
+
+    /// Characteristic declarations: properties, value handle and UUID.
+    fn read_by_type(&mut self, params: &[u8]) {
+        let Some(&size) = params.first() else {
+            return;
+        };
+        if size != 7 && size != 21 {
+            return;
+        }
+        for entry in params[1..].chunks_exact(size as usize) {
+            let handle = u16::from_le_bytes([entry[3], entry[4]]);
+            self.discover(handle, &entry[5..]);
+        }
+    }
+
+    fn discover(&mut self, handle: u16, uuid: &[u8]) {
+        match uuid16(uuid) {
+            Some(TX_UUID) => self.tx = Some(handle),
+            Some(RX_UUID) => self.rx = Some(handle),
+            _ => {}
+        }
+    }
+
+    fn record(&mut self, timestamp: i64, direction: Direction, data: &[u8]) {
+        let start = *self.start.get_or_insert(timestamp);
+        self.capture.records.push(CaptureRecord {
+            t: (timestamp - start).max(0) as u64,
+            direction,
+            data: data.to_vec(),
+        });
+    }
+}
+
+/// The 16-bit UUID, given as is or as a (little endian) 128-bit UUID based on
+/// the Bluetooth base UUID.
+fn uuid16(uuid: &[u8]) -> Option<u16> {
+    match uuid.len() {
+        2 => Some(u16::from_le_bytes([uuid[0], uuid[1]])),
+        16 if uuid[..12] == BASE_UUID[..12] && uuid[14..] == BASE_UUID[14..] => {
+            Some(u16::from_le_bytes([uuid[12], uuid[13]]))
+        }
+        _ => None,
+    }
+}
+
+fn is_request_start(value: &[u8]) -> bool {
+    matches!(value, [Frame::START, Frame::READ | Frame::WRITE, ..])
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_from_btsnoop() {
+        let mut uuid = BASE_UUID;
+        uuid[12..14].copy_from_slice(&RX_UUID.to_le_bytes());
+        let mut find_information = vec![ATT_FIND_INFORMATION_RESPONSE, 0x02, 0x22, 0x00];
+        find_information.extend_from_slice(&uuid);
+
+        let read_by_type = [
+            ATT_READ_BY_TYPE_RESPONSE,
+            0x07,
+            0x24,
+            0x00,
+            0x08,
+            0x25,
+            0x00,
+            0x02,
+            0xff,
+        ];
+
+        let mut write = vec![ATT_WRITE_COMMAND, 0x25, 0x00];
+        write.extend_from_slice(Request::BatteryVoltage.bytes());
+        // the L2CAP frame is split over two ACL packets
+        let mut first = acl(0x0040, &[ATT_NOTIFICATION, 0x22, 0x00, 0xdd, 0x04, 0x00]);
+        let continuation = [H4_ACL, 0x40, 0x10, 0x03, 0x00, 0x08, 0xca, 0xfe];
+        first[5] = 9;
+
+        let file = btsnoop(&[
+            (1_000, true, acl(0x0040, &find_information)),
+            (2_000, true, acl(0x0040, &read_by_type)),
+            (3_000, false, acl(0x0040, &write)),
+            // another characteristic
+            (
+                3_500,
+                true,
+                acl(0x0040, &[ATT_NOTIFICATION, 0x30, 0x00, 0xdd]),
+            ),
+            (4_000, true, first),
+            (4_500, true, continuation.to_vec()),
+        ]);
+
+        let capture = Capture::from_btsnoop(&file).unwrap();
+        assert_eq!(
+            capture.records,
+            vec![
+                CaptureRecord {
+                    t: 0,
+                    direction: Direction::Write,
+                    data: Request::BatteryVoltage.bytes().to_vec(),
+                },
+                CaptureRecord {
+                    t: 1_500,
+                    direction: Direction::Notify,
+                    data: vec![0xdd, 0x04, 0x00, 0x08, 0xca, 0xfe],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_from_btsnoop_without_discovery() {
+        let mut write = vec![ATT_WRITE_REQUEST, 0x25, 0x00];
+        write.extend_from_slice(Request::BatteryDetail.bytes());
+
+        let file = btsnoop(&[
+            (0, true, acl(0x0001, &[ATT_NOTIFICATION, 0x30, 0x00, 0x01])),
+            (10, false, acl(0x0001, &write)),
+            (20, true, acl(0x0001, &[ATT_NOTIFICATION, 0x22, 0x00, 0xdd])),
+            (30, true, acl(0x0001, &[ATT_NOTIFICATION, 0x22, 0x00, 0x03])),
+            (40, true, acl(0x0001, &[ATT_NOTIFICATION, 0x30, 0x00, 0x02])),
+        ]);
+
+        let capture = Capture::from_btsnoop(&file).unwrap();
+        let data: Vec<_> = capture.records.iter().map(|r| r.data.clone()).collect();
+        assert_eq!(
+            data,
+            vec![
+                Request::BatteryDetail.bytes().to_vec(),
+                vec![0xdd],
+                vec![0x03]
+            ]
+        );
+
+        assert_eq!(
+            Capture::from_btsnoop(b"not a btsnoop file"),
+            Err(BtsnoopError::InvalidHeader)
+        );
+    }
+
+    /// An H4 ACL packet carrying an ATT PDU.
+    fn acl(connection: u16, att: &[u8]) -> Vec<u8> {
+        let mut packet = vec![H4_ACL];
+        packet.extend_from_slice(&(connection | 0x2000).to_le_bytes());
+        packet.extend_from_slice(&(att.len() as u16 + 4).to_le_bytes());
+        packet.extend_from_slice(&(att.len() as u16).to_le_bytes());
+        packet.extend_from_slice(&L2CAP_ATT.to_le_bytes());
+        packet.extend_from_slice(att);
+        packet
+    }
+
+    fn btsnoop(packets: &[(i64, bool, Vec<u8>)]) -> Vec<u8> {
+        let mut file = b"btsnoop\0".to_vec();
+        file.extend_from_slice(&1u32.to_be_bytes());
+        file.extend_from_slice(&DATALINK_H4.to_be_bytes());
+        for (timestamp, received, packet) in packets {
+            let len = packet.len() as u32;
+            file.extend_from_slice(&len.to_be_bytes());
+            file.extend_from_slice(&len.to_be_bytes());
+            file.extend_from_slice(&(*received as u32).to_be_bytes());
+            file.extend_from_slice(&0u32.to_be_bytes());
+            file.extend_from_slice(&timestamp.to_be_bytes());
+            file.extend_from_slice(packet);
+        }
+        file
+    }
+
+    use super::*;
+    use crate::Request;
+}
+
+use crate::{Capture, CaptureRecord, Direction, Frame, Result, RX_UUID, TX_UUID};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
@@ -0,0 +1,429 @@
+/// How a capacity was measured.
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MeasurementKind {
+    /// Discharged from full to empty.
+    Full,
+    /// Discharged from full over part of the state of charge, scaled up.
+    Partial,
+}
+
+/// The usable capacity measured over one discharge.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct CapacityMeasurement {
+    /// The end of the discharge.
+    pub timestamp: DateTime<FixedOffset>,
+    pub kind: MeasurementKind,
+    /// The usable capacity (Ah).
+    pub capacity: f64,
+    /// The charge counted (Ah).
+    pub ah: f64,
+    /// The state of charge covered (%).
+    pub soc_span: u8,
+    /// `BatteryDetail::cycles`.
+    pub cycles: i16,
+    /// `BatteryDetail::standard_capacity` (Ah).
+    pub standard_capacity: f64,
+    /// The full charge capacity the BMS assumed, its residual capacity over
+    /// its state of charge at the start (Ah).
+    pub bms_capacity: Option<f64>,
+}
+
+/// How fast the state of health drops, see `CapacityTracker::fade`.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct CapacityFade {
+    /// Per 100 cycles (fraction of the standard capacity).
+    pub per_100_cycles: Option<f64>,
+    /// Per year (fraction of the standard capacity).
+    pub per_year: Option<f64>,
+}
+
+/// A discharge being measured.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+struct Run {
+    start_soc: u8,
+    bms_capacity: Option<f64>,
+    /// The charge discharged since the full charge, net of any charging (Ah).
+    ah: f64,
+    /// The lowest state of charge reached and `ah` at that moment.
+    deepest: (u8, f64),
+}
+
+/// What the tracker keeps between restarts.
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct CapacityState {
+    measurements: Vec<CapacityMeasurement>,
+    run: Option<Run>,
+    /// The time and current (A) of the last snapshot.
+    last: Option<(DateTime<FixedOffset>, f64)>,
+}
+
+/// Measures the usable capacity by counting the charge discharged after a
+/// full charge, tracking the state of health against `standard_capacity`.
+///
+/// A discharge starts at the last snapshot where the battery is full: at
+/// 100 % or with a cell at `full_cell_voltage`. Reaching empty, 0 % or a cell
+/// at `empty_cell_voltage` while discharging, gives a full measurement. A
+/// discharge ending otherwise, by the next full charge or a gap longer than
+/// `max_gap`, gives a partial measurement when its deepest point covers
+/// `min_soc_span`: the charge counted down to that point divided by the
+/// state of charge covered. Partial measurements lean on the state of charge
+/// of the BMS, so they are weighted by the span they cover.
+pub struct CapacityTracker {
+    config: CapacityConfig,
+    state: CapacityState,
+    file: Option<StateFile>,
+}
+
+impl CapacityMeasurement {
+    /// The state of health, the capacity over the standard capacity.
+    pub fn soh(&self) -> Option<f64> {
+        (self.standard_capacity > 0.0).then(|| self.capacity / self.standard_capacity)
+    }
+
+    /// How far the capacity of the BMS is off the measured one, e.g. 0.1 when
+    /// the BMS assumes 10 % more.
+    pub fn divergence(&self) -> Option<f64> {
+        let bms_capacity = self.bms_capacity?;
+        (self.capacity > 0.0).then(|| bms_capacity / self.capacity - 1.0)
+    }
+
+    /// How far the measurement can be trusted, from 0 to 1.
+    fn weight(&self) -> f64 {
+        match self.kind {
+            MeasurementKind::Full => 1.0,
+            MeasurementKind::Partial => self.soc_span as f64 / 100.0,
+        }
+    }
+}
+
+impl CapacityTracker {
+    pub fn new(config: CapacityConfig) -> Self {
+        CapacityTracker {
+            config,
+            state: CapacityState::default(),
+            file: None,
+        }
+    }
+
+    /// Creates a tracker keeping its measurements in the configured state
+    /// file, loading them when it exists. The file is written at most once
+    /// a minute.
+    pub fn from_config(config: &CapacityConfig) -> Result<Self> {
+        let mut tracker = Self::new(config.clone());
+        (tracker.state, tracker.file) =
+            StateFile::open(config.state.as_ref(), chrono::Duration::minutes(1))?;
+        Ok(tracker)
+    }
+
+    /// Counts the charge since the previous snapshot, returning the
+    /// measurement of the discharge it ended, if any.
+    pub fn update(&mut self, snapshot: &Snapshot) -> Result<Option<CapacityMeasurement>> {
+        let detail = &snapshot.detail;
+        let current = detail.current as f64 / 100.0;
+        let soc = detail.residual_capacity_percent;
+        let mut measurement = None;
+
+        if let Some((time, last_current)) = self.state.last {
+            let seconds = (snapshot.timestamp - time).num_milliseconds() as f64 / 1000.0;
+            if seconds > self.config.max_gap as f64 {
+                // the charge is no longer counted, end the discharge here
+                if let Some(run) = self.state.run.take() {
+                    measurement = self.partial(run, snapshot);
+                }
+            } else if let Some(run) = &mut self.state.run {
+                if seconds > 0.0 {
+                    run.ah -= (last_current + current) / 2.0 * seconds / 3600.0;
+                }
+                if soc < run.deepest.0 {
+                    run.deepest = (soc, run.ah);
+                }
+            }
+        }
+        self.state.last = Some((snapshot.timestamp, current));
+
+        let full = soc >= 100
+            || snapshot
+                .max_cell_voltage()
+                .is_some_and(|voltage| voltage >= self.config.full_cell_voltage);
+        let empty = soc == 0
+            || snapshot
+                .min_cell_voltage()
+                .is_some_and(|voltage| voltage <= self.config.empty_cell_voltage);
+
+        if empty && current < 0.0 {
+            if let Some(run) = self.state.run.take() {
+                measurement = Some(self.measurement(
+                    MeasurementKind::Full,
+                    run.ah,
+                    run.ah,
+                    run.start_soc.saturating_sub(soc),
+                    run.bms_capacity,
+                    snapshot,
+                ));
+            }
+        } else if full {
+            if let Some(run) = self.state.run.take() {
+                measurement = measurement.or(self.partial(run, snapshot));
+            }
+            self.state.run = Some(Run {
+                start_soc: soc,
+                bms_capacity: (soc > 0).then(|| detail.residual_capacity as f64 / soc as f64),
+                ah: 0.0,
+                deepest: (soc, 0.0),
+            });
+        }
+
+        if let Some(measurement) = &measurement {
+            self.state.measurements.push(measurement.clone());
+        }
+        if let Some(file) = &mut self.file {
+            file.save(&self.state, snapshot.timestamp)?;
+        }
+        Ok(measurement)
+    }
+
+    /// The measurements, oldest first.
+    pub fn measurements(&self) -> &[CapacityMeasurement] {
+        &self.state.measurements
+    }
+
+    /// The state of health, the weighted mean of the last `window`
+    /// measurements.
+    pub fn soh(&self) -> Option<f64> {
+        let recent = self
+            .state
+            .measurements
+            .iter()
+            .rev()
+            .take(self.config.window.max(1) as usize);
+        let (mut sum, mut weight) = (0.0, 0.0);
+        for measurement in recent {
+            if let Some(soh) = measurement.soh() {
+                sum += soh * measurement.weight();
+                weight += measurement.weight();
+            }
+        }
+        (weight > 0.0).then(|| sum / weight)
+    }
+
+    /// How fast the state of health drops over cycles and over time.
+    pub fn fade(&self) -> CapacityFade {
+        let measurements = &self.state.measurements;
+        let points = |x: &dyn Fn(&CapacityMeasurement) -> f64| -> Vec<(f64, f64, f64)> {
+            measurements
+                .iter()
+                .filter_map(|m| Some((x(m), m.soh()?, m.weight())))
+                .collect()
+        };
+        let first = measurements.first().map(|m| m.timestamp);
+        let years = |m: &CapacityMeasurement| match first {
+            Some(first) => (m.timestamp - first).num_seconds() as f64 / (365.25 * 24.0 * 3600.0),
+            None => 0.0,
+        };
+        CapacityFade {
+            per_100_cycles: weighted_slope(&points(&|m| m.cycles as f64))
+                .map(|slope| -slope * 100.0),
+            per_year: weighted_slope(&points(&years)).map(|slope| -slope),
+        }
+    }
+
+    /// Explains a measurement the BMS disagrees with by more than
+    /// `divergence`.
+    pub fn warning(&self, measurement: &CapacityMeasurement) -> Option<String> {
+        let divergence = measurement.divergence()?;
+        if divergence.abs() <= self.config.divergence {
+            return None;
+        }
+        Some(format!(
+            "the BMS assumes {:.1} Ah, {:+.1} % off the {:.1} Ah measured",
+            measurement.bms_capacity?,
+            divergence * 100.0,
+            measurement.capacity
+        ))
+    }
+
+    /// The measurement of a discharge ended before empty, if deep enough.
+    fn partial(&self, run: Run, snapshot: &Snapshot) -> Option<CapacityMeasurement> {
+        let (soc, ah) = run.deepest;
+        let span = run.start_soc.saturating_sub(soc);
+        if span == 0 || span < self.config.min_soc_span {
+            return None;
+        }
+        Some(self.measurement(
+            MeasurementKind::Partial,
+            ah / span as f64 * 100.0,
+            ah,
+            span,
+            run.bms_capacity,
+            snapshot,
+        ))
+    }
+
+    fn measurement(
+        &self,
+        kind: MeasurementKind,
+        capacity: f64,
+        ah: f64,
+        soc_span: u8,
+        bms_capacity: Option<f64>,
+        snapshot: &Snapshot,
+    ) -> CapacityMeasurement {
+        CapacityMeasurement {
+            timestamp: snapshot.timestamp,
+            kind,
+            capacity,
+            ah,
+            soc_span,
+            cycles: snapshot.detail.cycles,
+            standard_capacity: snapshot.detail.standard_capacity as f64 / 100.0,
+            bms_capacity,
+        }
+    }
+}
+
+impl Display for MeasurementKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MeasurementKind::Full => "full",
+            MeasurementKind::Partial => "partial",
+        })
+    }
+}
+
+impl Display for CapacityMeasurement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:<7} {:>7.1} Ah over {:>3} %  cycle {:>5}",
+            self.timestamp.format("%Y-%m-%d %H:%M"),
+            self.kind,
+            self.capacity,
+            self.soc_span,
+            self.cycles
+        )?;
+        if let Some(soh) = self.soh() {
+            write!(f, "  soh {:>5.1} %", soh * 100.0)?;
+        }
+        if let Some(bms_capacity) = self.bms_capacity {
+            write!(f, "  bms {:>7.1} Ah", bms_capacity)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_full() {
+        let mut tracker = CapacityTracker::new(CapacityConfig::default());
+        // full, then discharging 45 A for two hours down to empty
+        assert_eq!(tracker.update(&snapshot(0, 100, 0, 3500)).unwrap(), None);
+        for minute in 1..120 {
+            let soc = 99 - (minute * 98 / 120) as u8;
+            let measurement = tracker
+                .update(&snapshot(minute * 60, soc, -4500, 3250))
+                .unwrap();
+            assert_eq!(measurement, None);
+        }
+        let measurement = tracker
+            .update(&snapshot(120 * 60, 1, -4500, 2850))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(measurement.kind, MeasurementKind::Full);
+        // 45 A for 2 hours, less the first half minute at 0 A
+        let ah = 90.0 - 45.0 / 120.0;
+        assert!((measurement.capacity - ah).abs() < 1e-9);
+        assert_eq!(measurement.soc_span, 99);
+        assert_eq!(measurement.cycles, 42);
+        assert!((measurement.soh().unwrap() - ah / 100.0).abs() < 1e-9);
+        assert_eq!(measurement.bms_capacity, Some(100.0));
+        assert_eq!(
+            tracker.warning(&measurement).unwrap(),
+            "the BMS assumes 100.0 Ah, +11.6 % off the 89.6 Ah measured"
+        );
+        assert_eq!(tracker.measurements().len(), 1);
+    }
+
+    #[test]
+    fn test_partial() {
+        let mut tracker = CapacityTracker::new(CapacityConfig {
+            max_gap: 7200,
+            ..Default::default()
+        });
+        // full, 40 Ah out down to 50 %, a little back in, then charged full
+        tracker.update(&snapshot(0, 100, -2000, 3450)).unwrap();
+        tracker.update(&snapshot(7200, 50, -2000, 3250)).unwrap();
+        tracker.update(&snapshot(9000, 55, 1000, 3300)).unwrap();
+        let measurement = tracker
+            .update(&snapshot(10800, 100, 1000, 3500))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(measurement.kind, MeasurementKind::Partial);
+        assert_eq!(measurement.soc_span, 50);
+        assert!((measurement.ah - 40.0).abs() < 1e-9);
+        assert!((measurement.capacity - 80.0).abs() < 1e-9);
+
+        // too shallow to measure
+        tracker.update(&snapshot(12600, 90, -2000, 3300)).unwrap();
+        assert_eq!(
+            tracker.update(&snapshot(14400, 100, 0, 3500)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_fade() {
+        let mut tracker = CapacityTracker::new(CapacityConfig {
+            window: 2,
+            ..Default::default()
+        });
+        for (cycles, capacity) in [(100, 100.0), (200, 98.0), (300, 96.0)] {
+            tracker.state.measurements.push(CapacityMeasurement {
+                timestamp: at(cycles as i64 * 24 * 3600),
+                kind: MeasurementKind::Full,
+                capacity,
+                ah: capacity,
+                soc_span: 100,
+                cycles,
+                standard_capacity: 100.0,
+                bms_capacity: None,
+            });
+        }
+
+        assert!((tracker.soh().unwrap() - 0.97).abs() < 1e-9);
+        let fade = tracker.fade();
+        assert!((fade.per_100_cycles.unwrap() - 0.02).abs() < 1e-9);
+        // 2 % every 100 days
+        assert!((fade.per_year.unwrap() - 0.02 * 3.6525).abs() < 1e-9);
+    }
+
+    fn snapshot(seconds: i64, soc: u8, current: i16, cell: i16) -> Snapshot {
+        SnapshotBuilder::at(seconds)
+            .cells(&[cell, 3300, 3300, 3300])
+            .detail(BatteryDetail {
+                current,
+                residual_capacity: soc as i16 * 100,
+                standard_capacity: 10000,
+                cycles: 42,
+                residual_capacity_percent: soc,
+                ..Default::default()
+            })
+            .build()
+    }
+
+    use super::*;
+    use crate::snapshot::{test_time as at, SnapshotBuilder};
+    use crate::BatteryDetail;
+}
+
+use crate::{
+    util::{weighted_slope, StateFile},
+    CapacityConfig, Result, Snapshot,
+};
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
@@ -0,0 +1,712 @@
+/// Which way a captured chunk went.
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// A request chunk written to the TX characteristic.
+    Write,
+    /// A notification chunk received from the RX characteristic.
+    Notify,
+}
+
+/// One chunk of a capture.
+///
+/// Captures are stored as JSON lines, one record per line, in the order the
+/// chunks were written or received:
+///
+/// ```text
+/// {"t":0,"direction":"write","data":"dda50400fffc77"}
+/// {"t":41250,"direction":"notify","data":"dd040008"}
+/// {"t":41310,"direction":"notify","data":"0de20ddc0dec0dedfc2d77"}
+/// ```
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    /// Time since the capture started (µs).
+    pub t: u64,
+    pub direction: Direction,
+    /// The chunk, as hex.
+    #[serde(with = "hex")]
+    pub data: Vec<u8>,
+}
+
+/// A recorded session.
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+pub struct Capture {
+    pub records: Vec<CaptureRecord>,
+}
+
+/// Appends records to a capture file.
+pub struct CaptureWriter<W = BufWriter<File>>
+where
+    W: Write,
+{
+    writer: W,
+    start: Instant,
+}
+
+/// Records every chunk written to and received from `T`.
+pub struct CapturingTransport<T, W = BufWriter<File>>
+where
+    T: Transport,
+    W: Write,
+{
+    transport: T,
+    capture: Option<Arc<Mutex<CaptureWriter<W>>>>,
+}
+
+/// Records every notification received from `N`.
+pub struct CapturingReceiver<N, W = BufWriter<File>>
+where
+    N: NotificationsReceiver,
+    W: Write,
+{
+    receiver: N,
+    capture: Option<Arc<Mutex<CaptureWriter<W>>>>,
+}
+
+/// Replays the notifications of a capture, paced by what is written.
+///
+/// The capture is replayed once, at `speed` times the original speed. The
+/// replay clock starts at the first write, matched with the first write of
+/// the capture, and a notification is only released once as many frames were
+/// written as before it in the capture, so stale notifications cleared before
+/// a request are not lost to timing differences.
+pub struct ReplayTransport {
+    capture: Option<Capture>,
+    speed: f64,
+    connected: bool,
+    writes: Arc<Mutex<ReplayWrites>>,
+}
+
+/// Releases the notifications of a capture at their (scaled) original time.
+pub struct ReplayReceiver {
+    /// The notifications, with the number of writes before each.
+    records: VecDeque<(usize, CaptureRecord)>,
+    speed: f64,
+    /// The capture time the replay clock starts at (µs).
+    origin: u64,
+    writes: Arc<Mutex<ReplayWrites>>,
+}
+
+/// The writes to a `ReplayTransport`, shared with its receiver.
+#[derive(Default)]
+struct ReplayWrites {
+    count: usize,
+    /// When the replay clock started.
+    start: Option<Instant>,
+}
+
+impl Capture {
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut records = Vec::new();
+        for line in s.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            records.push(serde_json::from_str(line)?);
+        }
+        Ok(Capture { records })
+    }
+
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Reassembles the chunks of each direction into frames, timestamped with
+    /// the chunk completing them.
+    pub fn frames(&self) -> Vec<CaptureRecord> {
+        let mut writes = FrameAssembler::new();
+        let mut notifications = FrameAssembler::new();
+        let mut frames = Vec::new();
+        for record in &self.records {
+            let assembler = match record.direction {
+                Direction::Write => &mut writes,
+                Direction::Notify => &mut notifications,
+            };
+            for data in assembler.push(&record.data) {
+                frames.push(CaptureRecord { data, ..*record });
+            }
+        }
+        frames
+    }
+}
+
+impl CaptureWriter {
+    /// Creates (or truncates) a capture file.
+    pub fn create<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W> CaptureWriter<W>
+where
+    W: Write,
+{
+    pub fn new(writer: W) -> Self {
+        CaptureWriter {
+            writer,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Records a chunk, timestamped with the time since the writer was created.
+    pub fn record(&mut self, direction: Direction, data: &[u8]) -> Result<()> {
+        self.record_at(direction, data, Instant::now())
+    }
+
+    /// Records a chunk that was written or received at `at`.
+    pub fn record_at(&mut self, direction: Direction, data: &[u8], at: Instant) -> Result<()> {
+        self.append(&CaptureRecord {
+            t: at.saturating_duration_since(self.start).as_micros() as u64,
+            direction,
+            data: data.to_vec(),
+        })
+    }
+
+    pub fn append(&mut self, record: &CaptureRecord) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+        // a capture is most useful when the session ended unexpectedly
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<T, W> CapturingTransport<T, W>
+where
+    T: Transport,
+    W: Write,
+{
+    /// Passes everything through until a capture is set.
+    pub fn new(transport: T) -> Self {
+        CapturingTransport {
+            transport,
+            capture: None,
+        }
+    }
+
+    pub fn with_capture(mut self, writer: CaptureWriter<W>) -> Self {
+        self.capture = Some(Arc::new(Mutex::new(writer)));
+        self
+    }
+
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn capture(&self) -> Option<&Arc<Mutex<CaptureWriter<W>>>> {
+        self.capture.as_ref()
+    }
+}
+
+impl<T, W> Transport for CapturingTransport<T, W>
+where
+    T: Transport,
+    W: Write,
+{
+    type Receiver = CapturingReceiver<T::Receiver, W>;
+
+    async fn connect(&mut self) -> Result<Self::Receiver> {
+        Ok(CapturingReceiver {
+            receiver: self.transport.connect().await?,
+            capture: self.capture.clone(),
+        })
+    }
+
+    async fn is_connected(&mut self) -> bool {
+        self.transport.is_connected().await
+    }
+
+    async fn write(&mut self, value: &[u8]) -> Result<()> {
+        record(&self.capture, Direction::Write, value, Instant::now());
+        self.transport.write(value).await
+    }
+}
+
+impl<N, W> CapturingReceiver<N, W>
+where
+    N: NotificationsReceiver,
+    W: Write,
+{
+    /// Records a notification at its arrival, rather than when it was read.
+    fn record(&self, value: &[u8]) {
+        let at = self.receiver.arrived().unwrap_or_else(Instant::now);
+        record(&self.capture, Direction::Notify, value, at);
+    }
+}
+
+impl<N, W> NotificationsReceiver for CapturingReceiver<N, W>
+where
+    N: NotificationsReceiver,
+    W: Write,
+{
+    fn next(&mut self) -> Vec<u8> {
+        let value = self.receiver.next();
+        self.record(&value);
+        value
+    }
+
+    fn next_timeout(&mut self, timeout: Duration) -> Option<Vec<u8>> {
+        let value = self.receiver.next_timeout(timeout)?;
+        self.record(&value);
+        Some(value)
+    }
+
+    fn arrived(&self) -> Option<Instant> {
+        self.receiver.arrived()
+    }
+}
+
+impl ReplayTransport {
+    /// # Parameters
+    /// ---
+    /// * `speed` - How many times faster than the original to replay,
+    ///   `f64::INFINITY` replays without waiting.
+    pub fn new(capture: Capture, speed: f64) -> Self {
+        ReplayTransport {
+            capture: Some(capture),
+            speed,
+            connected: false,
+            writes: Arc::default(),
+        }
+    }
+}
+
+impl Transport for ReplayTransport {
+    type Receiver = ReplayReceiver;
+
+    async fn connect(&mut self) -> Result<ReplayReceiver> {
+        // a capture is replayed once, reconnecting ends the replay
+        let capture = self.capture.take().ok_or(TransportClosed)?;
+        self.connected = true;
+        Ok(ReplayReceiver::paced(
+            capture,
+            self.speed,
+            self.writes.clone(),
+        ))
+    }
+
+    async fn is_connected(&mut self) -> bool {
+        self.connected
+    }
+
+    async fn write(&mut self, value: &[u8]) -> Result<()> {
+        log::debug!("replay ignores write {:x?}", value);
+        let mut writes = self.writes.lock().unwrap_or_else(|err| err.into_inner());
+        writes.count += 1;
+        writes.start.get_or_insert_with(Instant::now);
+        Ok(())
+    }
+}
+
+impl ReplayReceiver {
+    /// Replays the notifications on their own, the clock starting now at
+    /// the first record.
+    pub fn new(capture: Capture, speed: f64) -> Self {
+        let writes = ReplayWrites {
+            count: usize::MAX,
+            start: Some(Instant::now()),
+        };
+        let origin = capture.records.first().map_or(0, |record| record.t);
+        Self::with_writes(capture, speed, origin, Arc::new(Mutex::new(writes)))
+    }
+
+    /// Replays the notifications paced by the writes to a `ReplayTransport`.
+    fn paced(capture: Capture, speed: f64, writes: Arc<Mutex<ReplayWrites>>) -> Self {
+        let first_write = capture
+            .records
+            .iter()
+            .find(|record| record.direction == Direction::Write);
+        let origin = match first_write {
+            Some(record) => record.t,
+            // nothing to pace by, start at the first record
+            None => {
+                let mut writes = writes.lock().unwrap_or_else(|err| err.into_inner());
+                writes.start = Some(Instant::now());
+                capture.records.first().map_or(0, |record| record.t)
+            }
+        };
+        Self::with_writes(capture, speed, origin, writes)
+    }
+
+    fn with_writes(
+        capture: Capture,
+        speed: f64,
+        origin: u64,
+        writes: Arc<Mutex<ReplayWrites>>,
+    ) -> Self {
+        let mut count = 0;
+        let mut records = VecDeque::new();
+        for record in capture.records {
+            match record.direction {
+                Direction::Write => count += 1,
+                Direction::Notify => records.push_back((count, record)),
+            }
+        }
+        ReplayReceiver {
+            records,
+            speed,
+            origin,
+            writes,
+        }
+    }
+
+    /// How long until the next notification is due, `None` when there is none
+    /// or it waits for a write.
+    ///
+    /// Without `paced`, the writes are ignored and a clock that has not
+    /// started yet starts now.
+    fn wait(&self, paced: bool) -> Option<Duration> {
+        let (written, record) = self.records.front()?;
+        let mut writes = self.writes.lock().unwrap_or_else(|err| err.into_inner());
+        let start = if paced {
+            if *written > writes.count {
+                return None;
+            }
+            writes.start?
+        } else {
+            *writes.start.get_or_insert_with(Instant::now)
+        };
+        if !(self.speed > 0.0 && self.speed.is_finite()) {
+            return Some(Duration::ZERO);
+        }
+        let due = Duration::from_micros(record.t.saturating_sub(self.origin)).div_f64(self.speed);
+        Some(due.saturating_sub(start.elapsed()))
+    }
+}
+
+impl NotificationsReceiver for ReplayReceiver {
+    /// Waits for the time of the next notification only, as nothing could be
+    /// written while blocking.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic when the capture is exhausted.
+    fn next(&mut self) -> Vec<u8> {
+        let wait = self.wait(false).expect("capture exhausted");
+        thread::sleep(wait);
+        self.records.pop_front().unwrap().1.data
+    }
+
+    fn next_timeout(&mut self, timeout: Duration) -> Option<Vec<u8>> {
+        match self.wait(true) {
+            Some(wait) if wait <= timeout => {
+                thread::sleep(wait);
+                self.records.pop_front().map(|(_, record)| record.data)
+            }
+            _ => {
+                thread::sleep(timeout);
+                None
+            }
+        }
+    }
+}
+
+/// Records a chunk, logging failures instead of failing the session.
+fn record<W>(
+    capture: &Option<Arc<Mutex<CaptureWriter<W>>>>,
+    direction: Direction,
+    data: &[u8],
+    at: Instant,
+) where
+    W: Write,
+{
+    let Some(capture) = capture else {
+        return;
+    };
+    let mut capture = capture.lock().unwrap_or_else(|err| err.into_inner());
+    if let Err(err) = capture.record_at(direction, data, at) {
+        log::error!("failed to write capture: {}", err);
+    }
+}
+
+mod hex {
+    pub fn serialize<S>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&to_hex(data))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        from_hex(&s).ok_or_else(|| D::Error::custom(format!("invalid hex {}", s)))
+    }
+
+    use crate::util::{from_hex, to_hex};
+    use serde::{de::Error as _, Deserialize, Deserializer, Serializer};
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_parse() {
+        let capture = Capture::parse(
+            r#"{"t":0,"direction":"write","data":"dda50400fffc77"}
+
+            {"t":41250,"direction":"notify","data":"DD040008"}
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            capture.records,
+            vec![
+                CaptureRecord {
+                    t: 0,
+                    direction: Direction::Write,
+                    data: Request::BatteryVoltage.bytes().to_vec(),
+                },
+                CaptureRecord {
+                    t: 41250,
+                    direction: Direction::Notify,
+                    data: vec![0xdd, 0x04, 0x00, 0x08],
+                },
+            ]
+        );
+
+        assert!(Capture::parse(r#"{"t":0,"direction":"write","data":"zz"}"#).is_err());
+        assert!(Capture::parse(r#"{"t":0,"direction":"read","data":""}"#).is_err());
+    }
+
+    #[test]
+    fn test_frames() {
+        let capture = Capture::parse(
+            r#"{"t":0,"direction":"notify","data":"dd040008"}
+            {"t":10,"direction":"write","data":"dda50400fffc77"}
+            {"t":20,"direction":"notify","data":"0de20ddc0dec0dedfc2d77"}"#,
+        )
+        .unwrap();
+        let frames = capture.frames();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].t, 10);
+        assert_eq!(frames[0].data, Request::BatteryVoltage.bytes());
+        assert_eq!(frames[1].t, 20);
+        assert_eq!(frames[1].direction, Direction::Notify);
+        assert_eq!(frames[1].data.len(), 15);
+    }
+
+    #[test]
+    fn test_capture_and_replay() {
+        let pending = Rc::new(RefCell::new(VecDeque::new()));
+        let transport = MockTransport(pending.clone());
+        let mut transport =
+            CapturingTransport::new(transport).with_capture(CaptureWriter::new(Vec::new()));
+
+        let mut receiver = block_on(transport.connect()).unwrap();
+        let frame = StaticResponses.response(&Request::BatteryVoltage);
+        pending.borrow_mut().push_back(frame[..4].to_vec());
+        pending.borrow_mut().push_back(frame[4..].to_vec());
+
+        block_on(transport.write(Request::BatteryVoltage.bytes())).unwrap();
+        assert_eq!(receiver.next(), frame[..4]);
+        assert_eq!(
+            receiver.next_timeout(Duration::from_secs(1)).unwrap(),
+            frame[4..]
+        );
+        assert_eq!(receiver.next_timeout(Duration::from_secs(1)), None);
+
+        let capture = transport.capture().unwrap().lock().unwrap();
+        let capture = Capture::parse(std::str::from_utf8(capture.get_ref()).unwrap()).unwrap();
+        let directions: Vec<Direction> = capture.records.iter().map(|r| r.direction).collect();
+        assert_eq!(
+            directions,
+            vec![Direction::Write, Direction::Notify, Direction::Notify]
+        );
+        assert!(capture.records.windows(2).all(|w| w[0].t <= w[1].t));
+
+        // the fragmentation is replayed as captured, once the request is
+        // written
+        let mut replay = ReplayTransport::new(capture, f64::INFINITY);
+        let mut receiver = block_on(replay.connect()).unwrap();
+        assert!(block_on(replay.is_connected()));
+        assert!(block_on(replay.connect()).is_err());
+        assert_eq!(receiver.next_timeout(Duration::ZERO), None);
+        block_on(replay.write(Request::BatteryVoltage.bytes())).unwrap();
+        assert_eq!(
+            read_reply_timeout(&mut receiver, 0x04, Duration::from_secs(1)),
+            Some(frame)
+        );
+        assert_eq!(receiver.next_timeout(Duration::ZERO), None);
+    }
+
+    #[test]
+    fn test_replay_ends() {
+        let record = |t, direction, data| CaptureRecord { t, direction, data };
+        let capture = Capture {
+            records: vec![
+                record(0, Direction::Write, Request::Clear.bytes().to_vec()),
+                record(
+                    1_000,
+                    Direction::Write,
+                    Request::BatteryVoltage.bytes().to_vec(),
+                ),
+                record(
+                    2_000,
+                    Direction::Notify,
+                    StaticResponses.response(&Request::BatteryVoltage),
+                ),
+            ],
+        };
+        let config = ConnectionConfig {
+            response_timeout: 0,
+            ..Default::default()
+        };
+        let replay = ReplayTransport::new(capture, f64::INFINITY);
+        let mut supervisor = ConnectionSupervisor::new(replay, ManualClock::default(), &config);
+
+        block_on(supervisor.connect()).unwrap();
+        assert!(block_on(supervisor.request_voltage()).is_ok());
+        // the capture is used up: the request times out, and reconnecting
+        // fails for good instead of being retried
+        let err = block_on(supervisor.request_voltage()).unwrap_err();
+        assert!(!err.is::<TransportClosed>());
+        let err = block_on(supervisor.request_voltage()).unwrap_err();
+        assert!(err.is::<TransportClosed>());
+        assert!(block_on(supervisor.connect()).is_err());
+    }
+
+    #[test]
+    fn test_replay_timing() {
+        let record = |t, data| CaptureRecord {
+            t,
+            direction: Direction::Notify,
+            data,
+        };
+        let capture = Capture {
+            records: vec![record(0, vec![1]), record(10_000_000, vec![2])],
+        };
+        let mut receiver = ReplayReceiver::new(capture, 1_000.0);
+        assert_eq!(receiver.next_timeout(Duration::ZERO), Some(vec![1]));
+        // due after 10 ms at this speed
+        assert_eq!(receiver.next_timeout(Duration::ZERO), None);
+        assert_eq!(receiver.next_timeout(Duration::from_secs(1)), Some(vec![2]));
+    }
+
+    #[test]
+    fn test_replay_paced_by_writes() {
+        let record = |t, direction, data| CaptureRecord { t, direction, data };
+        // a clear with a late stale notification, then a request 5 s later
+        let capture = Capture {
+            records: vec![
+                record(2_000_000, Direction::Notify, vec![1]),
+                record(3_000_000, Direction::Write, Request::Clear.bytes().to_vec()),
+                record(3_500_000, Direction::Notify, vec![2]),
+                record(
+                    8_000_000,
+                    Direction::Write,
+                    Request::BatteryVoltage.bytes().to_vec(),
+                ),
+                record(8_040_000, Direction::Notify, vec![3]),
+            ],
+        };
+        let mut replay = ReplayTransport::new(capture, 1_000.0);
+        let mut receiver = block_on(replay.connect()).unwrap();
+
+        // the clock starts at the first write, not at connect
+        assert_eq!(receiver.next_timeout(Duration::ZERO), None);
+        block_on(replay.write(Request::Clear.bytes())).unwrap();
+        assert_eq!(receiver.next_timeout(Duration::ZERO), Some(vec![1]));
+        assert_eq!(receiver.next_timeout(Duration::from_secs(1)), Some(vec![2]));
+
+        // the response waits for its request, however late
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(receiver.next_timeout(Duration::ZERO), None);
+        block_on(replay.write(Request::BatteryVoltage.bytes())).unwrap();
+        assert_eq!(receiver.next_timeout(Duration::ZERO), Some(vec![3]));
+        assert_eq!(receiver.next_timeout(Duration::ZERO), None);
+    }
+
+    #[test]
+    fn test_capture_arrival_time() {
+        let writer = CaptureWriter::new(Vec::new());
+        let arrived = Instant::now() + Duration::from_millis(50);
+        let capture = Arc::new(Mutex::new(writer));
+        let mut receiver = CapturingReceiver {
+            receiver: ArrivedReceiver(Some(vec![1]), arrived),
+            capture: Some(capture.clone()),
+        };
+
+        assert_eq!(receiver.next_timeout(Duration::ZERO), Some(vec![1]));
+        assert_eq!(receiver.arrived(), Some(arrived));
+        let capture = capture.lock().unwrap();
+        let capture = Capture::parse(std::str::from_utf8(capture.get_ref()).unwrap()).unwrap();
+        // recorded at its arrival, not when it was read
+        assert!(capture.records[0].t >= 50_000);
+    }
+
+    struct ArrivedReceiver(Option<Vec<u8>>, Instant);
+
+    impl NotificationsReceiver for ArrivedReceiver {
+        fn next(&mut self) -> Vec<u8> {
+            self.0.take().unwrap()
+        }
+
+        fn next_timeout(&mut self, _timeout: Duration) -> Option<Vec<u8>> {
+            self.0.take()
+        }
+
+        fn arrived(&self) -> Option<Instant> {
+            Some(self.1)
+        }
+    }
+
+    struct MockTransport(Rc<RefCell<VecDeque<Vec<u8>>>>);
+    struct MockReceiver(Rc<RefCell<VecDeque<Vec<u8>>>>);
+
+    impl Transport for MockTransport {
+        type Receiver = MockReceiver;
+
+        async fn connect(&mut self) -> Result<MockReceiver> {
+            Ok(MockReceiver(self.0.clone()))
+        }
+
+        async fn is_connected(&mut self) -> bool {
+            true
+        }
+
+        async fn write(&mut self, _value: &[u8]) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl NotificationsReceiver for MockReceiver {
+        fn next(&mut self) -> Vec<u8> {
+            self.0.borrow_mut().pop_front().unwrap()
+        }
+
+        fn next_timeout(&mut self, _timeout: Duration) -> Option<Vec<u8>> {
+            self.0.borrow_mut().pop_front()
+        }
+    }
+
+    use super::*;
+    use crate::{
+        read_reply_timeout, util::block_on, ConnectionConfig, ConnectionSupervisor, ManualClock,
+        Request, ResponseSource, StaticResponses,
+    };
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+}
+
+use crate::{FrameAssembler, NotificationsReceiver, Result, Transport, TransportClosed};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
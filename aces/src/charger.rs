@@ -0,0 +1,423 @@
+/// The chemistry of the cells, selecting the default charger voltages.
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Chemistry {
+    #[default]
+    Lifepo4,
+    Nmc,
+    Lto,
+}
+
+/// The phase of the external charger.
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChargePhase {
+    /// Charging below the absorption voltage, usually at a constant current.
+    Bulk,
+    /// The absorption voltage is held while the current tapers.
+    Absorption,
+    /// The float voltage is held with a charging current at or below the
+    /// tail. A pack resting without current is not charging.
+    Float,
+    NotCharging,
+}
+
+/// A charger misbehaving, raised as an `Alert`.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum ChargerAnomaly {
+    /// Absorption lasted longer than `max_absorption`.
+    NoTaper,
+    /// Floating above the float voltage for longer than `float_settle`.
+    FloatTooHigh,
+    /// Floating with a cell within `overvoltage_margin` of `cell_overvoltage`.
+    FloatNearOvervoltage,
+}
+
+/// Infers the phase of the external charger from the pack voltage and current.
+///
+/// Voltages are compared per cell, so the same thresholds hold for any pack
+/// size, and the tail current is relative to the capacity. The anomalies
+/// fire as alerts and clear once the condition no longer holds.
+pub struct ChargerClassifier {
+    config: ChargerConfig,
+    phase: ChargePhase,
+    since: Option<DateTime<FixedOffset>>,
+    active: Vec<ChargerAnomaly>,
+}
+
+impl Chemistry {
+    /// The absorption voltage per cell (mV).
+    pub fn absorption_voltage(&self) -> i16 {
+        match self {
+            Chemistry::Lifepo4 => 3550,
+            Chemistry::Nmc => 4150,
+            Chemistry::Lto => 2700,
+        }
+    }
+
+    /// The float voltage per cell (mV).
+    pub fn float_voltage(&self) -> i16 {
+        match self {
+            Chemistry::Lifepo4 => 3375,
+            Chemistry::Nmc => 4050,
+            Chemistry::Lto => 2550,
+        }
+    }
+
+    /// The usual cell overvoltage protection (mV).
+    pub fn cell_overvoltage(&self) -> i16 {
+        match self {
+            Chemistry::Lifepo4 => 3650,
+            Chemistry::Nmc => 4250,
+            Chemistry::Lto => 2800,
+        }
+    }
+}
+
+impl ChargerAnomaly {
+    /// The rule name of the alert.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChargerAnomaly::NoTaper => "charger no taper",
+            ChargerAnomaly::FloatTooHigh => "charger float too high",
+            ChargerAnomaly::FloatNearOvervoltage => "charger float near overvoltage",
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            ChargerAnomaly::FloatNearOvervoltage => Severity::Critical,
+            _ => Severity::Warning,
+        }
+    }
+}
+
+impl ChargerClassifier {
+    pub fn new(config: ChargerConfig) -> Self {
+        ChargerClassifier {
+            config,
+            phase: ChargePhase::NotCharging,
+            since: None,
+            active: Vec::new(),
+        }
+    }
+
+    /// The phase after the last snapshot.
+    pub fn phase(&self) -> ChargePhase {
+        self.phase
+    }
+
+    /// When the current phase started.
+    pub fn since(&self) -> Option<DateTime<FixedOffset>> {
+        self.since
+    }
+
+    /// The phase of a single snapshot.
+    pub fn classify(&self, snapshot: &Snapshot) -> ChargePhase {
+        let current = snapshot.detail.current as f64 / 100.0;
+        let voltage = self.cell_voltage(snapshot);
+        let tolerance = self.config.tolerance;
+
+        if current > self.tail_current(snapshot) {
+            if voltage >= self.absorption_voltage() - tolerance {
+                ChargePhase::Absorption
+            } else {
+                ChargePhase::Bulk
+            }
+        } else if current > 0.0 && voltage >= self.float_voltage() - tolerance {
+            ChargePhase::Float
+        } else {
+            ChargePhase::NotCharging
+        }
+    }
+
+    /// Classifies the snapshot, returning the anomaly alerts that fired or
+    /// cleared.
+    pub fn update(&mut self, snapshot: &Snapshot) -> Vec<Alert> {
+        let phase = self.classify(snapshot);
+        if phase != self.phase || self.since.is_none() {
+            self.phase = phase;
+            self.since = Some(snapshot.timestamp);
+        }
+
+        let mut alerts = Vec::new();
+        for anomaly in [
+            ChargerAnomaly::NoTaper,
+            ChargerAnomaly::FloatTooHigh,
+            ChargerAnomaly::FloatNearOvervoltage,
+        ] {
+            let message = self.check(anomaly, snapshot);
+            let was_active = self.active.contains(&anomaly);
+            let state = match (&message, was_active) {
+                (Some(_), false) => {
+                    self.active.push(anomaly);
+                    AlertState::Fired
+                }
+                (None, true) => {
+                    self.active.retain(|active| *active != anomaly);
+                    AlertState::Cleared
+                }
+                _ => continue,
+            };
+            alerts.push(Alert {
+                timestamp: snapshot.timestamp,
+                rule: anomaly.name().to_string(),
+                severity: anomaly.severity(),
+                state,
+                message: message.unwrap_or_else(|| format!("now in {}", phase)),
+            });
+        }
+        alerts
+    }
+
+    /// Describes the anomaly if it holds for the snapshot.
+    fn check(&self, anomaly: ChargerAnomaly, snapshot: &Snapshot) -> Option<String> {
+        match anomaly {
+            ChargerAnomaly::NoTaper => {
+                let lasted = snapshot.timestamp - self.since?;
+                if self.phase != ChargePhase::Absorption
+                    || lasted.num_seconds() < self.config.max_absorption as i64
+                {
+                    return None;
+                }
+                Some(format!(
+                    "absorption for {}h{:02}m, current {:.2} A still above the {:.2} A tail",
+                    lasted.num_hours(),
+                    lasted.num_minutes() % 60,
+                    snapshot.detail.current as f64 / 100.0,
+                    self.tail_current(snapshot)
+                ))
+            }
+            ChargerAnomaly::FloatTooHigh => {
+                let voltage = self.cell_voltage(snapshot);
+                let limit = self.float_voltage() + self.config.tolerance;
+                // the voltage takes a while to fall from absorption to float
+                let floating = snapshot.timestamp - self.since?;
+                if self.phase != ChargePhase::Float
+                    || floating.num_seconds() < self.config.float_settle as i64
+                    || voltage <= limit
+                {
+                    return None;
+                }
+                Some(format!(
+                    "floating at {} mV per cell, above {} mV",
+                    voltage, limit
+                ))
+            }
+            ChargerAnomaly::FloatNearOvervoltage => {
+                let (cell, voltage) = snapshot
+                    .voltage
+                    .iter()
+                    .copied()
+                    .enumerate()
+                    .max_by_key(|(_, voltage)| *voltage)?;
+                let limit = self.cell_overvoltage();
+                if self.phase != ChargePhase::Float
+                    || voltage < limit - self.config.overvoltage_margin
+                {
+                    return None;
+                }
+                Some(format!(
+                    "cell {} at {} mV while floating, within {} mV of cell_overvoltage ({} mV)",
+                    cell + 1,
+                    voltage,
+                    self.config.overvoltage_margin,
+                    limit
+                ))
+            }
+        }
+    }
+
+    /// The pack voltage divided over the cells in series (mV).
+    fn cell_voltage(&self, snapshot: &Snapshot) -> i16 {
+        let cells = match self.config.cell_count {
+            Some(cells) => cells as i32,
+            None => snapshot.voltage.len() as i32,
+        };
+        (snapshot.detail.total_voltage as i32 * 10 / cells.max(1)) as i16
+    }
+
+    /// The tail current (A).
+    fn tail_current(&self, snapshot: &Snapshot) -> f64 {
+        let capacity = self
+            .config
+            .capacity
+            .unwrap_or(snapshot.detail.standard_capacity as f64 / 100.0);
+        self.config.tail_current * capacity
+    }
+
+    fn absorption_voltage(&self) -> i16 {
+        self.config
+            .absorption_voltage
+            .unwrap_or(self.config.chemistry.absorption_voltage())
+    }
+
+    fn float_voltage(&self) -> i16 {
+        self.config
+            .float_voltage
+            .unwrap_or(self.config.chemistry.float_voltage())
+    }
+
+    fn cell_overvoltage(&self) -> i16 {
+        self.config
+            .cell_overvoltage
+            .unwrap_or(self.config.chemistry.cell_overvoltage())
+    }
+}
+
+impl Display for ChargePhase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ChargePhase::Bulk => "bulk",
+            ChargePhase::Absorption => "absorption",
+            ChargePhase::Float => "float",
+            ChargePhase::NotCharging => "not charging",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_classify() {
+        let classifier = ChargerClassifier::new(ChargerConfig::default());
+        // 100 Ah, so the tail is 2 A; absorption from 3525 mV and float from
+        // 3350 mV per cell
+        for (total_voltage, current, phase) in [
+            (1320, 2000, ChargePhase::Bulk),
+            (1380, 2000, ChargePhase::Bulk),
+            (1410, 2000, ChargePhase::Absorption),
+            (1420, 500, ChargePhase::Absorption),
+            (1420, 150, ChargePhase::Float),
+            (1350, 1, ChargePhase::Float),
+            // resting after a charge
+            (1350, 0, ChargePhase::NotCharging),
+            (1330, 0, ChargePhase::NotCharging),
+            (1350, -500, ChargePhase::NotCharging),
+        ] {
+            assert_eq!(
+                classifier.classify(&snapshot(0, total_voltage, current, 3300)),
+                phase,
+                "{} V {} A",
+                total_voltage as f32 / 100.0,
+                current as f32 / 100.0
+            );
+        }
+    }
+
+    #[test]
+    fn test_pack_size() {
+        // the same per cell voltage on an 8 cell pack, and a smaller pack
+        // tapering to 0.5 A
+        let classifier = ChargerClassifier::new(ChargerConfig {
+            cell_count: Some(8),
+            capacity: Some(25.0),
+            ..Default::default()
+        });
+        assert_eq!(
+            classifier.classify(&snapshot(0, 2840, 100, 3300)),
+            ChargePhase::Absorption
+        );
+        assert_eq!(
+            classifier.classify(&snapshot(0, 2840, 40, 3300)),
+            ChargePhase::Float
+        );
+
+        let classifier = ChargerClassifier::new(ChargerConfig {
+            chemistry: Chemistry::Nmc,
+            ..Default::default()
+        });
+        assert_eq!(
+            classifier.classify(&snapshot(0, 1640, 2000, 3300)),
+            ChargePhase::Bulk
+        );
+    }
+
+    #[test]
+    fn test_no_taper() {
+        let mut classifier = ChargerClassifier::new(ChargerConfig {
+            max_absorption: 3600,
+            ..Default::default()
+        });
+        assert_eq!(classifier.update(&snapshot(0, 1420, 2000, 3500)), []);
+        assert_eq!(classifier.update(&snapshot(3599, 1420, 2000, 3500)), []);
+
+        let alerts = classifier.update(&snapshot(3600, 1420, 2000, 3500));
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].rule, "charger no taper");
+        assert_eq!(alerts[0].state, AlertState::Fired);
+        assert_eq!(
+            alerts[0].message,
+            "absorption for 1h00m, current 20.00 A still above the 2.00 A tail"
+        );
+        assert_eq!(classifier.update(&snapshot(4000, 1420, 2000, 3500)), []);
+
+        let alerts = classifier.update(&snapshot(4060, 1350, 100, 3400));
+        assert_eq!(classifier.phase(), ChargePhase::Float);
+        assert_eq!(classifier.since(), Some(at(4060)));
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].state, AlertState::Cleared);
+    }
+
+    #[test]
+    fn test_float_too_high() {
+        let mut classifier = ChargerClassifier::new(ChargerConfig::default());
+        // tapering below the tail, still settling from absorption
+        assert_eq!(classifier.update(&snapshot(0, 1420, 2000, 3500)), []);
+        assert_eq!(classifier.update(&snapshot(60, 1400, 150, 3500)), []);
+        assert_eq!(classifier.phase(), ChargePhase::Float);
+
+        // floating at the absorption voltage with a cell close to 3650 mV
+        let alerts = classifier.update(&snapshot(120, 1420, 50, 3610));
+        assert_eq!(
+            alerts
+                .iter()
+                .map(|alert| (alert.rule.as_str(), alert.severity, alert.message.as_str()))
+                .collect::<Vec<_>>(),
+            [(
+                "charger float near overvoltage",
+                Severity::Critical,
+                "cell 4 at 3610 mV while floating, within 50 mV of cell_overvoltage (3650 mV)"
+            )]
+        );
+        let alerts = classifier.update(&snapshot(960, 1420, 50, 3610));
+        assert_eq!(
+            alerts
+                .iter()
+                .map(|alert| (alert.rule.as_str(), alert.severity, alert.message.as_str()))
+                .collect::<Vec<_>>(),
+            [(
+                "charger float too high",
+                Severity::Warning,
+                "floating at 3550 mV per cell, above 3400 mV"
+            )]
+        );
+
+        let alerts = classifier.update(&snapshot(1020, 1360, 50, 3400));
+        assert_eq!(alerts.len(), 2);
+        assert!(alerts
+            .iter()
+            .all(|alert| alert.state == AlertState::Cleared));
+    }
+
+    fn snapshot(seconds: i64, total_voltage: i16, current: i16, high_cell: i16) -> Snapshot {
+        SnapshotBuilder::at(seconds)
+            .cells(&[3300, 3300, 3300, high_cell])
+            .detail(BatteryDetail {
+                total_voltage,
+                current,
+                standard_capacity: 10000,
+                ..Default::default()
+            })
+            .build()
+    }
+
+    use super::*;
+    use crate::snapshot::{test_time as at, SnapshotBuilder};
+    use crate::BatteryDetail;
+}
+
+use crate::{Alert, AlertState, ChargerConfig, Severity, Snapshot};
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
@@ -0,0 +1,60 @@
+/// A source of time and delays, so timing logic can be tested without real sleeps.
+pub trait Clock {
+    /// The time elapsed since the clock was created.
+    fn now(&self) -> Duration;
+
+    fn sleep(&mut self, duration: Duration) -> impl Future<Output = ()>;
+}
+
+/// A clock that only advances when slept on.
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+pub struct ManualClock {
+    /// The time slept so far.
+    pub elapsed: Duration,
+    /// Every sleep, in order.
+    pub sleeps: Vec<Duration>,
+}
+
+/// A clock backed by the system, for synchronous runners.
+///
+/// Sleeping blocks the thread.
+#[derive(Clone, Debug)]
+pub struct SystemClock(Instant);
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock(Instant::now())
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.0.elapsed()
+    }
+
+    async fn sleep(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.elapsed
+    }
+
+    async fn sleep(&mut self, duration: Duration) {
+        self.elapsed += duration;
+        self.sleeps.push(duration);
+    }
+}
+
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
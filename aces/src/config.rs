@@ -0,0 +1,981 @@
+/// The runner configuration.
+///
+/// ```toml
+/// [device]
+/// name = "AL12V100HFA0191"
+/// name_match = "exact"
+/// service = 0xff00
+///
+/// [poll]
+/// voltage = 10
+/// detail = 10
+/// protect = 300
+/// jitter = 0.1
+/// fast_interval = 1
+/// fast_duration = 60
+///
+/// [connection]
+/// scan_timeout = 3
+/// connect_timeout = 10
+/// response_timeout = 5
+/// retries = 3
+/// reconnect_delay = 1
+/// max_reconnect_delay = 300
+///
+/// [output]
+/// stdout = true
+/// capture = "capture.jsonl"
+///
+/// [output.telemetry]
+/// enabled = true
+/// directory = "telemetry"
+/// format = "csv"
+/// rotation = { size = 10485760 }
+/// compress = true
+///
+/// [faults]
+/// scenario = "lossy"
+///
+/// [replay]
+/// file = "field-report.jsonl"
+/// speed = 10
+///
+/// [console]
+/// history = ".aces_history"
+/// macros = "aces_macros.toml"
+/// timeout = 2
+///
+/// [scan]
+/// first = 0x00
+/// last = 0xff
+/// pace = 500
+/// timeout = 1000
+///
+/// [history]
+/// enabled = true
+/// path = "aces_history.sqlite"
+/// raw_days = 7
+/// minute_days = 90
+///
+/// [energy]
+/// state = "aces_energy.json"
+/// max_gap = 60
+///
+/// [sessions]
+/// start_current = 1.0
+/// stop_current = 0.3
+/// min_duration = 60
+/// max_gap = 60
+///
+/// [charger]
+/// chemistry = "lifepo4"
+/// cell_count = 4
+/// capacity = 100
+/// absorption_voltage = 3550
+/// float_voltage = 3375
+/// tolerance = 25
+/// tail_current = 0.02
+/// max_absorption = 14400
+/// float_settle = 900
+/// cell_overvoltage = 3650
+/// overvoltage_margin = 50
+///
+/// [resistance]
+/// state = "aces_resistance.json"
+/// min_step = 5.0
+/// max_interval = 10
+/// min_confidence = 0.5
+/// window = 7
+/// rise_threshold = 0.2
+///
+/// [capacity]
+/// state = "aces_capacity.json"
+/// full_cell_voltage = 3450
+/// empty_cell_voltage = 2900
+/// min_soc_span = 40
+/// max_gap = 60
+/// window = 5
+/// divergence = 0.1
+///
+/// [imbalance]
+/// state = "aces_imbalance.json"
+/// end_of_charge_voltage = 3450
+/// end_of_discharge_voltage = 3000
+/// end_of_discharge_soc = 10
+/// deviation = 50
+/// weak_score = 70
+/// min_episodes = 3
+///
+/// [protection]
+/// state = "aces_protect.json"
+/// timeline = "aces_protection.jsonl"
+///
+/// [write]
+/// dry_run = false
+/// audit_log = "aces_audit.jsonl"
+/// user = "workshop"
+///
+/// [[rule]]
+/// name = "cell high"
+/// condition = { type = "cell_voltage_above", threshold = 3650 }
+/// ```
+#[derive(PartialEq, Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub device: DeviceConfig,
+    pub poll: PollConfig,
+    pub connection: ConnectionConfig,
+    pub output: OutputConfig,
+    /// The faults injected by the mock battery.
+    pub faults: FaultConfig,
+    pub replay: ReplayConfig,
+    pub console: ConsoleConfig,
+    pub scan: ScanConfig,
+    pub write: WriteConfig,
+    pub protection: ProtectionConfig,
+    pub history: HistoryConfig,
+    pub energy: EnergyConfig,
+    pub sessions: SessionConfig,
+    pub charger: ChargerConfig,
+    pub resistance: ResistanceConfig,
+    pub capacity: CapacityConfig,
+    pub imbalance: ImbalanceConfig,
+    /// The `[[rule]]` tables.
+    #[serde(rename = "rule", deserialize_with = "alert_rules")]
+    pub alerts: AlertRules,
+}
+
+/// How the target device is selected, see `Matcher`.
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceConfig {
+    /// The advertised name of the target device, empty to match any name.
+    pub name: String,
+    pub name_match: NameMatch,
+    /// The address of the target device.
+    pub address: Option<String>,
+    /// A 16-bit service UUID the target device has to advertise.
+    pub service: Option<u16>,
+    /// The minimum signal strength (dBm).
+    pub min_rssi: Option<i16>,
+}
+
+/// How often each register is polled, see `Scheduler`.
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PollConfig {
+    /// The cell voltages (seconds).
+    pub voltage: u64,
+    /// The pack voltage, current and state of charge (seconds).
+    pub detail: u64,
+    /// The protection counters (seconds).
+    pub protect: u64,
+    /// The random delay added to each poll, as a fraction of its interval.
+    pub jitter: f32,
+    /// The longest interval while fast polling after an alert (seconds).
+    pub fast_interval: u64,
+    /// How long to fast poll after an alert (seconds).
+    pub fast_duration: u64,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectionConfig {
+    /// How long to scan for the target device (seconds).
+    pub scan_timeout: u64,
+    /// How long to wait for a connection to be established (seconds).
+    pub connect_timeout: u64,
+    /// How long to wait for a complete response (seconds).
+    pub response_timeout: u64,
+    /// How many times to retry a failed operation.
+    pub retries: u32,
+    /// The delay before the first reconnection attempt (seconds).
+    pub reconnect_delay: u64,
+    /// The maximum delay between reconnection attempts (seconds).
+    pub max_reconnect_delay: u64,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    /// Print every poll to stdout.
+    pub stdout: bool,
+    pub telemetry: TelemetryConfig,
+    /// Record every request and notification to this file, see `CaptureRecord`.
+    pub capture: Option<PathBuf>,
+}
+
+/// Replays a capture instead of connecting to a device.
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplayConfig {
+    /// The capture to replay, see `CaptureRecord`.
+    pub file: Option<PathBuf>,
+    /// How many times faster than the original to replay.
+    pub speed: f64,
+}
+
+/// The interactive console, see `Console`.
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConsoleConfig {
+    /// The file keeping the command history.
+    pub history: Option<PathBuf>,
+    /// The file keeping the macros (TOML).
+    pub macros: Option<PathBuf>,
+    /// How long to wait for the next reply fragment (seconds).
+    pub timeout: u64,
+}
+
+/// The registers read by the scanner, see `Scanner`.
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScanConfig {
+    /// The first register to read.
+    pub first: u8,
+    /// The last register to read.
+    pub last: u8,
+    /// The delay between two requests (milliseconds).
+    pub pace: u64,
+    /// How long to wait for the next reply fragment (milliseconds).
+    pub timeout: u64,
+}
+
+/// How frames changing the battery are written, see `SafeWriter`.
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WriteConfig {
+    /// Only show the frames that would be written.
+    pub dry_run: bool,
+    /// The file every write is appended to (JSON lines).
+    pub audit_log: Option<PathBuf>,
+    /// Who writes, recorded in the audit log. Defaults to the `USER`
+    /// environment variable.
+    pub user: Option<String>,
+}
+
+/// The SQLite history of the snapshots, see `HistoryStore`.
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+    /// How long every snapshot is kept (days).
+    pub raw_days: u32,
+    /// How long the per minute aggregates are kept (days), hourly ones are
+    /// kept forever.
+    pub minute_days: u32,
+}
+
+/// The energy charged and discharged, see `EnergyAccumulator`.
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnergyConfig {
+    /// The file keeping the totals (JSON).
+    pub state: Option<PathBuf>,
+    /// Polls further apart are not integrated (seconds).
+    pub max_gap: u64,
+}
+
+/// How snapshots are segmented into sessions, see `SessionDetector`.
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    /// The current above which charging or discharging starts (A).
+    pub start_current: f64,
+    /// The current below which charging or discharging stops (A).
+    pub stop_current: f64,
+    /// How long a new kind of session must last (seconds).
+    pub min_duration: u64,
+    /// Polls further apart are not integrated (seconds).
+    pub max_gap: u64,
+}
+
+/// How the phase of the external charger is inferred, see
+/// `ChargerClassifier`. The voltages default to those of the chemistry.
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChargerConfig {
+    pub chemistry: Chemistry,
+    /// The cells in series, defaults to the number of cell voltages read.
+    pub cell_count: Option<u8>,
+    /// The capacity (Ah), defaults to the standard capacity read.
+    pub capacity: Option<f64>,
+    /// The absorption voltage per cell (mV).
+    pub absorption_voltage: Option<i16>,
+    /// The float voltage per cell (mV).
+    pub float_voltage: Option<i16>,
+    /// How far below a setpoint the voltage per cell may be (mV).
+    pub tolerance: i16,
+    /// The current ending absorption, as a fraction of the capacity (C).
+    pub tail_current: f64,
+    /// How long absorption may last before the charger never tapers (seconds).
+    pub max_absorption: u64,
+    /// How long the voltage may take to fall to float before floating too
+    /// high raises an alert (seconds).
+    pub float_settle: u64,
+    /// The cell overvoltage protection of the battery (mV).
+    pub cell_overvoltage: Option<i16>,
+    /// How close to `cell_overvoltage` a cell may float (mV).
+    pub overvoltage_margin: i16,
+}
+
+/// How the internal resistance is estimated, see `ResistanceEstimator`.
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResistanceConfig {
+    /// The file keeping the daily means (JSON).
+    pub state: Option<PathBuf>,
+    /// The smallest current step to estimate from (A).
+    pub min_step: f64,
+    /// The longest time between the two snapshots of a step (seconds).
+    pub max_interval: u64,
+    /// Estimates trusted less are not recorded, from 0 to 1.
+    pub min_confidence: f64,
+    /// The days averaged into the baseline and the latest resistance.
+    pub window: u32,
+    /// How much more than the median cell a cell may rise, e.g. 0.2 for 20 %.
+    pub rise_threshold: f64,
+}
+
+/// How the usable capacity is measured, see `CapacityTracker`.
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CapacityConfig {
+    /// The file keeping the measurements (JSON).
+    pub state: Option<PathBuf>,
+    /// A cell at this voltage means the battery is full (mV).
+    pub full_cell_voltage: i16,
+    /// A cell at this voltage while discharging means it is empty (mV).
+    pub empty_cell_voltage: i16,
+    /// The smallest state of charge a partial measurement covers (%).
+    pub min_soc_span: u8,
+    /// Polls further apart end the measurement (seconds).
+    pub max_gap: u64,
+    /// The measurements averaged into the state of health.
+    pub window: u32,
+    /// How far the capacity of the BMS may be off the measured one, e.g. 0.1
+    /// for 10 %.
+    pub divergence: f64,
+}
+
+/// How the imbalance of the cells is followed, see `ImbalanceTracker`.
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImbalanceConfig {
+    /// The file keeping the statistics (JSON).
+    pub state: Option<PathBuf>,
+    /// A cell at this voltage while charging is the end of charge (mV).
+    pub end_of_charge_voltage: i16,
+    /// A cell at this voltage while discharging is the end of discharge (mV).
+    pub end_of_discharge_voltage: i16,
+    /// Discharging at this state of charge is the end of discharge (%).
+    pub end_of_discharge_soc: u8,
+    /// The mean deviation costing a cell the most score (mV).
+    pub deviation: i16,
+    /// Cells scoring lower are weak, from 0 to 100.
+    pub weak_score: f64,
+    /// The ends of charge and discharge to see before calling a cell weak.
+    pub min_episodes: u32,
+}
+
+/// Where the protection counters are tracked, see `ProtectionTracker`.
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProtectionConfig {
+    /// The file keeping the last counters of each battery (JSON).
+    pub state: Option<PathBuf>,
+    /// The file protection events are appended to (JSON lines).
+    pub timeline: Option<PathBuf>,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    pub enabled: bool,
+    pub directory: PathBuf,
+    pub format: TelemetryFormat,
+    pub rotation: Rotation,
+    pub compress: bool,
+}
+
+/// Where to load the configuration from, and the values to override.
+///
+/// Overrides use dotted keys (e.g. `device.name`). Values are parsed as TOML
+/// when possible (`30`, `true`, `"csv"`) and used as a string otherwise, or
+/// when the key takes a string (e.g. a numeric `device.name`).
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+pub struct ConfigSource {
+    pub path: Option<PathBuf>,
+    pub overrides: Vec<(String, String)>,
+}
+
+#[derive(Eq, PartialEq, Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Missing value for argument {0}")]
+    MissingValue(String),
+    #[error("Unknown argument {0}")]
+    UnknownArgument(String),
+    #[error("Invalid override {0}")]
+    InvalidOverride(String),
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        DeviceConfig {
+            name: "AL12V100HFA0191".to_string(),
+            name_match: NameMatch::Exact,
+            address: None,
+            service: None,
+            min_rssi: None,
+        }
+    }
+}
+
+impl Default for PollConfig {
+    fn default() -> Self {
+        PollConfig {
+            voltage: 5,
+            detail: 5,
+            protect: 60,
+            jitter: 0.1,
+            fast_interval: 1,
+            fast_duration: 60,
+        }
+    }
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        ConnectionConfig {
+            scan_timeout: 3,
+            connect_timeout: 10,
+            response_timeout: 5,
+            retries: 3,
+            reconnect_delay: 1,
+            max_reconnect_delay: 300,
+        }
+    }
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            stdout: true,
+            telemetry: TelemetryConfig::default(),
+            capture: None,
+        }
+    }
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        ReplayConfig {
+            file: None,
+            speed: 1.0,
+        }
+    }
+}
+
+impl Default for ConsoleConfig {
+    fn default() -> Self {
+        ConsoleConfig {
+            history: Some(PathBuf::from(".aces_history")),
+            macros: Some(PathBuf::from("aces_macros.toml")),
+            timeout: 2,
+        }
+    }
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        ScanConfig {
+            first: 0x00,
+            last: 0xff,
+            pace: 500,
+            timeout: 1000,
+        }
+    }
+}
+
+impl Default for WriteConfig {
+    fn default() -> Self {
+        WriteConfig {
+            dry_run: false,
+            audit_log: Some(PathBuf::from("aces_audit.jsonl")),
+            user: None,
+        }
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            enabled: false,
+            path: PathBuf::from("aces_history.sqlite"),
+            raw_days: 7,
+            minute_days: 90,
+        }
+    }
+}
+
+impl Default for EnergyConfig {
+    fn default() -> Self {
+        EnergyConfig {
+            state: Some(PathBuf::from("aces_energy.json")),
+            max_gap: 60,
+        }
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            start_current: 1.0,
+            stop_current: 0.3,
+            min_duration: 60,
+            max_gap: 60,
+        }
+    }
+}
+
+impl Default for ChargerConfig {
+    fn default() -> Self {
+        ChargerConfig {
+            chemistry: Chemistry::Lifepo4,
+            cell_count: None,
+            capacity: None,
+            absorption_voltage: None,
+            float_voltage: None,
+            tolerance: 25,
+            tail_current: 0.02,
+            max_absorption: 4 * 3600,
+            float_settle: 900,
+            cell_overvoltage: None,
+            overvoltage_margin: 50,
+        }
+    }
+}
+
+impl Default for ResistanceConfig {
+    fn default() -> Self {
+        ResistanceConfig {
+            state: Some(PathBuf::from("aces_resistance.json")),
+            min_step: 5.0,
+            max_interval: 10,
+            min_confidence: 0.5,
+            window: 7,
+            rise_threshold: 0.2,
+        }
+    }
+}
+
+impl Default for CapacityConfig {
+    fn default() -> Self {
+        CapacityConfig {
+            state: Some(PathBuf::from("aces_capacity.json")),
+            full_cell_voltage: 3450,
+            empty_cell_voltage: 2900,
+            min_soc_span: 40,
+            max_gap: 60,
+            window: 5,
+            divergence: 0.1,
+        }
+    }
+}
+
+impl Default for ImbalanceConfig {
+    fn default() -> Self {
+        ImbalanceConfig {
+            state: Some(PathBuf::from("aces_imbalance.json")),
+            end_of_charge_voltage: 3450,
+            end_of_discharge_voltage: 3000,
+            end_of_discharge_soc: 10,
+            deviation: 50,
+            weak_score: 70.0,
+            min_episodes: 3,
+        }
+    }
+}
+
+impl Default for ProtectionConfig {
+    fn default() -> Self {
+        ProtectionConfig {
+            state: Some(PathBuf::from("aces_protect.json")),
+            timeline: Some(PathBuf::from("aces_protection.jsonl")),
+        }
+    }
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig {
+            enabled: false,
+            directory: PathBuf::from("telemetry"),
+            format: TelemetryFormat::Csv,
+            rotation: Rotation::Daily,
+            compress: true,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(s: &str) -> Result<Self> {
+        let config: Self = toml::from_str(s)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks what deserializing can not: the alert rules and the device name
+    /// pattern.
+    fn validate(&self) -> Result<()> {
+        self.alerts.validate()?;
+        Matcher::from_config(&self.device)?;
+        Ok(())
+    }
+}
+
+impl ConfigSource {
+    /// The environment variable holding the path of the configuration file.
+    pub const PATH_VARIABLE: &'static str = "ACES_CONFIG";
+    /// The prefix of environment variables overriding a configuration value.
+    ///
+    /// Nested keys are separated by a double underscore, e.g.
+    /// `ACES_DEVICE__NAME` overrides `device.name`. Variables that do not
+    /// name a configuration key are ignored.
+    pub const VARIABLE_PREFIX: &'static str = "ACES_";
+    /// The configuration file used when no path is given, if it exists.
+    pub const DEFAULT_PATH: &'static str = "aces.toml";
+    /// The NVS namespace holding the configuration on the ESP32 devices.
+    pub const NVS_NAMESPACE: &'static str = "aces";
+    /// The NVS key holding the configuration (TOML) on the ESP32 devices.
+    pub const NVS_KEY: &'static str = "config";
+
+    /// Collects the configuration source from the environment and the
+    /// command line arguments (without the program name).
+    ///
+    /// Supported arguments are `--config <path>` and `--set <key>=<value>`.
+    /// Command line arguments take precedence over the environment.
+    pub fn from_env_and_args<E, A>(env: E, args: A) -> Result<Self>
+    where
+        E: IntoIterator<Item = (String, String)>,
+        A: IntoIterator<Item = String>,
+    {
+        let mut source = ConfigSource::default();
+
+        let mut env: Vec<_> = env.into_iter().collect();
+        env.sort();
+        for (name, value) in env {
+            if name == Self::PATH_VARIABLE {
+                source.path = Some(PathBuf::from(value));
+            } else if let Some(key) = name.strip_prefix(Self::VARIABLE_PREFIX) {
+                let key = key.to_lowercase().replace("__", ".");
+                if is_key(&key) {
+                    source.overrides.push((key, value));
+                } else {
+                    // e.g. a variable of another program sharing the prefix
+                    log::info!("ignoring {}, {} is not a configuration key", name, key);
+                }
+            }
+        }
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| ConfigError::MissingValue(arg.clone()))
+            };
+            match arg.as_str() {
+                "--config" => source.path = Some(PathBuf::from(value()?)),
+                "--set" => {
+                    let value = value()?;
+                    let (key, value) = value
+                        .split_once('=')
+                        .ok_or_else(|| ConfigError::InvalidOverride(value.clone()))?;
+                    source.overrides.push((key.to_string(), value.to_string()));
+                }
+                _ => return Err(ConfigError::UnknownArgument(arg).into()),
+            }
+        }
+
+        Ok(source)
+    }
+
+    /// Loads the configuration file and applies the overrides.
+    pub fn load(&self) -> Result<Config> {
+        let contents = match &self.path {
+            Some(path) => fs::read_to_string(path)?,
+            None => match fs::read_to_string(Self::DEFAULT_PATH) {
+                Ok(contents) => contents,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+                Err(err) => return Err(err.into()),
+            },
+        };
+        self.apply(&contents)
+    }
+
+    /// Parses a configuration stored on a device without a file system or
+    /// command line, e.g. in NVS, falling back to the defaults when nothing
+    /// is stored or it cannot be read or parsed.
+    pub fn load_stored<E>(stored: std::result::Result<Option<String>, E>) -> Config
+    where
+        E: Display,
+    {
+        match stored {
+            Ok(Some(contents)) => match Config::parse(&contents) {
+                Ok(config) => return config,
+                Err(err) => log::error!("invalid configuration: {}", err),
+            },
+            Ok(None) => log::info!("no configuration stored, using defaults"),
+            Err(err) => log::error!("failed to read configuration: {}", err),
+        }
+        Config::default()
+    }
+
+    fn apply(&self, contents: &str) -> Result<Config> {
+        let mut table: toml::Table = toml::from_str(contents)?;
+        for (key, value) in &self.overrides {
+            let parsed = parse_value(value);
+            let typed = !parsed.is_str();
+            set_value(&mut table, key, parsed)
+                .ok_or_else(|| ConfigError::InvalidOverride(key.clone()))?;
+            if typed && !is_config(&table) {
+                // the key may take a string that looks like a number
+                let mut string = table.clone();
+                set_value(&mut string, key, toml::Value::String(value.clone()));
+                if is_config(&string) {
+                    table = string;
+                }
+            }
+        }
+        let config: Config = toml::Value::Table(table).try_into()?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+fn alert_rules<'de, D>(deserializer: D) -> std::result::Result<AlertRules, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(AlertRules {
+        rules: Vec::deserialize(deserializer)?,
+    })
+}
+
+fn is_config(table: &toml::Table) -> bool {
+    toml::Value::Table(table.clone())
+        .try_into::<Config>()
+        .is_ok()
+}
+
+/// Whether `key` names a configuration value, whatever the type of its value.
+fn is_key(key: &str) -> bool {
+    let mut table = toml::Table::new();
+    if set_value(&mut table, key, toml::Value::Table(toml::Table::new())).is_none() {
+        return false;
+    }
+    match toml::Value::Table(table).try_into::<Config>() {
+        Ok(_) => true,
+        Err(err) => !err.to_string().contains("unknown field"),
+    }
+}
+
+fn parse_value(value: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+fn set_value(table: &mut toml::Table, key: &str, value: toml::Value) -> Option<()> {
+    match key.split_once('.') {
+        Some((head, rest)) => {
+            let entry = table
+                .entry(head)
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            set_value(entry.as_table_mut()?, rest, value)
+        }
+        None if !key.is_empty() => {
+            table.insert(key.to_string(), value);
+            Some(())
+        }
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse(
+            r#"
+            [device]
+            name = "AL12V100HFA0191"
+
+            [poll]
+            voltage = 5
+            protect = 300
+
+            [output.telemetry]
+            enabled = true
+            format = "json_lines"
+            rotation = { size = 1024 }
+
+            [[rule]]
+            name = "soc low"
+            condition = { type = "soc_below", threshold = 20 }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.poll,
+            PollConfig {
+                voltage: 5,
+                protect: 300,
+                ..Default::default()
+            }
+        );
+        assert_eq!(config.connection, ConnectionConfig::default());
+        assert_eq!(
+            config.output.telemetry,
+            TelemetryConfig {
+                enabled: true,
+                format: TelemetryFormat::JsonLines,
+                rotation: Rotation::Size(1024),
+                ..Default::default()
+            }
+        );
+        assert_eq!(config.alerts.rules.len(), 1);
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_parse_example_config() {
+        let config = Config::parse(include_str!("../../aces.example.toml")).unwrap();
+        assert_eq!(config.alerts.rules.len(), 2);
+    }
+
+    #[test]
+    fn test_from_env_and_args() {
+        let env = vec![
+            ("HOME".to_string(), "/root".to_string()),
+            ("ACES_CONFIG".to_string(), "env.toml".to_string()),
+            ("ACES_POLL__VOLTAGE".to_string(), "5".to_string()),
+            ("ACES_HOME".to_string(), "/opt/aces".to_string()),
+            ("ACES_POLL__VOLTGE".to_string(), "5".to_string()),
+        ];
+        let args = [
+            "--set",
+            "device.name=AL12V100HFA0192",
+            "--config",
+            "args.toml",
+        ];
+        let source =
+            ConfigSource::from_env_and_args(env, args.iter().map(|a| a.to_string())).unwrap();
+
+        assert_eq!(
+            source,
+            ConfigSource {
+                path: Some(PathBuf::from("args.toml")),
+                overrides: vec![
+                    ("poll.voltage".to_string(), "5".to_string()),
+                    ("device.name".to_string(), "AL12V100HFA0192".to_string()),
+                ],
+            }
+        );
+
+        assert!(ConfigSource::from_env_and_args(vec![], vec!["--config".to_string()]).is_err());
+        assert!(ConfigSource::from_env_and_args(vec![], vec!["--nope".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_apply_overrides() {
+        let source = ConfigSource {
+            path: None,
+            overrides: vec![
+                ("poll.voltage".to_string(), "5".to_string()),
+                ("device.name".to_string(), "AL12V100HFA0192".to_string()),
+                ("output.telemetry.enabled".to_string(), "true".to_string()),
+                ("output.telemetry.rotation".to_string(), "daily".to_string()),
+            ],
+        };
+        let config = source.apply("[poll]\nvoltage = 10\ndetail = 20\n").unwrap();
+
+        assert_eq!(config.device.name, "AL12V100HFA0192");
+        assert_eq!(config.poll.voltage, 5);
+        assert_eq!(config.poll.detail, 20);
+        assert!(config.output.telemetry.enabled);
+        assert_eq!(config.output.telemetry.rotation, Rotation::Daily);
+
+        let source = ConfigSource {
+            path: None,
+            overrides: vec![("device.name.first".to_string(), "x".to_string())],
+        };
+        assert!(source.apply("").is_err());
+
+        // a numeric name stays a string, a number where one is expected
+        // still has to be one
+        let source = ConfigSource {
+            path: None,
+            overrides: vec![
+                ("device.name".to_string(), "12".to_string()),
+                ("poll.voltage".to_string(), "7".to_string()),
+            ],
+        };
+        let config = source.apply("").unwrap();
+        assert_eq!(config.device.name, "12");
+        assert_eq!(config.poll.voltage, 7);
+        let source = ConfigSource {
+            path: None,
+            overrides: vec![("poll.voltage".to_string(), "often".to_string())],
+        };
+        assert!(source.apply("").is_err());
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        assert!(Config::parse("[poll]\nvoltge = 5\n").is_err());
+        assert!(Config::parse("[devise]\nname = \"x\"\n").is_err());
+        let source = ConfigSource {
+            path: None,
+            overrides: vec![("device.nmae".to_string(), "x".to_string())],
+        };
+        assert!(source.apply("").is_err());
+    }
+
+    #[test]
+    fn test_invalid_device_name() {
+        let config = "[device]\nname = \"AL12V(\"\nname_match = \"regex\"\n";
+        assert!(Config::parse(config).is_err());
+        let source = ConfigSource {
+            path: None,
+            overrides: vec![
+                ("device.name".to_string(), "AL12V(".to_string()),
+                ("device.name_match".to_string(), "regex".to_string()),
+            ],
+        };
+        assert!(source.apply("").is_err());
+    }
+
+    #[test]
+    fn test_load_stored() {
+        let stored = Ok::<_, String>(Some("[poll]\nvoltage = 5\n".to_string()));
+        assert_eq!(ConfigSource::load_stored(stored).poll.voltage, 5);
+        let default = Config::default();
+        assert_eq!(ConfigSource::load_stored(Ok::<_, String>(None)), default);
+        let invalid = Ok::<_, String>(Some("[poll]\nvoltage = \"x\"\n".to_string()));
+        assert_eq!(ConfigSource::load_stored(invalid), default);
+        assert_eq!(ConfigSource::load_stored(Err("no NVS")), default);
+    }
+
+    use super::*;
+}
+
+use crate::{
+    AlertRules, Chemistry, FaultConfig, Matcher, NameMatch, Result, Rotation, TelemetryFormat,
+};
+use serde::{Deserialize, Deserializer};
+use std::fmt::Display;
+use std::{fs, io, path::PathBuf};
@@ -0,0 +1,548 @@
+/// An interactive console sending arbitrary frames, to debug and explore
+/// registers.
+///
+/// Lines are commands (see `HELP`) or raw hex frames. The console starts in
+/// safe mode, which only sends read requests until it is unlocked. Other
+/// frames go through a `SafeWriter`.
+pub struct Console {
+    unlocked: bool,
+    timeout: Duration,
+    writer: SafeWriter,
+    history: Vec<String>,
+    macros: BTreeMap<String, Vec<String>>,
+    history_file: Option<PathBuf>,
+    macros_file: Option<PathBuf>,
+}
+
+pub const HELP: &str = "\
+detail, voltage, protect, clear   send a named request
+read <register>                   read a register (hex, e.g. 05)
+write <register> <hex>            write a register (unlocked only)
+[raw] <hex>                       send a frame, e.g. dda50300fffd77
+unlock, lock                      leave or enter safe mode
+history, !<n>, !!                 list or repeat commands
+define <name> <cmd>; <cmd>...     save a macro
+undefine <name>, macros           delete or list macros
+run <name>                        run a macro
+quit                              leave the console
+";
+
+#[derive(Eq, PartialEq, Debug, thiserror::Error)]
+pub enum ConsoleError {
+    #[error("Unknown command {0}, try help")]
+    UnknownCommand(String),
+    #[error("Missing argument for {0}")]
+    MissingArgument(&'static str),
+    #[error("Invalid register {0}")]
+    InvalidRegister(String),
+    #[error("Invalid hex {0}")]
+    InvalidHex(String),
+    #[error("Only read requests are sent in safe mode, unlock to send other frames")]
+    Locked,
+    #[error("Unknown macro {0}")]
+    UnknownMacro(String),
+    #[error("Macros can not run macros")]
+    NestedMacro,
+    #[error("No command {0} in the history")]
+    NotInHistory(String),
+}
+
+#[derive(Eq, PartialEq, Clone, Debug)]
+enum Command {
+    Help,
+    Quit,
+    Send(Vec<u8>),
+    Unlock,
+    Lock,
+    History,
+    Define(String, Vec<String>),
+    Undefine(String),
+    Macros,
+    Run(String),
+}
+
+impl Console {
+    pub fn new(timeout: Duration) -> Self {
+        Console {
+            unlocked: false,
+            timeout,
+            writer: SafeWriter::new(timeout),
+            history: Vec::new(),
+            macros: BTreeMap::new(),
+            history_file: None,
+            macros_file: None,
+        }
+    }
+
+    /// Creates a console keeping its history and macros in the configured
+    /// files, loading what they contain.
+    pub fn from_config(config: &ConsoleConfig) -> Result<Self> {
+        let mut console = Self::new(Duration::from_secs(config.timeout));
+        if let Some(path) = &config.history {
+            if let Ok(contents) = fs::read_to_string(path) {
+                console.history = contents.lines().map(str::to_string).collect();
+            }
+            console.history_file = Some(path.clone());
+        }
+        if let Some(path) = &config.macros {
+            if let Ok(contents) = fs::read_to_string(path) {
+                console.macros = toml::from_str(&contents)?;
+            }
+            console.macros_file = Some(path.clone());
+        }
+        Ok(console)
+    }
+
+    /// The writer sending the frames other than read requests.
+    pub fn writer(mut self, writer: SafeWriter) -> Self {
+        self.writer = writer;
+        self
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.unlocked
+    }
+
+    pub fn prompt(&self) -> &'static str {
+        if self.unlocked {
+            "aces (unlocked)> "
+        } else {
+            "aces> "
+        }
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    pub fn macros(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.macros
+    }
+
+    /// Runs a line, returning what to show, or `None` to leave the console.
+    pub async fn execute<T, C>(
+        &mut self,
+        line: &str,
+        supervisor: &mut ConnectionSupervisor<T, C>,
+    ) -> Result<Option<String>>
+    where
+        T: Transport,
+        C: Clock,
+    {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(Some(String::new()));
+        }
+        let line = self.recall(line)?;
+        self.remember(&line);
+
+        match Command::parse(&line)? {
+            Command::Quit => Ok(None),
+            Command::Run(name) => {
+                let lines = self
+                    .macros
+                    .get(&name)
+                    .cloned()
+                    .ok_or(ConsoleError::UnknownMacro(name))?;
+                let mut out = String::new();
+                for line in lines {
+                    out += &format!("{}{}\n", self.prompt(), line);
+                    match Command::parse(&line)? {
+                        Command::Run(_) => return Err(ConsoleError::NestedMacro.into()),
+                        Command::Quit => return Ok(None),
+                        command => out += &self.run(command, supervisor).await?,
+                    }
+                }
+                Ok(Some(out))
+            }
+            command => Ok(Some(self.run(command, supervisor).await?)),
+        }
+    }
+
+    async fn run<T, C>(
+        &mut self,
+        command: Command,
+        supervisor: &mut ConnectionSupervisor<T, C>,
+    ) -> Result<String>
+    where
+        T: Transport,
+        C: Clock,
+    {
+        let out = match command {
+            Command::Help => HELP.to_string(),
+            Command::Send(frame) => {
+                let fragments = if is_safe(&frame) {
+                    supervisor.exchange(&frame, self.timeout).await?
+                } else if !self.unlocked {
+                    return Err(ConsoleError::Locked.into());
+                } else {
+                    match self.writer.write_frame(supervisor, &frame).await? {
+                        Some(fragments) => fragments,
+                        None => return Ok(format!("> {}\ndry run, not sent\n", to_hex(&frame))),
+                    }
+                };
+
+                let mut out = format!("> {}\n", to_hex(&frame));
+                for fragment in &fragments {
+                    out += &format!("< {}\n", to_hex(fragment));
+                }
+                let reply = fragments.concat();
+                if reply.is_empty() {
+                    out += "no reply\n";
+                } else {
+                    out += &dissect(&reply);
+                }
+                out
+            }
+            Command::Unlock => {
+                self.unlocked = true;
+                "unlocked, write frames can change the battery configuration\n".to_string()
+            }
+            Command::Lock => {
+                self.unlocked = false;
+                "locked, only read requests are sent\n".to_string()
+            }
+            Command::History => self
+                .history
+                .iter()
+                .enumerate()
+                .map(|(i, line)| format!("{:>4}  {}\n", i + 1, line))
+                .collect(),
+            Command::Define(name, lines) => {
+                self.macros.insert(name.clone(), lines);
+                self.save_macros()?;
+                format!("defined {}\n", name)
+            }
+            Command::Undefine(name) => {
+                self.macros
+                    .remove(&name)
+                    .ok_or(ConsoleError::UnknownMacro(name.clone()))?;
+                self.save_macros()?;
+                format!("deleted {}\n", name)
+            }
+            Command::Macros => self
+                .macros
+                .iter()
+                .map(|(name, lines)| format!("{}: {}\n", name, lines.join("; ")))
+                .collect(),
+            Command::Run(_) | Command::Quit => unreachable!("handled by execute"),
+        };
+        Ok(out)
+    }
+
+    /// Resolves `!!` and `!<n>` to the command from the history.
+    fn recall(&self, line: &str) -> std::result::Result<String, ConsoleError> {
+        let Some(index) = line.strip_prefix('!') else {
+            return Ok(line.to_string());
+        };
+        let entry = match index {
+            "!" => self.history.last(),
+            _ => index
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| self.history.get(n.checked_sub(1)?)),
+        };
+        entry
+            .cloned()
+            .ok_or_else(|| ConsoleError::NotInHistory(line.to_string()))
+    }
+
+    fn remember(&mut self, line: &str) {
+        self.history.push(line.to_string());
+
+        let Some(path) = &self.history_file else {
+            return;
+        };
+        let appended = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", line));
+        if let Err(err) = appended {
+            log::error!("failed to write history: {}", err);
+        }
+    }
+
+    fn save_macros(&self) -> Result<()> {
+        if let Some(path) = &self.macros_file {
+            fs::write(path, toml::to_string(&self.macros)?)?;
+        }
+        Ok(())
+    }
+}
+
+impl Command {
+    fn parse(line: &str) -> std::result::Result<Self, ConsoleError> {
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        let mut args = rest.split_whitespace();
+
+        let command = match name {
+            "help" => Command::Help,
+            "quit" | "exit" => Command::Quit,
+            "detail" => Command::Send(Request::BatteryDetail.bytes().to_vec()),
+            "voltage" => Command::Send(Request::BatteryVoltage.bytes().to_vec()),
+            "protect" => Command::Send(Request::BatteryProtect.bytes().to_vec()),
+            "clear" => Command::Send(Request::Clear.bytes().to_vec()),
+            "read" => {
+                let register =
+                    parse_register(args.next().ok_or(ConsoleError::MissingArgument("read"))?)?;
+                Command::Send(Frame::read(register).bytes())
+            }
+            "write" => {
+                let register =
+                    parse_register(args.next().ok_or(ConsoleError::MissingArgument("write"))?)?;
+                let payload = rest.split_once(char::is_whitespace).map_or("", |(_, p)| p);
+                let payload = from_hex(payload)
+                    .ok_or_else(|| ConsoleError::InvalidHex(payload.to_string()))?;
+                Command::Send(Frame::write(register, payload).bytes())
+            }
+            "raw" => Command::Send(
+                from_hex(rest).ok_or_else(|| ConsoleError::InvalidHex(rest.to_string()))?,
+            ),
+            "unlock" => Command::Unlock,
+            "lock" => Command::Lock,
+            "history" => Command::History,
+            "define" => {
+                let name = args.next().ok_or(ConsoleError::MissingArgument("define"))?;
+                let body = rest[name.len()..].trim();
+                let lines: Vec<String> = body
+                    .split(';')
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                if lines.is_empty() {
+                    return Err(ConsoleError::MissingArgument("define"));
+                }
+                Command::Define(name.to_string(), lines)
+            }
+            "undefine" => Command::Undefine(
+                args.next()
+                    .ok_or(ConsoleError::MissingArgument("undefine"))?
+                    .to_string(),
+            ),
+            "macros" => Command::Macros,
+            "run" => Command::Run(
+                args.next()
+                    .ok_or(ConsoleError::MissingArgument("run"))?
+                    .to_string(),
+            ),
+            _ => match from_hex(line) {
+                Some(frame) if !frame.is_empty() => Command::Send(frame),
+                _ => return Err(ConsoleError::UnknownCommand(name.to_string())),
+            },
+        };
+        Ok(command)
+    }
+}
+
+/// Registers are hex, with or without `0x`.
+fn parse_register(s: &str) -> std::result::Result<u8, ConsoleError> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    u8::from_str_radix(digits, 16).map_err(|_| ConsoleError::InvalidRegister(s.to_string()))
+}
+
+/// Whether a frame is allowed in safe mode: `Request::Clear` or a read request.
+fn is_safe(frame: &[u8]) -> bool {
+    frame == Request::Clear.bytes()
+        || matches!(
+            Frame::parse(frame),
+            Ok(Frame {
+                direction: Direction::Write,
+                status: Frame::READ,
+                ..
+            })
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Command::parse("read 0x03"),
+            Ok(Command::Send(Request::BatteryDetail.bytes().to_vec()))
+        );
+        assert_eq!(
+            Command::parse("DDA50400FFFC77"),
+            Ok(Command::Send(Request::BatteryVoltage.bytes().to_vec()))
+        );
+        assert_eq!(
+            Command::parse("write e1 00 00"),
+            Ok(Command::Send(Frame::write(0xe1, vec![0x00, 0x00]).bytes()))
+        );
+        assert_eq!(
+            Command::parse("define cells voltage; read 04 ;"),
+            Ok(Command::Define(
+                "cells".to_string(),
+                vec!["voltage".to_string(), "read 04".to_string()]
+            ))
+        );
+        assert_eq!(
+            Command::parse("read 0x100"),
+            Err(ConsoleError::InvalidRegister("0x100".to_string()))
+        );
+        assert_eq!(
+            Command::parse("reboot"),
+            Err(ConsoleError::UnknownCommand("reboot".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_safe_mode() {
+        let mut supervisor = supervisor();
+        let mut console = Console::new(Duration::from_secs(1));
+
+        let out = execute(&mut console, &mut supervisor, "voltage").unwrap();
+        assert!(
+            out.starts_with("> dda50400fffc77\n< \n< dd040008"),
+            "{}",
+            out
+        );
+        assert!(out.contains("  cell 1                3.554 V\n"));
+
+        let err = execute(&mut console, &mut supervisor, "write e1 00 00").unwrap_err();
+        assert_eq!(err.to_string(), ConsoleError::Locked.to_string());
+        assert!(execute(&mut console, &mut supervisor, "raw dd5ae1020000ff1d77").is_err());
+        assert_eq!(supervisor.transport().written.len(), 2);
+
+        execute(&mut console, &mut supervisor, "unlock").unwrap();
+        let out = execute(&mut console, &mut supervisor, "!2").unwrap();
+        assert!(out.ends_with("no reply\n"), "{}", out);
+        assert_eq!(
+            supervisor.transport().written.last().unwrap(),
+            &Frame::write(0xe1, vec![0x00, 0x00]).bytes()
+        );
+        assert_eq!(console.history()[4], "write e1 00 00");
+
+        execute(&mut console, &mut supervisor, "lock").unwrap();
+        assert!(execute(&mut console, &mut supervisor, "!!").is_ok());
+        assert!(execute(&mut console, &mut supervisor, "!5").is_err());
+    }
+
+    #[test]
+    fn test_writer() {
+        let mut supervisor = supervisor();
+        let timeout = Duration::from_secs(1);
+        let mut console = Console::new(timeout).writer(SafeWriter::new(timeout).dry_run(true));
+        execute(&mut console, &mut supervisor, "unlock").unwrap();
+
+        let out = execute(&mut console, &mut supervisor, "write e1 00 00").unwrap();
+        assert_eq!(out, "> dd5ae1020000ff1d77\ndry run, not sent\n");
+        let err = execute(&mut console, &mut supervisor, "write 24 13 88").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            SafeWriteError::ConfigRegister(0x24).to_string()
+        );
+        assert!(supervisor.transport().written.is_empty());
+    }
+
+    #[test]
+    fn test_macros() {
+        let mut supervisor = supervisor();
+        let mut console = Console::new(Duration::from_secs(1));
+
+        execute(&mut console, &mut supervisor, "define all detail; protect").unwrap();
+        let out = execute(&mut console, &mut supervisor, "run all").unwrap();
+        assert!(out.contains("aces> protect\n"));
+        assert!(out.contains("  protection            none (0)\n"));
+        assert!(out.contains("  cell undervoltage     4\n"));
+
+        execute(&mut console, &mut supervisor, "define nested run all").unwrap();
+        assert!(execute(&mut console, &mut supervisor, "run nested").is_err());
+        execute(&mut console, &mut supervisor, "undefine nested").unwrap();
+        assert_eq!(
+            execute(&mut console, &mut supervisor, "macros").unwrap(),
+            "all: detail; protect\n"
+        );
+        assert!(block_on(console.execute("quit", &mut supervisor))
+            .unwrap()
+            .is_none());
+    }
+
+    fn execute(
+        console: &mut Console,
+        supervisor: &mut ConnectionSupervisor<MockBattery, ManualClock>,
+        line: &str,
+    ) -> Result<String> {
+        Ok(block_on(console.execute(line, supervisor))?.unwrap())
+    }
+
+    fn supervisor() -> ConnectionSupervisor<MockBattery, ManualClock> {
+        ConnectionSupervisor::new(
+            MockBattery::default(),
+            ManualClock::default(),
+            &ConnectionConfig::default(),
+        )
+    }
+
+    /// The mock battery, answering the known requests.
+    struct MockBattery {
+        responder: Responder<StaticResponses>,
+        written: Vec<Vec<u8>>,
+        pending: Rc<RefCell<VecDeque<Vec<u8>>>>,
+    }
+
+    struct MockReceiver(Rc<RefCell<VecDeque<Vec<u8>>>>);
+
+    impl Default for MockBattery {
+        fn default() -> Self {
+            MockBattery {
+                responder: Responder::new(StaticResponses),
+                written: Vec::new(),
+                pending: Rc::default(),
+            }
+        }
+    }
+
+    impl Transport for MockBattery {
+        type Receiver = MockReceiver;
+
+        async fn connect(&mut self) -> Result<MockReceiver> {
+            self.responder.connected();
+            Ok(MockReceiver(self.pending.clone()))
+        }
+
+        async fn is_connected(&mut self) -> bool {
+            true
+        }
+
+        async fn write(&mut self, value: &[u8]) -> Result<()> {
+            self.written.push(value.to_vec());
+            self.pending
+                .borrow_mut()
+                .extend(self.responder.write(value));
+            Ok(())
+        }
+    }
+
+    impl NotificationsReceiver for MockReceiver {
+        fn next(&mut self) -> Vec<u8> {
+            self.0.borrow_mut().pop_front().unwrap()
+        }
+
+        fn next_timeout(&mut self, _timeout: Duration) -> Option<Vec<u8>> {
+            self.0.borrow_mut().pop_front()
+        }
+    }
+
+    use super::*;
+    use crate::util::block_on;
+    use crate::{
+        ConnectionConfig, ManualClock, NotificationsReceiver, Responder, SafeWriteError,
+        StaticResponses,
+    };
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+}
+
+use crate::util::{from_hex, to_hex};
+use crate::{
+    dissect, Clock, ConnectionSupervisor, ConsoleConfig, Direction, Frame, Request, Result,
+    SafeWriter, Transport,
+};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write as _;
+use std::path::PathBuf;
+use std::time::Duration;
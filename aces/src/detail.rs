@@ -1,4 +1,4 @@
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Clone, Debug, Default, Serialize)]
 pub struct BatteryDetail {
     /// Total voltage (V).
     pub total_voltage: i16,
@@ -49,6 +49,42 @@ impl BatteryDetail {
             list_ntc: NtcList::parse_message(&msg[22..])?.0,
         })
     }
+
+    /// Encodes the detail, `charge` and `discharge` take precedence over
+    /// the matching bits of `control_state`.
+    pub fn to_message(&self) -> Vec<u8> {
+        let mut msg = Vec::new();
+        for value in [
+            self.total_voltage,
+            self.current,
+            self.residual_capacity,
+            self.standard_capacity,
+            self.cycles,
+            self.date_of_production,
+            self.equilibrium,
+            self.equilibrium_high,
+            self.protection_of_state.0,
+        ] {
+            // device uses big endian encoding
+            msg.extend_from_slice(&value.to_be_bytes());
+        }
+        msg.push(self.software_version);
+        msg.push(self.residual_capacity_percent);
+        msg.push((self.control_state & !3) | self.charge as u8 | (self.discharge as u8) << 1);
+        msg.push(self.battery_number);
+        msg.extend(NtcList(self.list_ntc.clone()).to_message());
+        msg
+    }
+
+    /// Whether the BMS is balancing a cell (from 0), `equilibrium` holding
+    /// the first 16 cells and `equilibrium_high` the next 16.
+    pub fn is_balancing(&self, cell: usize) -> bool {
+        match cell {
+            0..16 => self.equilibrium as u16 & (1 << cell) != 0,
+            16..32 => self.equilibrium_high as u16 & (1 << (cell - 16)) != 0,
+            _ => false,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -98,7 +134,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_to_message() {
+        let msg = [
+            0x05, 0x35, 0x00, 0x00, 0x24, 0xb7, 0x27, 0xde, 0x00, 0x0a, 0x2b, 0x94, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x20, 0x5c, 0x03, 0x04, 0x03, 0x0b, 0x84, 0x0b, 0x79, 0x0b,
+            0x75,
+        ];
+        let mut detail = BatteryDetail::parse_message(&msg).unwrap();
+        assert_eq!(detail.to_message(), msg);
+
+        detail.charge = false;
+        assert_eq!(detail.to_message()[20], 0x02);
+    }
+
+    #[test]
+    fn test_is_balancing() {
+        let detail = BatteryDetail {
+            equilibrium: 0b1001,
+            equilibrium_high: i16::MIN,
+            ..Default::default()
+        };
+        let balancing: Vec<usize> = (0..40).filter(|cell| detail.is_balancing(*cell)).collect();
+        assert_eq!(balancing, [0, 3, 31]);
+    }
+
     use super::*;
 }
 
 use super::{util::i16_from_bytes, NtcList, ParseError, ParseResult, ProtectionOfState};
+use serde::Serialize;
@@ -0,0 +1,310 @@
+/// A BLE advertisement, as reported by a scan.
+///
+/// The runners convert the advertisements reported by their BLE stack into
+/// this form, so every runner selects devices the same way.
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+pub struct Advertisement {
+    /// The advertised (local) name.
+    pub name: Option<String>,
+    /// The device address, e.g. `"AA:BB:CC:DD:EE:FF"`.
+    pub address: String,
+    /// The advertised service UUIDs.
+    pub service_uuids: Vec<u128>,
+    /// The manufacturer specific data, by company identifier.
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
+    /// The received signal strength (dBm).
+    pub rssi: Option<i16>,
+}
+
+/// How the advertised name is matched.
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NameMatch {
+    #[default]
+    Exact,
+    Prefix,
+    Regex,
+}
+
+/// Selects target devices from advertisements.
+#[derive(Clone, Debug)]
+pub struct Matcher {
+    name: Option<Name>,
+    address: Option<String>,
+    service: Option<u128>,
+    min_rssi: Option<i16>,
+}
+
+#[derive(Clone, Debug)]
+enum Name {
+    Exact(String),
+    Prefix(String),
+    Regex(Regex),
+}
+
+impl Advertisement {
+    /// Whether the advertisement includes the ACES service.
+    pub fn has_aces_service(&self) -> bool {
+        self.service_uuids
+            .contains(&uuid_from_u16(crate::SERVICE_UUID))
+    }
+}
+
+impl Matcher {
+    /// A matcher that accepts every advertisement.
+    pub fn any() -> Self {
+        Matcher {
+            name: None,
+            address: None,
+            service: None,
+            min_rssi: None,
+        }
+    }
+
+    pub fn from_config(config: &DeviceConfig) -> Result<Self> {
+        let mut matcher = Self::any();
+        if !config.name.is_empty() {
+            matcher = matcher.name(&config.name, config.name_match)?;
+        }
+        if let Some(address) = &config.address {
+            matcher = matcher.address(address);
+        }
+        if let Some(service) = config.service {
+            matcher = matcher.service(uuid_from_u16(service));
+        }
+        if let Some(min_rssi) = config.min_rssi {
+            matcher = matcher.min_rssi(min_rssi);
+        }
+        Ok(matcher)
+    }
+
+    pub fn name(mut self, name: &str, how: NameMatch) -> Result<Self> {
+        self.name = Some(match how {
+            NameMatch::Exact => Name::Exact(name.to_string()),
+            NameMatch::Prefix => Name::Prefix(name.to_string()),
+            NameMatch::Regex => Name::Regex(Regex::new(name)?),
+        });
+        Ok(self)
+    }
+
+    /// Only match the device with the given address (case-insensitive).
+    pub fn address(mut self, address: &str) -> Self {
+        self.address = Some(address.to_string());
+        self
+    }
+
+    /// Only match devices advertising the given service.
+    pub fn service(mut self, uuid: u128) -> Self {
+        self.service = Some(uuid);
+        self
+    }
+
+    /// Only match devices with at least the given signal strength (dBm).
+    pub fn min_rssi(mut self, rssi: i16) -> Self {
+        self.min_rssi = Some(rssi);
+        self
+    }
+
+    pub fn matches(&self, adv: &Advertisement) -> bool {
+        let name = adv.name.as_deref().unwrap_or_default();
+        let name_matches = match &self.name {
+            None => true,
+            Some(Name::Exact(expected)) => name == expected,
+            Some(Name::Prefix(prefix)) => name.starts_with(prefix.as_str()),
+            Some(Name::Regex(regex)) => regex.is_match(name),
+        };
+        let address_matches = match &self.address {
+            None => true,
+            Some(address) => adv.address.eq_ignore_ascii_case(address),
+        };
+        let service_matches = match self.service {
+            None => true,
+            Some(uuid) => adv.service_uuids.contains(&uuid),
+        };
+        let rssi_matches = match (self.min_rssi, adv.rssi) {
+            (None, _) => true,
+            (Some(min), Some(rssi)) => rssi >= min,
+            (Some(_), None) => false,
+        };
+
+        name_matches && address_matches && service_matches && rssi_matches
+    }
+
+    /// Returns the matching advertisements, strongest signal first.
+    pub fn rank<'a, I>(&self, advertisements: I) -> Vec<&'a Advertisement>
+    where
+        I: IntoIterator<Item = &'a Advertisement>,
+    {
+        let mut matching: Vec<_> = advertisements
+            .into_iter()
+            .filter(|adv| self.matches(adv))
+            .collect();
+        // devices without an RSSI are ranked last
+        matching.sort_by_key(|adv| Reverse(adv.rssi.unwrap_or(i16::MIN)));
+        matching
+    }
+
+    /// Returns the position of the matching advertisement with the strongest
+    /// signal.
+    ///
+    /// The position identifies the device the advertisement came from, as
+    /// addresses are not unique on every platform (CoreBluetooth reports
+    /// them all as `00:00:00:00:00:00`).
+    pub fn best<'a, I>(&self, advertisements: I) -> Option<usize>
+    where
+        I: IntoIterator<Item = &'a Advertisement>,
+    {
+        advertisements
+            .into_iter()
+            .enumerate()
+            .filter(|(_, adv)| self.matches(adv))
+            .min_by_key(|(_, adv)| Reverse(adv.rssi.unwrap_or(i16::MIN)))
+            .map(|(idx, _)| idx)
+    }
+}
+
+/// Expands a 16-bit UUID using the Bluetooth base UUID.
+pub fn uuid_from_u16(uuid: u16) -> u128 {
+    BLUETOOTH_BASE_UUID | ((uuid as u128) << 96)
+}
+
+/// Returns the 16-bit form of a UUID, if it is based on the Bluetooth base UUID.
+pub fn uuid_to_u16(uuid: u128) -> Option<u16> {
+    if uuid & !(0xffff << 96) == BLUETOOTH_BASE_UUID {
+        Some((uuid >> 96) as u16)
+    } else {
+        None
+    }
+}
+
+const BLUETOOTH_BASE_UUID: u128 = 0x00000000_0000_1000_8000_00805f9b34fb;
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_uuid_from_u16() {
+        assert_eq!(
+            uuid_from_u16(0xff00),
+            0x0000ff00_0000_1000_8000_00805f9b34fb
+        );
+        assert_eq!(uuid_to_u16(uuid_from_u16(0xff01)), Some(0xff01));
+        assert_eq!(uuid_to_u16(0x1234), None);
+    }
+
+    #[test]
+    fn test_match_name() {
+        let adv = advertisement("AL12V100HFA0191", "AA:BB:CC:DD:EE:FF", Some(-60));
+
+        let exact = Matcher::any()
+            .name("AL12V100HFA0191", NameMatch::Exact)
+            .unwrap();
+        assert!(exact.matches(&adv));
+        let exact = Matcher::any().name("AL12V100", NameMatch::Exact).unwrap();
+        assert!(!exact.matches(&adv));
+
+        let prefix = Matcher::any().name("AL12V100", NameMatch::Prefix).unwrap();
+        assert!(prefix.matches(&adv));
+
+        let regex = Matcher::any()
+            .name("^AL12V\\d+HFA", NameMatch::Regex)
+            .unwrap();
+        assert!(regex.matches(&adv));
+        let regex = Matcher::any().name("^AL24V", NameMatch::Regex).unwrap();
+        assert!(!regex.matches(&adv));
+
+        assert!(Matcher::any().name("(", NameMatch::Regex).is_err());
+    }
+
+    #[test]
+    fn test_match_address_service_and_rssi() {
+        let mut adv = advertisement("AL12V100HFA0191", "AA:BB:CC:DD:EE:FF", Some(-60));
+
+        assert!(Matcher::any().address("aa:bb:cc:dd:ee:ff").matches(&adv));
+        assert!(!Matcher::any().address("AA:BB:CC:DD:EE:00").matches(&adv));
+
+        let service = Matcher::any().service(uuid_from_u16(crate::SERVICE_UUID));
+        assert!(!service.matches(&adv));
+        adv.service_uuids.push(uuid_from_u16(crate::SERVICE_UUID));
+        assert!(service.matches(&adv));
+        assert!(adv.has_aces_service());
+
+        assert!(Matcher::any().min_rssi(-70).matches(&adv));
+        assert!(!Matcher::any().min_rssi(-50).matches(&adv));
+    }
+
+    #[test]
+    fn test_rank() {
+        let advs = vec![
+            advertisement("AL12V100HFA0191", "00:00:00:00:00:01", Some(-80)),
+            advertisement("AL12V100HFA0192", "00:00:00:00:00:02", None),
+            advertisement("AL12V100HFA0193", "00:00:00:00:00:03", Some(-40)),
+            advertisement("OTHER", "00:00:00:00:00:04", Some(-20)),
+        ];
+
+        let matcher = Matcher::any().name("AL12V", NameMatch::Prefix).unwrap();
+        let ranked: Vec<_> = matcher
+            .rank(&advs)
+            .into_iter()
+            .map(|adv| adv.address.as_str())
+            .collect();
+        assert_eq!(
+            ranked,
+            vec![
+                "00:00:00:00:00:03",
+                "00:00:00:00:00:01",
+                "00:00:00:00:00:02"
+            ]
+        );
+        assert_eq!(matcher.best(&advs), Some(2));
+
+        // CoreBluetooth hides the addresses
+        let hidden: Vec<_> = advs
+            .iter()
+            .map(|adv| Advertisement {
+                address: "00:00:00:00:00:00".to_string(),
+                ..adv.clone()
+            })
+            .collect();
+        assert_eq!(matcher.best(&hidden), Some(2));
+        assert_eq!(
+            Matcher::any()
+                .name("NONE", NameMatch::Exact)
+                .unwrap()
+                .best(&advs),
+            None
+        );
+    }
+
+    #[test]
+    fn test_from_config() {
+        let config = DeviceConfig {
+            name: "AL12V".to_string(),
+            name_match: NameMatch::Prefix,
+            address: None,
+            service: Some(crate::SERVICE_UUID),
+            min_rssi: None,
+        };
+        let matcher = Matcher::from_config(&config).unwrap();
+
+        let mut adv = advertisement("AL12V100HFA0191", "AA:BB:CC:DD:EE:FF", None);
+        assert!(!matcher.matches(&adv));
+        adv.service_uuids.push(uuid_from_u16(crate::SERVICE_UUID));
+        assert!(matcher.matches(&adv));
+    }
+
+    fn advertisement(name: &str, address: &str, rssi: Option<i16>) -> Advertisement {
+        Advertisement {
+            name: Some(name.to_string()),
+            address: address.to_string(),
+            rssi,
+            ..Default::default()
+        }
+    }
+
+    use super::*;
+}
+
+use crate::{DeviceConfig, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::{cmp::Reverse, collections::HashMap};
@@ -0,0 +1,282 @@
+/// Takes apart the frames in `bytes` for people to read, e.g. a log sent by a
+/// customer.
+///
+/// Every frame shows its direction, register, status, length, payload and
+/// checksum, and the decoded values (with units) of the registers this crate
+/// knows. Payloads of other registers are shown as a hex dump.
+pub fn dissect(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let length = Frame::length(rest).unwrap_or(rest.len()).min(rest.len());
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        dissect_frame(&mut out, &rest[..length]);
+        rest = &rest[length..];
+    }
+    out
+}
+
+fn dissect_frame(out: &mut String, bytes: &[u8]) {
+    let frame = match Frame::parse(bytes) {
+        Ok(frame) => frame,
+        Err(err) => {
+            line(out, "invalid frame", err);
+            out.push_str(&hex_dump(bytes));
+            return;
+        }
+    };
+
+    line(out, "frame", to_hex(bytes));
+    let (direction, status) = match frame.direction {
+        Direction::Write => (
+            "request",
+            match frame.status {
+                Frame::WRITE => "write",
+                _ => "read",
+            },
+        ),
+        Direction::Notify => (
+            "response",
+            match frame.status {
+                0x00 => "ok",
+                _ => "error",
+            },
+        ),
+    };
+    line(out, "direction", direction);
+    line(
+        out,
+        "register",
+        format!(
+            "{:02x} ({})",
+            frame.register,
+            register_name(frame.register).unwrap_or("unknown")
+        ),
+    );
+    line(out, "status", format!("{:02x} ({})", frame.status, status));
+    line(out, "length", frame.payload.len());
+    line(out, "payload", to_hex(&frame.payload));
+    if frame.is_checksum_valid() {
+        line(out, "checksum", format!("{:04x} (valid)", frame.checksum));
+    } else {
+        line(
+            out,
+            "checksum",
+            format!(
+                "{:04x} (invalid, expected {:04x})",
+                frame.checksum,
+                frame.expected_checksum()
+            ),
+        );
+    }
+
+    if frame.payload.is_empty() {
+        return;
+    }
+    let decoded = match (frame.direction, frame.register) {
+        (Direction::Notify, 0x03) => BatteryDetail::parse_message(&frame.payload).map(detail),
+        (Direction::Notify, 0x04) => BatteryVoltage::parse_message(&frame.payload).map(voltage),
+        (Direction::Notify, 0xaa) => BatteryProtect::parse_message(&frame.payload).map(protect),
+        _ => Err(ParseError::InvalidData),
+    };
+    match decoded {
+        Ok(fields) => {
+            for (name, value) in fields {
+                out.push_str(&format!("  {:<22}{}\n", name, value));
+            }
+        }
+        Err(ParseError::InvalidData) => out.push_str(&hex_dump(&frame.payload)),
+        Err(err) => {
+            line(out, "invalid payload", err);
+            out.push_str(&hex_dump(&frame.payload));
+        }
+    }
+}
+
+fn line<V>(out: &mut String, name: &str, value: V)
+where
+    V: Display,
+{
+    let line = format!("{:<24}{}", name, value);
+    out.push_str(line.trim_end());
+    out.push('\n');
+}
+
+fn register_name(register: u8) -> Option<&'static str> {
+    match register {
+        0x03 => Some("detail"),
+        0x04 => Some("voltage"),
+        0xaa => Some("protect"),
+        _ => None,
+    }
+}
+
+fn detail(detail: BatteryDetail) -> Vec<(String, String)> {
+    let date = detail.date_of_production as u16;
+    let protection = ProtectionOfState::NAMES
+        .get(detail.protection_of_state.0 as usize)
+        .copied()
+        .unwrap_or("unknown");
+    let on_off = |on| if on { "on" } else { "off" };
+
+    let mut fields = vec![
+        field(
+            "total voltage",
+            format!("{:.2} V", detail.total_voltage as f32 / 100.0),
+        ),
+        field("current", format!("{:.2} A", detail.current as f32 / 100.0)),
+        field(
+            "residual capacity",
+            format!("{:.2} Ah", detail.residual_capacity as f32 / 100.0),
+        ),
+        field(
+            "standard capacity",
+            format!("{:.2} Ah", detail.standard_capacity as f32 / 100.0),
+        ),
+        field("cycles", detail.cycles),
+        field(
+            "date of production",
+            format!(
+                "{}-{:02}-{:02}",
+                2000 + (date >> 9),
+                (date >> 5) & 0x0f,
+                date & 0x1f
+            ),
+        ),
+        field(
+            "balancing",
+            format!(
+                "{:016b} {:016b}",
+                detail.equilibrium_high as u16, detail.equilibrium as u16
+            ),
+        ),
+        field(
+            "protection",
+            format!("{} ({})", protection, detail.protection_of_state.0),
+        ),
+        field(
+            "software version",
+            format!(
+                "{}.{}",
+                detail.software_version >> 4,
+                detail.software_version & 0x0f
+            ),
+        ),
+        field(
+            "state of charge",
+            format!("{} %", detail.residual_capacity_percent),
+        ),
+        field(
+            "mosfets",
+            format!(
+                "charge {}, discharge {} ({:02x})",
+                on_off(detail.charge),
+                on_off(detail.discharge),
+                detail.control_state
+            ),
+        ),
+        field("cells", detail.battery_number),
+    ];
+    for (i, t) in detail.list_ntc.iter().enumerate() {
+        fields.push(field(
+            &format!("temperature {}", i + 1),
+            format!("{:.1} °C", *t as f32 / 10.0),
+        ));
+    }
+    fields
+}
+
+fn voltage(voltage: BatteryVoltage) -> Vec<(String, String)> {
+    voltage
+        .0
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            field(
+                &format!("cell {}", i + 1),
+                format!("{:.3} V", *v as f32 / 1000.0),
+            )
+        })
+        .collect()
+}
+
+fn protect(protect: BatteryProtect) -> Vec<(String, String)> {
+    BatteryProtect::FIELD_NAMES
+        .iter()
+        .enumerate()
+        .map(|(i, name)| field(&name.replace('_', " "), protect.value_at(i).unwrap_or(0)))
+        .collect()
+}
+
+fn field<V>(name: &str, value: V) -> (String, String)
+where
+    V: Display,
+{
+    (name.to_string(), value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_dissect_request() {
+        assert_eq!(
+            dissect(Request::BatteryDetail.bytes()),
+            "\
+frame                   dda50300fffd77
+direction               request
+register                03 (detail)
+status                  a5 (read)
+length                  0
+payload
+checksum                fffd (valid)
+"
+        );
+    }
+
+    #[test]
+    fn test_dissect_response() {
+        let bytes = from_hex("dd040008 0de20ddc0dec0ded cafe 77 dd0500").unwrap();
+        assert_eq!(
+            dissect(&bytes),
+            "\
+frame                   dd0400080de20ddc0dec0dedcafe77
+direction               response
+register                04 (voltage)
+status                  00 (ok)
+length                  8
+payload                 0de20ddc0dec0ded
+checksum                cafe (invalid, expected fc2d)
+  cell 1                3.554 V
+  cell 2                3.548 V
+  cell 3                3.564 V
+  cell 4                3.565 V
+
+invalid frame           Not enough data
+0000  dd 05 00                                         |...|
+"
+        );
+
+        let detail = dissect(&StaticResponses.response(&Request::BatteryDetail));
+        assert!(detail.contains("checksum                fb07 (valid)\n"));
+        assert!(detail.contains("  total voltage         13.36 V\n"));
+        assert!(detail.contains("  date of production    2021-12-20\n"));
+        assert!(detail.contains("  software version      2.0\n"));
+        assert!(detail.contains("  temperature 1         21.2 °C\n"));
+
+        let unknown = dissect(&from_hex("dd0500024143ff7a77").unwrap());
+        assert!(unknown.contains("register                05 (unknown)\n"));
+        assert!(unknown.ends_with("0000  41 43                                            |AC|\n"));
+    }
+
+    use super::*;
+    use crate::util::from_hex;
+    use crate::{Request, ResponseSource, StaticResponses};
+}
+
+use crate::util::{hex_dump, to_hex};
+use crate::{
+    BatteryDetail, BatteryProtect, BatteryVoltage, Direction, Frame, ParseError, ProtectionOfState,
+};
+use std::fmt::Display;
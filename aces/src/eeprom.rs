@@ -0,0 +1,594 @@
+/// A configuration register of the BMS.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct ConfigRegister {
+    pub address: u8,
+    /// The name of the `BatteryConfig` field.
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+/// A register whose value differs between two configurations, see
+/// `BatteryConfig::diff`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct ConfigChange {
+    pub register: ConfigRegister,
+    /// The current value, formatted as in `BatteryConfig::to_toml`.
+    pub from: String,
+    /// The new value, formatted as in `BatteryConfig::to_toml`.
+    pub to: String,
+    /// The payload writing the new value.
+    pub payload: Vec<u8>,
+}
+
+#[derive(Eq, PartialEq, Debug, thiserror::Error)]
+pub enum CloneError {
+    #[error("Configuration of model {config:?} cannot be cloned onto model {pack:?}")]
+    ModelMismatch { config: String, pack: String },
+    #[error(transparent)]
+    Encode(#[from] EncodeError),
+}
+
+/// A value its register cannot hold, e.g. a temperature past the range of
+/// the register once converted to 0.1 K.
+#[derive(Eq, PartialEq, Debug, thiserror::Error)]
+#[error("Cannot encode {register}: {error}")]
+pub struct EncodeError {
+    pub register: &'static str,
+    pub error: ParseError,
+}
+
+/// A temperature (0.1 °C), stored by the BMS in 0.1 K.
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct Temperature(pub i16);
+
+/// The value of a configuration register.
+pub trait ConfigValue: Sized {
+    fn decode(payload: &[u8]) -> ParseResult<Self>;
+
+    fn encode(&self) -> ParseResult<Vec<u8>>;
+}
+
+/// Describes every configuration register once, as a field of
+/// `BatteryConfig` and an entry of `BatteryConfig::REGISTERS`.
+macro_rules! battery_config {
+    ($($address:literal => $name:ident: $ty:ty, $description:literal;)*) => {
+        /// The configuration stored in the EEPROM of the BMS, read and written
+        /// in factory mode (see `FactorySession`).
+        ///
+        /// Every field is required when deserializing, so a file missing a
+        /// register is rejected instead of restoring it as zero.
+        #[derive(Eq, PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+        #[serde(deny_unknown_fields)]
+        pub struct BatteryConfig {
+            $(
+                #[doc = $description]
+                pub $name: $ty,
+            )*
+        }
+
+        impl BatteryConfig {
+            /// The configuration registers, in order of address.
+            pub const REGISTERS: &'static [ConfigRegister] = &[
+                $(ConfigRegister {
+                    address: $address,
+                    name: stringify!($name),
+                    description: $description,
+                },)*
+            ];
+
+            /// Sets the field of `register` from its payload.
+            pub fn decode(&mut self, register: u8, payload: &[u8]) -> ParseResult<()> {
+                match register {
+                    $($address => self.$name = ConfigValue::decode(payload)?,)*
+                    _ => return Err(ParseError::InvalidData),
+                }
+                Ok(())
+            }
+
+            /// The payload of `register`, or an error when its value does not
+            /// fit the payload.
+            pub fn encode(&self, register: u8) -> ParseResult<Vec<u8>> {
+                match register {
+                    $($address => self.$name.encode(),)*
+                    _ => Err(ParseError::InvalidData),
+                }
+            }
+        }
+    };
+}
+
+battery_config! {
+    0x10 => design_capacity: u16, "Design capacity (10 mAh).";
+    0x11 => cycle_capacity: u16, "Cycle capacity (10 mAh).";
+    0x12 => cell_full_voltage: u16, "Cell voltage at 100 % (mV).";
+    0x13 => cell_empty_voltage: u16, "Cell voltage at 0 % (mV).";
+    0x14 => self_discharge_rate: u16, "Self-discharge rate (0.1 %).";
+    0x15 => manufacture_date: u16, "Date of manufacture, `(year - 2000) << 9 | month << 5 | day`.";
+    0x16 => serial_number: u16, "Serial number.";
+    0x17 => cycle_count: u16, "Cycles.";
+    0x18 => charge_over_temp: Temperature, "Charge over-temperature protection.";
+    0x19 => charge_over_temp_release: Temperature, "Charge over-temperature release.";
+    0x1a => charge_under_temp: Temperature, "Charge under-temperature protection.";
+    0x1b => charge_under_temp_release: Temperature, "Charge under-temperature release.";
+    0x1c => discharge_over_temp: Temperature, "Discharge over-temperature protection.";
+    0x1d => discharge_over_temp_release: Temperature, "Discharge over-temperature release.";
+    0x1e => discharge_under_temp: Temperature, "Discharge under-temperature protection.";
+    0x1f => discharge_under_temp_release: Temperature, "Discharge under-temperature release.";
+    0x20 => pack_overvoltage: u16, "Pack overvoltage protection (10 mV).";
+    0x21 => pack_overvoltage_release: u16, "Pack overvoltage release (10 mV).";
+    0x22 => pack_undervoltage: u16, "Pack undervoltage protection (10 mV).";
+    0x23 => pack_undervoltage_release: u16, "Pack undervoltage release (10 mV).";
+    0x24 => cell_overvoltage: u16, "Cell overvoltage protection (mV).";
+    0x25 => cell_overvoltage_release: u16, "Cell overvoltage release (mV).";
+    0x26 => cell_undervoltage: u16, "Cell undervoltage protection (mV).";
+    0x27 => cell_undervoltage_release: u16, "Cell undervoltage release (mV).";
+    0x28 => charge_overcurrent: i16, "Charge overcurrent protection (10 mA).";
+    0x29 => discharge_overcurrent: i16, "Discharge overcurrent protection (10 mA), negative.";
+    0x2a => balance_start_voltage: u16, "Cell voltage above which balancing starts (mV).";
+    0x2b => balance_window: u16, "Cell voltage difference above which cells are balanced (mV).";
+    0x2c => shunt_resistance: u16, "Current shunt resistance (0.1 mΩ).";
+    0x2d => functions: u16, "Function bits: switch, load detection, balancing, balancing only while charging, LEDs, LED capacity.";
+    0x2e => ntc_enabled: u16, "Enabled NTCs, one bit each.";
+    0x2f => cell_count: u16, "Cells in series.";
+    0x30 => fet_control_time: u16, "MOSFET control time (s).";
+    0x31 => led_time: u16, "LED display time (s).";
+    0x32 => cell_voltage_80: u16, "Cell voltage at 80 % (mV).";
+    0x33 => cell_voltage_60: u16, "Cell voltage at 60 % (mV).";
+    0x34 => cell_voltage_40: u16, "Cell voltage at 40 % (mV).";
+    0x35 => cell_voltage_20: u16, "Cell voltage at 20 % (mV).";
+    0x36 => hard_cell_overvoltage: u16, "Hardware cell overvoltage protection (mV).";
+    0x37 => hard_cell_undervoltage: u16, "Hardware cell undervoltage protection (mV).";
+    0x38 => hard_current: u16, "Hardware overcurrent and short circuit thresholds and delays (bits).";
+    0x39 => hard_voltage_delays: [u8; 2], "Hardware cell undervoltage and overvoltage delays (s).";
+    0x3a => charge_temp_delays: [u8; 2], "Charge under-temperature and over-temperature delays (s).";
+    0x3b => discharge_temp_delays: [u8; 2], "Discharge under-temperature and over-temperature delays (s).";
+    0x3c => pack_voltage_delays: [u8; 2], "Pack undervoltage and overvoltage delays (s).";
+    0x3d => cell_voltage_delays: [u8; 2], "Cell undervoltage and overvoltage delays (s).";
+    0x3e => charge_overcurrent_delays: [u8; 2], "Charge overcurrent delay and release (s).";
+    0x3f => discharge_overcurrent_delays: [u8; 2], "Discharge overcurrent delay and release (s).";
+    0xa0 => manufacturer: String, "Manufacturer name.";
+    0xa1 => model: String, "Device name.";
+    0xa2 => barcode: String, "Barcode.";
+}
+
+impl BatteryConfig {
+    /// The registers identifying a pack rather than configuring it, kept by
+    /// `cloned_onto`: date of manufacture, serial number, cycles and barcode.
+    pub const PACK_SPECIFIC: &'static [u8] = &[0x15, 0x16, 0x17, 0xa2];
+
+    /// Looks up a register by the name of its field.
+    pub fn register(name: &str) -> Option<&'static ConfigRegister> {
+        Self::REGISTERS
+            .iter()
+            .find(|register| register.name == name)
+    }
+
+    /// Formats the configuration as TOML, one register per line with its
+    /// address and description.
+    pub fn to_toml(&self) -> Result<String> {
+        let values = self.values()?;
+        let mut out = String::new();
+        for register in Self::REGISTERS {
+            out += &format!(
+                "{} = {} # {:02x}: {}\n",
+                register.name, values[register.name], register.address, register.description
+            );
+        }
+        Ok(out)
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)? + "\n")
+    }
+
+    pub fn from_json(text: &str) -> Result<Self> {
+        Ok(serde_json::from_str(text)?)
+    }
+
+    /// Reads a configuration file, as JSON if its extension is `json` and as
+    /// TOML otherwise.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        if is_json(path) {
+            Self::from_json(&text)
+        } else {
+            Self::from_toml(&text)
+        }
+    }
+
+    /// Writes a configuration file, as JSON if its extension is `json` and as
+    /// TOML otherwise.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = if is_json(path) {
+            self.to_json()?
+        } else {
+            self.to_toml()?
+        };
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// The registers to write to turn this configuration into `target`, in
+    /// order of address.
+    pub fn diff(&self, target: &BatteryConfig) -> Result<Vec<ConfigChange>> {
+        let (from, to) = (self.values()?, target.values()?);
+        let mut changes = Vec::new();
+        for register in Self::REGISTERS {
+            let payload = target.encode_register(register)?;
+            if self.encode_register(register)? != payload {
+                changes.push(ConfigChange {
+                    register: *register,
+                    from: from[register.name].to_string(),
+                    to: to[register.name].to_string(),
+                    payload,
+                });
+            }
+        }
+        Ok(changes)
+    }
+
+    /// This configuration for another pack of the same model, keeping the
+    /// `PACK_SPECIFIC` registers of `pack`.
+    pub fn cloned_onto(&self, pack: &BatteryConfig) -> std::result::Result<Self, CloneError> {
+        if self.model != pack.model {
+            return Err(CloneError::ModelMismatch {
+                config: self.model.clone(),
+                pack: pack.model.clone(),
+            });
+        }
+        let mut config = self.clone();
+        for register in Self::REGISTERS
+            .iter()
+            .filter(|register| Self::PACK_SPECIFIC.contains(&register.address))
+        {
+            let payload = pack.encode_register(register)?;
+            config.decode(register.address, &payload).unwrap();
+        }
+        Ok(config)
+    }
+
+    /// The payload of `register`, naming it when its value cannot be encoded.
+    fn encode_register(
+        &self,
+        register: &ConfigRegister,
+    ) -> std::result::Result<Vec<u8>, EncodeError> {
+        self.encode(register.address).map_err(|error| EncodeError {
+            register: register.name,
+            error,
+        })
+    }
+
+    pub(crate) fn values(&self) -> Result<toml::Table> {
+        let toml::Value::Table(values) = toml::Value::try_from(self)? else {
+            unreachable!("a struct serializes to a table");
+        };
+        Ok(values)
+    }
+}
+
+impl Display for ConfigChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({:02x}): {} -> {}",
+            self.register.name, self.register.address, self.from, self.to
+        )
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "json")
+}
+
+impl ConfigValue for u16 {
+    fn decode(payload: &[u8]) -> ParseResult<Self> {
+        match payload {
+            [_, _] => Ok(u16_from_bytes(payload)),
+            _ => Err(ParseError::InvalidData),
+        }
+    }
+
+    fn encode(&self) -> ParseResult<Vec<u8>> {
+        // device uses big endian encoding
+        Ok(self.to_be_bytes().to_vec())
+    }
+}
+
+impl ConfigValue for i16 {
+    fn decode(payload: &[u8]) -> ParseResult<Self> {
+        match payload {
+            [_, _] => Ok(i16_from_bytes(payload)),
+            _ => Err(ParseError::InvalidData),
+        }
+    }
+
+    fn encode(&self) -> ParseResult<Vec<u8>> {
+        Ok(self.to_be_bytes().to_vec())
+    }
+}
+
+impl ConfigValue for Temperature {
+    fn decode(payload: &[u8]) -> ParseResult<Self> {
+        let kelvin = i16::decode(payload)?;
+        Ok(Temperature(
+            kelvin.checked_sub(2731).ok_or(ParseError::InvalidData)?,
+        ))
+    }
+
+    fn encode(&self) -> ParseResult<Vec<u8>> {
+        self.0
+            .checked_add(2731)
+            .ok_or(ParseError::InvalidData)?
+            .encode()
+    }
+}
+
+impl ConfigValue for [u8; 2] {
+    fn decode(payload: &[u8]) -> ParseResult<Self> {
+        payload.try_into().map_err(|_| ParseError::InvalidData)
+    }
+
+    fn encode(&self) -> ParseResult<Vec<u8>> {
+        Ok(self.to_vec())
+    }
+}
+
+/// Text is stored with its length first.
+impl ConfigValue for String {
+    fn decode(payload: &[u8]) -> ParseResult<Self> {
+        let (len, text) = payload.split_first().ok_or(ParseError::NotEnoughData)?;
+        let text = text.get(..*len as usize).ok_or(ParseError::NotEnoughData)?;
+        Ok(String::from_utf8_lossy(text).into_owned())
+    }
+
+    fn encode(&self) -> ParseResult<Vec<u8>> {
+        let len = u8::try_from(self.len()).map_err(|_| ParseError::InvalidData)?;
+        let mut payload = vec![len];
+        payload.extend_from_slice(self.as_bytes());
+        Ok(payload)
+    }
+}
+
+/// The configuration of a 4 cell LiFePO4 pack, passing `validate`.
+#[cfg(test)]
+pub(crate) fn valid_config() -> BatteryConfig {
+    BatteryConfig {
+        design_capacity: 10000,
+        cycle_capacity: 10000,
+        cell_full_voltage: 3450,
+        cell_empty_voltage: 2700,
+        charge_over_temp: Temperature(550),
+        charge_over_temp_release: Temperature(500),
+        charge_under_temp: Temperature(0),
+        charge_under_temp_release: Temperature(50),
+        discharge_over_temp: Temperature(650),
+        discharge_over_temp_release: Temperature(600),
+        discharge_under_temp: Temperature(-200),
+        discharge_under_temp_release: Temperature(-150),
+        pack_overvoltage: 1460,
+        pack_overvoltage_release: 1380,
+        pack_undervoltage: 1000,
+        pack_undervoltage_release: 1100,
+        cell_overvoltage: 3650,
+        cell_overvoltage_release: 3450,
+        cell_undervoltage: 2500,
+        cell_undervoltage_release: 2800,
+        charge_overcurrent: 10000,
+        discharge_overcurrent: -10000,
+        balance_start_voltage: 3400,
+        balance_window: 30,
+        shunt_resistance: 10,
+        cell_count: 4,
+        cell_voltage_80: 3350,
+        cell_voltage_60: 3300,
+        cell_voltage_40: 3250,
+        cell_voltage_20: 3200,
+        hard_cell_overvoltage: 3800,
+        hard_cell_undervoltage: 2300,
+        model: "AL12V100HFA0191".to_string(),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_registers() {
+        let registers = BatteryConfig::REGISTERS;
+        assert_eq!(registers.len(), 51);
+        assert!(registers.windows(2).all(|r| r[0].address < r[1].address));
+        assert_eq!(
+            BatteryConfig::register("cell_overvoltage"),
+            Some(&ConfigRegister {
+                address: 0x24,
+                name: "cell_overvoltage",
+                description: "Cell overvoltage protection (mV).",
+            })
+        );
+    }
+
+    #[test]
+    fn test_to_toml() {
+        let config = BatteryConfig {
+            charge_over_temp: Temperature(550),
+            cell_voltage_delays: [2, 5],
+            model: "AL12V100HFA0191".to_string(),
+            ..Default::default()
+        };
+        let toml = config.to_toml().unwrap();
+        assert_eq!(toml.lines().count(), BatteryConfig::REGISTERS.len());
+        assert!(
+            toml.contains("\ncharge_over_temp = 550 # 18: Charge over-temperature protection.\n")
+        );
+        assert!(toml.contains("\ncell_voltage_delays = [2, 5] # 3d: "));
+        assert!(toml.contains("\nmodel = \"AL12V100HFA0191\" # a1: Device name.\n"));
+    }
+
+    #[test]
+    fn test_decode_encode() {
+        let mut config = BatteryConfig::default();
+        config.decode(0x24, &[0x0e, 0x42]).unwrap();
+        config.decode(0x18, &[0x0b, 0xcd]).unwrap();
+        config.decode(0x29, &[0xd8, 0xf0]).unwrap();
+        config.decode(0x3d, &[0x02, 0x05]).unwrap();
+        config.decode(0xa1, b"\x0fAL12V100HFA0191").unwrap();
+        assert_eq!(config.cell_overvoltage, 3650);
+        assert_eq!(config.charge_over_temp, Temperature(290));
+        assert_eq!(config.discharge_overcurrent, -10000);
+        assert_eq!(config.cell_voltage_delays, [2, 5]);
+        assert_eq!(config.model, "AL12V100HFA0191");
+
+        for (register, payload) in [
+            (0x24, vec![0x0e, 0x42]),
+            (0x18, vec![0x0b, 0xcd]),
+            (0xa1, b"\x0fAL12V100HFA0191".to_vec()),
+        ] {
+            assert_eq!(config.encode(register), Ok(payload));
+        }
+        assert_eq!(config.encode(0x03), Err(ParseError::InvalidData));
+
+        assert_eq!(config.decode(0x24, &[0x0e]), Err(ParseError::InvalidData));
+        assert_eq!(
+            config.decode(0xa0, &[0x05, 0x41]),
+            Err(ParseError::NotEnoughData)
+        );
+        assert_eq!(config.decode(0x03, &[]), Err(ParseError::InvalidData));
+
+        // past the range of the register in 0.1 K, or of the length byte
+        assert_eq!(
+            config.decode(0x18, &[0x80, 0x00]),
+            Err(ParseError::InvalidData)
+        );
+        config.charge_over_temp = Temperature(i16::MAX);
+        assert_eq!(config.encode(0x18), Err(ParseError::InvalidData));
+        config.model = "A".repeat(256);
+        assert_eq!(config.encode(0xa1), Err(ParseError::InvalidData));
+    }
+
+    #[test]
+    fn test_toml_json_round_trip() {
+        let config = BatteryConfig {
+            charge_over_temp: Temperature(550),
+            discharge_overcurrent: -10000,
+            cell_voltage_delays: [2, 5],
+            model: "AL12V100HFA0191".to_string(),
+            ..Default::default()
+        };
+        let toml = config.to_toml().unwrap();
+        assert_eq!(BatteryConfig::from_toml(&toml).unwrap(), config);
+        let json = config.to_json().unwrap();
+        assert_eq!(BatteryConfig::from_json(&json).unwrap(), config);
+
+        // a missing or misspelled register is an error, not a zero
+        let missing = toml.replace("cell_count = 0", "");
+        assert!(BatteryConfig::from_toml(&missing).is_err());
+        let misspelled = toml.replace("cell_count = 0", "cells_count = 0");
+        assert!(BatteryConfig::from_toml(&misspelled).is_err());
+    }
+
+    #[test]
+    fn test_diff() {
+        let live = BatteryConfig {
+            cell_overvoltage: 3650,
+            charge_over_temp: Temperature(550),
+            model: "AL12V100HFA0191".to_string(),
+            ..Default::default()
+        };
+        let target = BatteryConfig {
+            cell_overvoltage: 3600,
+            charge_over_temp: Temperature(500),
+            ..live.clone()
+        };
+        assert_eq!(live.diff(&live).unwrap(), []);
+
+        let changes = live.diff(&target).unwrap();
+        assert_eq!(
+            changes
+                .iter()
+                .map(ConfigChange::to_string)
+                .collect::<Vec<_>>(),
+            [
+                "charge_over_temp (18): 550 -> 500",
+                "cell_overvoltage (24): 3650 -> 3600",
+            ]
+        );
+        assert_eq!(changes[1].payload, [0x0e, 0x10]);
+
+        let hot = BatteryConfig {
+            charge_over_temp: Temperature(i16::MAX),
+            ..live.clone()
+        };
+        assert_eq!(
+            live.diff(&hot).unwrap_err().to_string(),
+            "Cannot encode charge_over_temp: Invalid data"
+        );
+    }
+
+    #[test]
+    fn test_cloned_onto() {
+        let template = BatteryConfig {
+            cell_overvoltage: 3600,
+            serial_number: 7,
+            cycle_count: 12,
+            barcode: "A".to_string(),
+            model: "AL12V100HFA0191".to_string(),
+            ..Default::default()
+        };
+        let pack = BatteryConfig {
+            cell_overvoltage: 3650,
+            serial_number: 42,
+            manufacture_date: 0x3a21,
+            cycle_count: 3,
+            barcode: "B".to_string(),
+            model: "AL12V100HFA0191".to_string(),
+            ..Default::default()
+        };
+
+        let cloned = template.cloned_onto(&pack).unwrap();
+        assert_eq!(
+            cloned,
+            BatteryConfig {
+                cell_overvoltage: 3600,
+                ..pack.clone()
+            }
+        );
+
+        let other = BatteryConfig {
+            model: "AL24V50HFA".to_string(),
+            ..pack.clone()
+        };
+        assert_eq!(
+            template.cloned_onto(&other),
+            Err(CloneError::ModelMismatch {
+                config: "AL12V100HFA0191".to_string(),
+                pack: "AL24V50HFA".to_string(),
+            })
+        );
+
+        let long = BatteryConfig {
+            barcode: "B".repeat(256),
+            ..pack
+        };
+        assert_eq!(
+            template.cloned_onto(&long),
+            Err(CloneError::Encode(EncodeError {
+                register: "barcode",
+                error: ParseError::InvalidData,
+            }))
+        );
+    }
+
+    use super::*;
+}
+
+use crate::util::{i16_from_bytes, u16_from_bytes};
+use crate::{ParseError, ParseResult, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::Path;
@@ -0,0 +1,415 @@
+/// The energy and charge that went in and out of the battery on one day.
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnergyTotals {
+    /// Energy charged (Wh).
+    pub charged_wh: f64,
+    /// Energy discharged (Wh).
+    pub discharged_wh: f64,
+    /// Charge charged (Ah).
+    pub charged_ah: f64,
+    /// Charge discharged (Ah).
+    pub discharged_ah: f64,
+    /// Time covered by polls (seconds).
+    pub covered: f64,
+    /// Time between polls further apart than `max_gap`, not integrated
+    /// (seconds).
+    pub gaps: f64,
+}
+
+/// The periods `EnergyAccumulator::report` groups days by.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum EnergyPeriod {
+    Day,
+    /// ISO 8601 weeks, starting on Monday.
+    Week,
+    Month,
+}
+
+/// The energy of a period, see `EnergyAccumulator::report`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct EnergyReport {
+    /// E.g. `2026-10-18`, `2026-W42` or `2026-10`.
+    pub label: String,
+    pub totals: EnergyTotals,
+    /// Discharged over charged energy, when something was charged.
+    pub efficiency: Option<f64>,
+    /// Discharged charge over the standard capacity, when it is known.
+    pub equivalent_cycles: Option<f64>,
+}
+
+/// What the accumulator keeps between restarts.
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct EnergyState {
+    days: BTreeMap<NaiveDate, EnergyTotals>,
+    /// The time, power (W) and current (A) of the last poll.
+    last: Option<(DateTime<FixedOffset>, f64, f64)>,
+    /// `BatteryDetail::standard_capacity` (Ah).
+    capacity_ah: Option<f64>,
+    /// `BatteryDetail::cycles`.
+    bms_cycles: Option<i16>,
+}
+
+/// Integrates the power of consecutive snapshots into energy charged and
+/// discharged per day.
+///
+/// Power and current are integrated with the trapezoidal rule, their
+/// positive (charging) and negative (discharging) parts separately. Two
+/// polls further apart than `max_gap` are not integrated, the time between
+/// them is counted as a gap instead. Snapshots repeating an earlier detail
+/// are skipped, so a missed poll widens the interval. An interval is booked
+/// on the day of its second poll. With a state file the totals survive
+/// restarts.
+pub struct EnergyAccumulator {
+    state: EnergyState,
+    max_gap: Duration,
+    file: Option<StateFile>,
+}
+
+impl EnergyTotals {
+    fn add(&mut self, other: &EnergyTotals) {
+        self.charged_wh += other.charged_wh;
+        self.discharged_wh += other.discharged_wh;
+        self.charged_ah += other.charged_ah;
+        self.discharged_ah += other.discharged_ah;
+        self.covered += other.covered;
+        self.gaps += other.gaps;
+    }
+}
+
+impl EnergyPeriod {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "day" => Some(EnergyPeriod::Day),
+            "week" => Some(EnergyPeriod::Week),
+            "month" => Some(EnergyPeriod::Month),
+            _ => None,
+        }
+    }
+
+    fn label(self, day: NaiveDate) -> String {
+        match self {
+            EnergyPeriod::Day => day.format("%Y-%m-%d").to_string(),
+            EnergyPeriod::Week => {
+                let week = day.iso_week();
+                format!("{}-W{:02}", week.year(), week.week())
+            }
+            EnergyPeriod::Month => day.format("%Y-%m").to_string(),
+        }
+    }
+}
+
+impl EnergyAccumulator {
+    pub fn new(max_gap: Duration) -> Self {
+        EnergyAccumulator {
+            state: EnergyState::default(),
+            max_gap,
+            file: None,
+        }
+    }
+
+    /// Creates an accumulator keeping its totals in the configured state
+    /// file, loading them when it exists. The file is written at most once
+    /// a minute.
+    pub fn from_config(config: &EnergyConfig) -> Result<Self> {
+        let mut accumulator = Self::new(Duration::from_secs(config.max_gap));
+        (accumulator.state, accumulator.file) =
+            StateFile::open(config.state.as_ref(), chrono::Duration::minutes(1))?;
+        Ok(accumulator)
+    }
+
+    /// Integrates the interval since the previous snapshot with a refreshed
+    /// detail.
+    pub fn update(&mut self, snapshot: &Snapshot) -> Result<()> {
+        if !snapshot.is_refreshed(Register::Detail) {
+            return Ok(());
+        }
+        let detail = &snapshot.detail;
+        let voltage = detail.total_voltage as f64 / 100.0;
+        let current = detail.current as f64 / 100.0;
+        let power = voltage * current;
+        let now = snapshot.timestamp;
+
+        if let Some((time, last_power, last_current)) = self.state.last {
+            let seconds = (now - time).num_milliseconds() as f64 / 1000.0;
+            let day = self.state.days.entry(now.date_naive()).or_default();
+            if seconds <= 0.0 {
+                log::warn!("snapshot at {} is not after the previous one", now);
+            } else if seconds > self.max_gap.as_secs_f64() {
+                day.gaps += seconds;
+            } else {
+                let hours = seconds / 3600.0;
+                let (charged, discharged) = trapezoid(last_power, power);
+                day.charged_wh += charged * hours;
+                day.discharged_wh += discharged * hours;
+                let (charged, discharged) = trapezoid(last_current, current);
+                day.charged_ah += charged * hours;
+                day.discharged_ah += discharged * hours;
+                day.covered += seconds;
+            }
+        }
+        self.state.last = Some((now, power, current));
+        if detail.standard_capacity > 0 {
+            self.state.capacity_ah = Some(detail.standard_capacity as f64 / 100.0);
+        }
+        self.state.bms_cycles = Some(detail.cycles);
+        if let Some(file) = &mut self.file {
+            file.save(&self.state, snapshot.timestamp)?;
+        }
+        Ok(())
+    }
+
+    /// The totals per period, oldest first.
+    pub fn report(&self, period: EnergyPeriod) -> Vec<EnergyReport> {
+        let mut periods: Vec<(String, EnergyTotals)> = Vec::new();
+        for (day, totals) in &self.state.days {
+            let label = period.label(*day);
+            match periods.last_mut() {
+                Some((last, sum)) if *last == label => sum.add(totals),
+                _ => periods.push((label, totals.clone())),
+            }
+        }
+        periods
+            .into_iter()
+            .map(|(label, totals)| self.report_of(label, totals))
+            .collect()
+    }
+
+    /// The totals since the first snapshot.
+    pub fn total(&self) -> EnergyReport {
+        let mut totals = EnergyTotals::default();
+        self.state.days.values().for_each(|day| totals.add(day));
+        self.report_of("total".to_string(), totals)
+    }
+
+    /// The cycle count reported by the BMS at the last snapshot, to compare
+    /// with `EnergyReport::equivalent_cycles` of `total`.
+    pub fn bms_cycles(&self) -> Option<i16> {
+        self.state.bms_cycles
+    }
+
+    fn report_of(&self, label: String, totals: EnergyTotals) -> EnergyReport {
+        EnergyReport {
+            label,
+            efficiency: (totals.charged_wh > 0.0).then(|| totals.discharged_wh / totals.charged_wh),
+            equivalent_cycles: self
+                .state
+                .capacity_ah
+                .map(|capacity| totals.discharged_ah / capacity),
+            totals,
+        }
+    }
+}
+
+impl Sink for EnergyAccumulator {
+    fn handle(&mut self, event: &Event) -> Result<()> {
+        match event {
+            Event::Snapshot(snapshot) => self.update(snapshot),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Display for EnergyReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<12}in {:>8.3} kWh  out {:>8.3} kWh",
+            self.label,
+            self.totals.charged_wh / 1000.0,
+            self.totals.discharged_wh / 1000.0
+        )?;
+        match self.efficiency {
+            Some(efficiency) => write!(f, "  efficiency {:>5.1} %", efficiency * 100.0)?,
+            None => write!(f, "  efficiency     -  ")?,
+        }
+        if let Some(cycles) = self.equivalent_cycles {
+            write!(f, "  cycles {:>6.2}", cycles)?;
+        }
+        if self.totals.gaps > 0.0 {
+            write!(f, "  gaps {}", format_seconds(self.totals.gaps))?;
+        }
+        Ok(())
+    }
+}
+
+/// The charging and discharging parts of the integral of a value going
+/// linearly from `a` to `b` over one unit of time.
+///
+/// When the sign changes, the interval is split where the value crosses
+/// zero, at `a / (a - b)`.
+fn trapezoid(a: f64, b: f64) -> (f64, f64) {
+    if a * b >= 0.0 {
+        let mean = (a + b) / 2.0;
+        return (mean.max(0.0), (-mean).max(0.0));
+    }
+    let crossing = a / (a - b);
+    let first = a * crossing / 2.0;
+    let second = b * (1.0 - crossing) / 2.0;
+    (first.max(second), -first.min(second))
+}
+
+fn format_seconds(seconds: f64) -> String {
+    let minutes = (seconds / 60.0).round() as i64;
+    format!("{}h{:02}m", minutes / 60, minutes % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_integrate() {
+        let mut energy = EnergyAccumulator::new(Duration::from_secs(60));
+        // 13.00 V, charging 10 A for a minute, then discharging 5 A
+        for (t, current) in [(0, 1000), (30, 1000), (60, 1000), (90, -500), (120, -500)] {
+            energy.update(&snapshot(at(10, t), 1300, current)).unwrap();
+        }
+
+        let total = energy.total();
+        // the 30 s from +10 A to -5 A cross zero after 20 s, adding 100 As
+        // charged and 25 As discharged
+        let charged = 130.0 * 70.0 / 3600.0;
+        let discharged = 65.0 * 35.0 / 3600.0;
+        assert!((total.totals.charged_wh - charged).abs() < 1e-9);
+        assert!((total.totals.discharged_wh - discharged).abs() < 1e-9);
+        assert!((total.totals.charged_ah - 10.0 * 70.0 / 3600.0).abs() < 1e-9);
+        assert!((total.totals.discharged_ah - 5.0 * 35.0 / 3600.0).abs() < 1e-9);
+        assert_eq!(total.totals.covered, 120.0);
+        assert_eq!(total.totals.gaps, 0.0);
+        assert!((total.efficiency.unwrap() - discharged / charged).abs() < 1e-9);
+        let cycles = total.totals.discharged_ah / 100.0;
+        assert!((total.equivalent_cycles.unwrap() - cycles).abs() < 1e-12);
+        assert_eq!(energy.bms_cycles(), Some(9));
+    }
+
+    #[test]
+    fn test_trapezoid() {
+        let (charged, discharged) = trapezoid(10.0, -5.0);
+        assert!((charged - 10.0 / 3.0).abs() < 1e-12);
+        assert!((discharged - 5.0 / 6.0).abs() < 1e-12);
+        let (charged, discharged) = trapezoid(-4.0, 2.0);
+        assert!((charged - 1.0 / 3.0).abs() < 1e-12);
+        assert!((discharged - 4.0 / 3.0).abs() < 1e-12);
+        assert_eq!(trapezoid(2.0, 4.0), (3.0, 0.0));
+        assert_eq!(trapezoid(0.0, -4.0), (0.0, 2.0));
+    }
+
+    #[test]
+    fn test_gaps() {
+        let mut energy = EnergyAccumulator::new(Duration::from_secs(60));
+        energy.update(&snapshot(at(10, 0), 1300, -1000)).unwrap();
+        energy.update(&snapshot(at(10, 60), 1300, -1000)).unwrap();
+        // the logger was down for an hour
+        energy.update(&snapshot(at(10, 3660), 1300, -1000)).unwrap();
+        energy.update(&snapshot(at(10, 3720), 1300, -1000)).unwrap();
+        // the detail failed to poll, the voltages were read
+        let mut stale = snapshot(at(10, 3750), 1300, -1000);
+        stale.refreshed = vec![Register::Voltage];
+        energy.update(&stale).unwrap();
+        energy.update(&snapshot(at(10, 3810), 1300, -1000)).unwrap();
+
+        let total = energy.total();
+        assert!((total.totals.discharged_wh - 130.0 * 120.0 / 3600.0).abs() < 1e-9);
+        assert_eq!(total.totals.gaps, 3690.0);
+        assert_eq!(total.efficiency, None);
+        assert!(total.to_string().ends_with("gaps 1h02m"), "{}", total);
+    }
+
+    #[test]
+    fn test_report() {
+        let mut energy = EnergyAccumulator::new(Duration::from_secs(3600));
+        // an hour charging at 100 W each day from Saturday to Monday, and an
+        // hour discharging at 90 W on Monday
+        for day in [17, 18, 19] {
+            energy.update(&snapshot(at(day, 0), 1000, 1000)).unwrap();
+            energy.update(&snapshot(at(day, 3600), 1000, 1000)).unwrap();
+        }
+        energy
+            .update(&snapshot(at(19, 3 * 3600), 1000, -900))
+            .unwrap();
+        energy
+            .update(&snapshot(at(19, 4 * 3600), 1000, -900))
+            .unwrap();
+
+        let days = energy.report(EnergyPeriod::Day);
+        assert_eq!(
+            days.iter().map(|r| r.label.as_str()).collect::<Vec<_>>(),
+            ["2026-10-17", "2026-10-18", "2026-10-19"]
+        );
+        // the gap from the previous day is booked on the next one
+        assert_eq!(days[1].totals.gaps, 23.0 * 3600.0);
+
+        let weeks = energy.report(EnergyPeriod::Week);
+        assert_eq!(weeks.len(), 2);
+        assert_eq!(weeks[0].label, "2026-W42");
+        assert_eq!(weeks[0].totals.charged_wh, 200.0);
+        assert_eq!(weeks[1].label, "2026-W43");
+        assert_eq!(weeks[1].totals.charged_wh, 100.0);
+        assert_eq!(weeks[1].totals.discharged_wh, 90.0);
+        assert_eq!(weeks[1].efficiency, Some(0.9));
+        assert_eq!(
+            weeks[1].to_string(),
+            "2026-W43    in    0.100 kWh  out    0.090 kWh  efficiency  90.0 %  \
+cycles   0.09  gaps 25h00m"
+        );
+
+        let months = energy.report(EnergyPeriod::Month);
+        assert_eq!(months.len(), 1);
+        assert_eq!(months[0].label, "2026-10");
+        assert_eq!(months[0].totals.charged_wh, 300.0);
+    }
+
+    #[test]
+    fn test_persistence() {
+        let path = std::env::temp_dir().join(format!("aces-energy-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config = EnergyConfig {
+            state: Some(path.clone()),
+            max_gap: 60,
+        };
+
+        let mut energy = EnergyAccumulator::from_config(&config).unwrap();
+        for seconds in [0, 30, 60] {
+            energy
+                .update(&snapshot(at(10, seconds), 1300, 1000))
+                .unwrap();
+        }
+
+        // a restart continues from the last poll written, once a minute
+        let mut energy = EnergyAccumulator::from_config(&config).unwrap();
+        energy.update(&snapshot(at(10, 108), 1300, 1000)).unwrap();
+        assert!((energy.total().totals.charged_wh - 3.9).abs() < 1e-9);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    /// Seconds after 10:00 on a day of October 2026.
+    fn at(day: u32, seconds: i64) -> DateTime<FixedOffset> {
+        test_time((day as i64 - 18) * 24 * 3600 + seconds)
+    }
+
+    fn snapshot(timestamp: DateTime<FixedOffset>, total_voltage: i16, current: i16) -> Snapshot {
+        SnapshotBuilder::at(0)
+            .timestamp(timestamp)
+            .cells(&[3300; 4])
+            .detail(BatteryDetail {
+                total_voltage,
+                current,
+                standard_capacity: 10000,
+                cycles: 9,
+                ..Default::default()
+            })
+            .build()
+    }
+
+    use super::*;
+    use crate::snapshot::{test_time, SnapshotBuilder};
+    use crate::BatteryDetail;
+}
+
+use crate::util::StateFile;
+use crate::{EnergyConfig, Event, Register, Result, Sink, Snapshot};
+use chrono::{DateTime, Datelike as _, FixedOffset, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
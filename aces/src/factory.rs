@@ -0,0 +1,498 @@
+/// The register entering factory mode, with `FACTORY_KEY`.
+const FACTORY_ENTER: u8 = 0x00;
+const FACTORY_KEY: [u8; 2] = [0x56, 0x78];
+/// The register leaving factory mode, with `FACTORY_SAVE` to keep the changes.
+const FACTORY_EXIT: u8 = 0x01;
+const FACTORY_SAVE: [u8; 2] = [0x28, 0x28];
+const FACTORY_DISCARD: [u8; 2] = [0x00, 0x00];
+
+#[derive(Eq, PartialEq, Debug, thiserror::Error)]
+pub enum FactoryError {
+    #[error("No reply from register {0:02x}")]
+    NoReply(u8),
+    #[error("Invalid reply from register {register:02x}: {error}")]
+    InvalidReply { register: u8, error: ParseError },
+    #[error("Reply from register {got:02x} instead of {expected:02x}")]
+    WrongRegister { expected: u8, got: u8 },
+    #[error("Register {register:02x} answered with status {status:02x}")]
+    Status { register: u8, status: u8 },
+    #[error("Registers {0:02x?} read back different values than written")]
+    NotVerified(Vec<u8>),
+}
+
+/// Access to the configuration registers of the BMS.
+///
+/// The BMS only answers configuration registers in factory mode, which
+/// `enter` switches to and `exit` leaves again. Leave it before dropping the
+/// session, otherwise the BMS stays in factory mode.
+///
+/// Every frame written goes through `SafeWriter` and is appended to its audit
+/// log. In dry run mode a session only reads: writing a register or leaving
+/// with `save` is refused.
+pub struct FactorySession<'s, T, C>
+where
+    T: Transport,
+    C: Clock,
+{
+    supervisor: &'s mut ConnectionSupervisor<T, C>,
+    writer: &'s SafeWriter,
+}
+
+impl<'s, T, C> FactorySession<'s, T, C>
+where
+    T: Transport,
+    C: Clock,
+{
+    /// Enters factory mode, waiting at most the timeout of `writer` for
+    /// each reply.
+    pub async fn enter(
+        supervisor: &'s mut ConnectionSupervisor<T, C>,
+        writer: &'s SafeWriter,
+    ) -> Result<Self> {
+        writer.check_audit_log()?;
+        let mut session = FactorySession { supervisor, writer };
+        log::info!("entering factory mode");
+        session
+            .audited(Frame::write(FACTORY_ENTER, FACTORY_KEY.to_vec()))
+            .await?;
+        Ok(session)
+    }
+
+    /// Leaves factory mode, keeping the written registers when `save` is set.
+    /// In dry run mode the registers are discarded and `save` is an error.
+    pub async fn exit(mut self, save: bool) -> Result<()> {
+        log::info!("leaving factory mode");
+        let dry_run = self.writer.is_dry_run();
+        let payload = if save && !dry_run {
+            FACTORY_SAVE
+        } else {
+            FACTORY_DISCARD
+        };
+        self.audited(Frame::write(FACTORY_EXIT, payload.to_vec()))
+            .await?;
+        if save && dry_run {
+            return Err(SafeWriteError::DryRun.into());
+        }
+        Ok(())
+    }
+
+    /// Reads the payload of a register.
+    pub async fn read(&mut self, register: u8) -> Result<Vec<u8>> {
+        self.exchange(Frame::read(register)).await
+    }
+
+    /// Writes the payload of a register, kept only when leaving factory mode
+    /// with `save`. Writes go through `SafeWriter`.
+    pub(crate) async fn write(&mut self, register: u8, payload: Vec<u8>) -> Result<()> {
+        if self.writer.is_dry_run() {
+            return Err(SafeWriteError::DryRun.into());
+        }
+        log::info!("writing register {:02x}", register);
+        self.audited(Frame::write(register, payload)).await?;
+        Ok(())
+    }
+
+    /// Reads every register of `BatteryConfig::REGISTERS`.
+    pub async fn read_config(&mut self) -> Result<BatteryConfig> {
+        let mut config = BatteryConfig::default();
+        for register in BatteryConfig::REGISTERS {
+            let payload = self.read(register.address).await?;
+            config.decode(register.address, &payload).map_err(|error| {
+                FactoryError::InvalidReply {
+                    register: register.address,
+                    error,
+                }
+            })?;
+        }
+        Ok(config)
+    }
+
+    /// Sends a write frame and appends it to the audit log.
+    async fn audited(&mut self, request: Frame) -> Result<Vec<u8>> {
+        let frame = request.bytes();
+        let reply = self.exchange(request).await;
+        let outcome = match &reply {
+            Ok(_) => "sent".to_string(),
+            Err(err) => err.to_string(),
+        };
+        self.writer.audit_frame(&frame, &outcome)?;
+        reply
+    }
+
+    /// Sends a frame, returning the payload of the reply.
+    async fn exchange(&mut self, request: Frame) -> Result<Vec<u8>> {
+        let register = request.register;
+        let reply = self
+            .supervisor
+            .exchange(&request.bytes(), self.writer.timeout())
+            .await?
+            .concat();
+        if reply.is_empty() {
+            return Err(FactoryError::NoReply(register).into());
+        }
+
+        let invalid = |error| FactoryError::InvalidReply { register, error };
+        let frame = Frame::parse(&reply).map_err(invalid)?;
+        if !frame.is_checksum_valid() {
+            return Err(invalid(ParseError::InvalidChecksum).into());
+        }
+        if frame.register != register {
+            return Err(FactoryError::WrongRegister {
+                expected: register,
+                got: frame.register,
+            }
+            .into());
+        }
+        if frame.status != 0x00 {
+            return Err(FactoryError::Status {
+                register,
+                status: frame.status,
+            }
+            .into());
+        }
+        Ok(frame.payload)
+    }
+}
+
+/// Reads the configuration in a factory mode session, leaving factory mode
+/// even when reading fails. Nothing is saved, so this also works in dry run
+/// mode.
+pub async fn read_battery_config<T, C>(
+    supervisor: &mut ConnectionSupervisor<T, C>,
+    writer: &SafeWriter,
+) -> Result<BatteryConfig>
+where
+    T: Transport,
+    C: Clock,
+{
+    let mut session = FactorySession::enter(supervisor, writer).await?;
+    let config = session.read_config().await;
+    let exited = session.exit(false).await;
+    match config {
+        Ok(config) => exited.map(|_| config),
+        Err(err) => {
+            if let Err(exit) = exited {
+                log::error!("failed to leave factory mode: {}", exit);
+            }
+            Err(err)
+        }
+    }
+}
+
+/// Writes the changes in one factory mode session and saves them, then reads
+/// the configuration back in a second session to verify them.
+///
+/// When a write fails, factory mode is left without saving, so the battery
+/// keeps its previous configuration. Returns the configuration read back.
+/// Sends the frames of `write_frames`, through `SafeWriter`.
+pub(crate) async fn write_battery_config<T, C>(
+    supervisor: &mut ConnectionSupervisor<T, C>,
+    changes: &[ConfigChange],
+    writer: &SafeWriter,
+) -> Result<BatteryConfig>
+where
+    T: Transport,
+    C: Clock,
+{
+    let mut session = FactorySession::enter(supervisor, writer).await?;
+    for change in changes {
+        let written = session
+            .write(change.register.address, change.payload.clone())
+            .await;
+        if let Err(err) = written {
+            if let Err(exit) = session.exit(false).await {
+                log::error!("failed to leave factory mode: {}", exit);
+            }
+            return Err(err);
+        }
+    }
+    session.exit(true).await?;
+
+    let config = read_battery_config(supervisor, writer).await?;
+    let different: Vec<u8> = changes
+        .iter()
+        .filter(|change| config.encode(change.register.address).as_ref() != Ok(&change.payload))
+        .map(|change| change.register.address)
+        .collect();
+    if !different.is_empty() {
+        return Err(FactoryError::NotVerified(different).into());
+    }
+    Ok(config)
+}
+
+/// The frames `write_battery_config` sends before reading back.
+pub(crate) fn write_frames(changes: &[ConfigChange]) -> Vec<Frame> {
+    let mut frames = vec![Frame::write(FACTORY_ENTER, FACTORY_KEY.to_vec())];
+    for change in changes {
+        frames.push(Frame::write(
+            change.register.address,
+            change.payload.clone(),
+        ));
+    }
+    frames.push(Frame::write(FACTORY_EXIT, FACTORY_SAVE.to_vec()));
+    frames
+}
+
+/// A battery with configuration registers, for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct MockEeprom {
+    pub registers: std::collections::BTreeMap<u8, Vec<u8>>,
+    pub factory_mode: bool,
+    /// Every frame written.
+    pub written: Vec<Vec<u8>>,
+    /// Set by leaving factory mode with `FACTORY_SAVE`.
+    pub saved: bool,
+    pending: std::rc::Rc<std::cell::RefCell<std::collections::VecDeque<Vec<u8>>>>,
+}
+
+#[cfg(test)]
+pub(crate) struct MockEepromReceiver(
+    std::rc::Rc<std::cell::RefCell<std::collections::VecDeque<Vec<u8>>>>,
+);
+
+#[cfg(test)]
+impl MockEeprom {
+    /// A battery holding `config`.
+    pub fn new(config: &BatteryConfig) -> Self {
+        let registers = BatteryConfig::REGISTERS
+            .iter()
+            .map(|r| (r.address, config.encode(r.address).unwrap()))
+            .collect();
+        MockEeprom {
+            registers,
+            ..Default::default()
+        }
+    }
+
+    pub fn config(&self) -> BatteryConfig {
+        let mut config = BatteryConfig::default();
+        for (register, payload) in &self.registers {
+            config.decode(*register, payload).unwrap();
+        }
+        config
+    }
+
+    fn reply(&mut self, request: &Frame) -> Frame {
+        let (status, payload) = match (request.is_write(), request.register) {
+            (true, FACTORY_ENTER) if request.payload == FACTORY_KEY => {
+                self.factory_mode = true;
+                (0x00, Vec::new())
+            }
+            (true, FACTORY_EXIT) => {
+                self.factory_mode = false;
+                self.saved |= request.payload == FACTORY_SAVE;
+                (0x00, Vec::new())
+            }
+            (_, register) if !self.factory_mode || !self.registers.contains_key(&register) => {
+                (0x80, Vec::new())
+            }
+            (true, register) => {
+                self.registers.insert(register, request.payload.clone());
+                (0x00, Vec::new())
+            }
+            (false, register) => (0x00, self.registers[&register].clone()),
+        };
+        Frame {
+            direction: crate::Direction::Notify,
+            register: request.register,
+            status,
+            checksum: crate::calculate_checksum(&payload, status),
+            payload,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Transport for MockEeprom {
+    type Receiver = MockEepromReceiver;
+
+    async fn connect(&mut self) -> Result<MockEepromReceiver> {
+        Ok(MockEepromReceiver(self.pending.clone()))
+    }
+
+    async fn is_connected(&mut self) -> bool {
+        true
+    }
+
+    async fn write(&mut self, value: &[u8]) -> Result<()> {
+        self.written.push(value.to_vec());
+        if let Ok(request) = Frame::parse(value) {
+            let reply = self.reply(&request).bytes();
+            self.pending.borrow_mut().push_back(reply);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl crate::NotificationsReceiver for MockEepromReceiver {
+    fn next(&mut self) -> Vec<u8> {
+        self.0.borrow_mut().pop_front().unwrap()
+    }
+
+    fn next_timeout(&mut self, _timeout: Duration) -> Option<Vec<u8>> {
+        self.0.borrow_mut().pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_read_battery_config() {
+        let config = BatteryConfig {
+            cell_overvoltage: 3650,
+            cell_count: 4,
+            charge_over_temp: Temperature(550),
+            model: "AL12V100HFA0191".to_string(),
+            ..Default::default()
+        };
+        let mut supervisor = supervisor(MockEeprom::new(&config));
+
+        let read = block_on(read_battery_config(&mut supervisor, &writer())).unwrap();
+        assert_eq!(read, config);
+
+        let battery = supervisor.transport();
+        assert_eq!(battery.config(), config);
+        assert!(!battery.factory_mode);
+        assert!(!battery.saved);
+        // clear, enter, every register, exit
+        assert_eq!(battery.written.len(), 3 + BatteryConfig::REGISTERS.len());
+        assert_eq!(battery.written[1], from_hex("dd5a00025678ff3077").unwrap());
+        assert_eq!(
+            battery.written.last().unwrap(),
+            &from_hex("dd5a01020000fffd77").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_read_battery_config_exits_on_error() {
+        let mut battery = MockEeprom::new(&BatteryConfig::default());
+        battery.registers.remove(&0x2f);
+        let mut supervisor = supervisor(battery);
+
+        let err = block_on(read_battery_config(&mut supervisor, &writer())).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            FactoryError::Status {
+                register: 0x2f,
+                status: 0x80
+            }
+            .to_string()
+        );
+        assert!(!supervisor.transport().factory_mode);
+    }
+
+    #[test]
+    fn test_write_battery_config() {
+        let live = BatteryConfig {
+            cell_overvoltage: 3650,
+            cell_count: 4,
+            model: "AL12V100HFA0191".to_string(),
+            ..Default::default()
+        };
+        let target = BatteryConfig {
+            cell_overvoltage: 3600,
+            model: "AL12V100HFA0192".to_string(),
+            ..live.clone()
+        };
+        let changes = live.diff(&target).unwrap();
+        let mut supervisor = supervisor(MockEeprom::new(&live));
+
+        let read = block_on(write_battery_config(&mut supervisor, &changes, &writer())).unwrap();
+        assert_eq!(read, target);
+
+        let battery = supervisor.transport();
+        assert_eq!(battery.config(), target);
+        assert!(battery.saved);
+        // clear, enter, 2 changes, exit, then enter, every register, exit
+        assert_eq!(
+            battery.written.len(),
+            5 + 2 + BatteryConfig::REGISTERS.len()
+        );
+        assert_eq!(battery.written[2], from_hex("dd5a24020e10ffbc77").unwrap());
+        assert_eq!(battery.written[4], from_hex("dd5a01022828ffad77").unwrap());
+    }
+
+    #[test]
+    fn test_write_battery_config_discards_on_error() {
+        let live = BatteryConfig::default();
+        let target = BatteryConfig {
+            cell_overvoltage: 3600,
+            cell_count: 4,
+            ..live.clone()
+        };
+        let changes = live.diff(&target).unwrap();
+        let mut battery = MockEeprom::new(&live);
+        battery.registers.remove(&0x2f);
+        let mut supervisor = supervisor(battery);
+
+        let err = block_on(write_battery_config(&mut supervisor, &changes, &writer())).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            FactoryError::Status {
+                register: 0x2f,
+                status: 0x80
+            }
+            .to_string()
+        );
+        let battery = supervisor.transport();
+        assert!(!battery.factory_mode);
+        assert!(!battery.saved);
+    }
+
+    #[test]
+    fn test_factory_session_goes_through_writer() {
+        let mut supervisor = supervisor(MockEeprom::new(&BatteryConfig::default()));
+
+        let unwritable = writer().audit_log(std::env::temp_dir().join("aces-missing/audit.jsonl"));
+        assert!(block_on(read_battery_config(&mut supervisor, &unwritable)).is_err());
+        assert!(supervisor.transport().written.is_empty());
+
+        let path =
+            std::env::temp_dir().join(format!("aces-audit-factory-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        // reading works in dry run, saving does not
+        let dry_run = writer().dry_run(true).audit_log(&path);
+        block_on(read_battery_config(&mut supervisor, &dry_run)).unwrap();
+        let session = block_on(FactorySession::enter(&mut supervisor, &dry_run)).unwrap();
+        let err = block_on(session.exit(true)).unwrap_err();
+        assert_eq!(err.to_string(), SafeWriteError::DryRun.to_string());
+        assert!(!supervisor.transport().factory_mode);
+        assert!(!supervisor.transport().saved);
+
+        let records = crate::AuditRecord::load(&path).unwrap();
+        let enter = ("dd5a00025678ff3077", "sent");
+        let discard = ("dd5a01020000fffd77", "sent");
+        assert_eq!(
+            records
+                .iter()
+                .map(|r| (r.frame.as_str(), r.outcome.as_str()))
+                .collect::<Vec<_>>(),
+            [enter, discard, enter, discard]
+        );
+    }
+
+    fn writer() -> SafeWriter {
+        SafeWriter::new(Duration::from_secs(1))
+    }
+
+    fn supervisor(battery: MockEeprom) -> ConnectionSupervisor<MockEeprom, ManualClock> {
+        ConnectionSupervisor::new(
+            battery,
+            ManualClock::default(),
+            &ConnectionConfig::default(),
+        )
+    }
+
+    use super::*;
+    use crate::util::{block_on, from_hex};
+    use crate::{ConnectionConfig, ManualClock, Temperature};
+}
+
+use crate::{
+    BatteryConfig, Clock, ConfigChange, ConnectionSupervisor, Frame, ParseError, Result,
+    SafeWriteError, SafeWriter, Transport,
+};
+#[cfg(test)]
+use std::time::Duration;
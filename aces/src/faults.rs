@@ -0,0 +1,454 @@
+/// A way for the mock battery to misbehave.
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Fault {
+    /// Split the frame into up to `max_chunks` notifications, at random boundaries.
+    Split {
+        #[serde(default = "default_max_chunks")]
+        max_chunks: usize,
+    },
+    /// Corrupt the checksum.
+    BadChecksum,
+    /// Leave out the `0x77` end byte.
+    MissingEnd,
+    /// Send the frame twice.
+    Duplicate,
+    /// Don't send the frame at all.
+    Drop,
+    /// Wait before sending the frame (ms).
+    Delay { delay: u64 },
+    /// Send an earlier frame without being asked.
+    Unsolicited,
+    /// Reply with an error status and no payload.
+    StatusError,
+}
+
+/// A fault, and how often it happens.
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+pub struct FaultRule {
+    #[serde(flatten)]
+    pub fault: Fault,
+    /// The chance the fault happens for each frame (0-1).
+    #[serde(default = "default_probability")]
+    pub probability: f32,
+}
+
+/// Selects the fault scenario the mock battery runs.
+///
+/// ```toml
+/// [faults]
+/// scenario = "flaky"
+///
+/// [[faults.scenarios.flaky]]
+/// type = "split"
+/// probability = 0.5
+///
+/// [[faults.scenarios.flaky]]
+/// type = "delay"
+/// delay = 6000
+/// probability = 0.1
+/// ```
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaultConfig {
+    /// A built-in scenario (see `FaultConfig::builtin`) or one from `scenarios`.
+    pub scenario: String,
+    /// A fixed seed, for reproducible faults.
+    pub seed: Option<u64>,
+    pub scenarios: HashMap<String, Vec<FaultRule>>,
+}
+
+#[derive(Eq, PartialEq, Debug, thiserror::Error)]
+#[error("Unknown fault scenario {0}")]
+pub struct UnknownScenario(pub String);
+
+/// A notification to send, after waiting for `delay`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Notification {
+    pub delay: Duration,
+    pub value: Vec<u8>,
+}
+
+/// Applies faults to the notifications of a `Responder`.
+pub struct FaultInjector {
+    rules: Vec<FaultRule>,
+    /// The last frame sent for each register, for unsolicited frames.
+    sent: Vec<Vec<u8>>,
+    rng: Xorshift,
+}
+
+impl FaultConfig {
+    /// The scenarios available without configuration.
+    pub const BUILTIN: [&'static str; 8] = [
+        "none", "split", "corrupt", "lossy", "slow", "chatty", "errors", "chaos",
+    ];
+
+    /// The rules of a built-in scenario.
+    pub fn builtin(name: &str) -> Option<Vec<FaultRule>> {
+        let rule = |fault, probability| FaultRule { fault, probability };
+        let split = Fault::Split {
+            max_chunks: default_max_chunks(),
+        };
+        let delay = Fault::Delay { delay: 6_000 };
+
+        let rules = match name {
+            "none" => vec![],
+            "split" => vec![rule(split, 1.0)],
+            "corrupt" => vec![rule(Fault::BadChecksum, 0.2), rule(Fault::MissingEnd, 0.1)],
+            "lossy" => vec![rule(Fault::Drop, 0.2), rule(Fault::Duplicate, 0.2)],
+            "slow" => vec![rule(delay, 0.3)],
+            "chatty" => vec![rule(Fault::Unsolicited, 0.5)],
+            "errors" => vec![rule(Fault::StatusError, 0.3)],
+            "chaos" => vec![
+                rule(split, 0.5),
+                rule(Fault::BadChecksum, 0.05),
+                rule(Fault::MissingEnd, 0.05),
+                rule(Fault::Drop, 0.05),
+                rule(Fault::Duplicate, 0.05),
+                rule(delay, 0.05),
+                rule(Fault::Unsolicited, 0.1),
+                rule(Fault::StatusError, 0.05),
+            ],
+            _ => return None,
+        };
+        Some(rules)
+    }
+
+    /// The rules of the selected scenario, configured scenarios take precedence.
+    pub fn rules(&self) -> std::result::Result<Vec<FaultRule>, UnknownScenario> {
+        if let Some(rules) = self.scenarios.get(&self.scenario) {
+            return Ok(rules.clone());
+        }
+        Self::builtin(&self.scenario).ok_or_else(|| UnknownScenario(self.scenario.clone()))
+    }
+}
+
+impl Default for FaultConfig {
+    fn default() -> Self {
+        FaultConfig {
+            scenario: "none".to_string(),
+            seed: None,
+            scenarios: HashMap::new(),
+        }
+    }
+}
+
+impl Notification {
+    /// A notification sent without waiting.
+    pub fn now(value: Vec<u8>) -> Self {
+        Notification {
+            delay: Duration::ZERO,
+            value,
+        }
+    }
+}
+
+impl FaultInjector {
+    pub fn new(rules: Vec<FaultRule>) -> Self {
+        FaultInjector {
+            rules,
+            sent: Vec::new(),
+            rng: Xorshift::from_time(),
+        }
+    }
+
+    pub fn from_config(config: &FaultConfig) -> std::result::Result<Self, UnknownScenario> {
+        let injector = Self::new(config.rules()?);
+        Ok(match config.seed {
+            Some(seed) => injector.with_seed(seed),
+            None => injector,
+        })
+    }
+
+    /// Uses a fixed seed, for reproducible faults.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Xorshift::new(seed);
+        self
+    }
+
+    /// Applies the faults to the notifications returned by a `Responder`.
+    ///
+    /// Empty notifications (clearing the previous response) are passed
+    /// through. Unsolicited frames may be added even without notifications.
+    pub fn apply(&mut self, notifications: Vec<Vec<u8>>) -> Vec<Notification> {
+        let mut result = Vec::new();
+
+        for mut frame in notifications {
+            if frame.len() < 7 {
+                result.push(Notification::now(frame));
+                continue;
+            }
+            self.remember(&frame);
+
+            let fired = self.fired(|fault| *fault != Fault::Unsolicited);
+            let has = |f: fn(&Fault) -> bool| fired.iter().any(f);
+
+            if has(|f| *f == Fault::StatusError) {
+                frame = status_error(&frame);
+            }
+            if has(|f| *f == Fault::BadChecksum) {
+                let idx = frame.len() - 2;
+                frame[idx] ^= 0xff;
+            }
+            if has(|f| *f == Fault::MissingEnd) {
+                frame.pop();
+            }
+            if has(|f| *f == Fault::Drop) {
+                continue;
+            }
+
+            let delay = fired
+                .iter()
+                .map(|fault| match fault {
+                    Fault::Delay { delay } => Duration::from_millis(*delay),
+                    _ => Duration::ZERO,
+                })
+                .sum();
+            let max_chunks = fired
+                .iter()
+                .filter_map(|fault| match fault {
+                    Fault::Split { max_chunks } => Some(*max_chunks),
+                    _ => None,
+                })
+                .max()
+                .unwrap_or(1);
+            let copies = if has(|f| *f == Fault::Duplicate) {
+                2
+            } else {
+                1
+            };
+
+            for copy in 0..copies {
+                let chunks = self.split(&frame, max_chunks);
+                for (idx, chunk) in chunks.into_iter().enumerate() {
+                    result.push(Notification {
+                        delay: if copy == 0 && idx == 0 {
+                            delay
+                        } else {
+                            Duration::ZERO
+                        },
+                        value: chunk,
+                    });
+                }
+            }
+        }
+
+        if !self.sent.is_empty() && !self.fired(|fault| *fault == Fault::Unsolicited).is_empty() {
+            let idx = (self.rng.next_u64() % self.sent.len() as u64) as usize;
+            result.push(Notification::now(self.sent[idx].clone()));
+        }
+
+        result
+    }
+
+    /// Rolls every rule matching `filter`, returns the faults that happen.
+    fn fired<F>(&mut self, filter: F) -> Vec<Fault>
+    where
+        F: Fn(&Fault) -> bool,
+    {
+        let mut fired = Vec::new();
+        for idx in 0..self.rules.len() {
+            if !filter(&self.rules[idx].fault) {
+                continue;
+            }
+            let roll = (self.rng.next_u64() % 1_000) as f32 / 1_000.0;
+            if roll < self.rules[idx].probability {
+                fired.push(self.rules[idx].fault.clone());
+            }
+        }
+        fired
+    }
+
+    fn remember(&mut self, frame: &[u8]) {
+        self.sent.retain(|sent| sent[..2] != frame[..2]);
+        self.sent.push(frame.to_vec());
+    }
+
+    /// Splits `frame` into up to `max_chunks` non-empty chunks.
+    fn split(&mut self, frame: &[u8], max_chunks: usize) -> Vec<Vec<u8>> {
+        let max_chunks = max_chunks.clamp(1, frame.len().max(1));
+        let chunks = 1 + (self.rng.next_u64() % max_chunks as u64) as usize;
+
+        let mut bounds = Vec::new();
+        while bounds.len() < chunks - 1 {
+            let bound = 1 + (self.rng.next_u64() % (frame.len() as u64 - 1)) as usize;
+            if !bounds.contains(&bound) {
+                bounds.push(bound);
+            }
+        }
+        bounds.sort();
+
+        let mut start = 0;
+        let mut result = Vec::new();
+        for bound in bounds.into_iter().chain([frame.len()]) {
+            result.push(frame[start..bound].to_vec());
+            start = bound;
+        }
+        result
+    }
+}
+
+/// The error reply for the register of `frame`.
+fn status_error(frame: &[u8]) -> Vec<u8> {
+    let checksum = calculate_checksum(&[], STATUS_ERROR);
+    vec![
+        frame[0],
+        frame[1],
+        STATUS_ERROR,
+        0x00,
+        (checksum >> 8) as u8,
+        checksum as u8,
+        0x77,
+    ]
+}
+
+fn default_max_chunks() -> usize {
+    4
+}
+
+fn default_probability() -> f32 {
+    1.0
+}
+
+/// The status byte of a failed request.
+const STATUS_ERROR: u8 = 0x80;
+
+#[cfg(test)]
+mod tests {
+    fn rule(fault: Fault) -> FaultRule {
+        FaultRule {
+            fault,
+            probability: 1.0,
+        }
+    }
+
+    fn frames() -> Vec<Vec<u8>> {
+        vec![
+            Vec::new(),
+            StaticResponses.response(&Request::BatteryVoltage),
+        ]
+    }
+
+    fn values(notifications: &[Notification]) -> Vec<Vec<u8>> {
+        notifications.iter().map(|n| n.value.clone()).collect()
+    }
+
+    #[test]
+    fn test_no_faults() {
+        let mut injector = FaultInjector::new(FaultConfig::default().rules().unwrap());
+        let notifications = injector.apply(frames());
+        assert_eq!(values(&notifications), frames());
+        assert!(notifications.iter().all(|n| n.delay == Duration::ZERO));
+    }
+
+    #[test]
+    fn test_corrupt_frames() {
+        let frame = &frames()[1];
+
+        let mut injector = FaultInjector::new(vec![rule(Fault::BadChecksum)]);
+        let value = &injector.apply(frames())[1].value;
+        assert_eq!(value.len(), frame.len());
+        assert_eq!(
+            Response::parse_response(value),
+            Err(ParseError::InvalidChecksum)
+        );
+
+        let mut injector = FaultInjector::new(vec![rule(Fault::MissingEnd)]);
+        assert_eq!(injector.apply(frames())[1].value, frame[..frame.len() - 1]);
+
+        let mut injector = FaultInjector::new(vec![rule(Fault::StatusError)]);
+        let value = &injector.apply(frames())[1].value;
+        assert_eq!(value[..4], [0xdd, 0x04, 0x80, 0x00]);
+        assert!(verify_checksum(u16_from_bytes(&value[4..6]), &[], value[2]));
+    }
+
+    #[test]
+    fn test_split_duplicate_drop_delay() {
+        let frame = frames()[1].clone();
+
+        let mut injector =
+            FaultInjector::new(vec![rule(Fault::Split { max_chunks: 5 })]).with_seed(7);
+        for _ in 0..20 {
+            let notifications = injector.apply(frames());
+            assert!(notifications.len() <= 6);
+            assert!(notifications[1..].iter().all(|n| !n.value.is_empty()));
+            assert_eq!(values(&notifications)[1..].concat(), frame);
+        }
+
+        let mut injector = FaultInjector::new(vec![
+            rule(Fault::Duplicate),
+            rule(Fault::Delay { delay: 100 }),
+        ]);
+        let notifications = injector.apply(frames());
+        assert_eq!(
+            values(&notifications),
+            vec![Vec::new(), frame.clone(), frame.clone()]
+        );
+        assert_eq!(notifications[1].delay, Duration::from_millis(100));
+        assert_eq!(notifications[2].delay, Duration::ZERO);
+
+        let mut injector = FaultInjector::new(vec![rule(Fault::Drop)]);
+        assert_eq!(values(&injector.apply(frames())), vec![Vec::<u8>::new()]);
+    }
+
+    #[test]
+    fn test_unsolicited() {
+        let mut injector = FaultInjector::new(vec![rule(Fault::Unsolicited)]);
+        assert_eq!(injector.apply(Vec::new()), vec![]);
+
+        let frame = frames()[1].clone();
+        assert_eq!(
+            values(&injector.apply(frames())),
+            vec![Vec::new(), frame.clone(), frame.clone()]
+        );
+        assert_eq!(values(&injector.apply(Vec::new())), vec![frame]);
+    }
+
+    #[test]
+    fn test_config() {
+        let config: FaultConfig = toml::from_str(
+            r#"
+            scenario = "flaky"
+            seed = 1
+
+            [[scenarios.flaky]]
+            type = "split"
+            probability = 0.5
+
+            [[scenarios.flaky]]
+            type = "delay"
+            delay = 6000
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.rules().unwrap(),
+            vec![
+                FaultRule {
+                    fault: Fault::Split { max_chunks: 4 },
+                    probability: 0.5
+                },
+                rule(Fault::Delay { delay: 6000 }),
+            ]
+        );
+
+        for name in FaultConfig::BUILTIN {
+            assert!(FaultConfig::builtin(name).is_some());
+        }
+        let config = FaultConfig {
+            scenario: "nope".to_string(),
+            ..Default::default()
+        };
+        assert!(FaultInjector::from_config(&config).is_err());
+    }
+
+    use super::*;
+    use crate::{
+        util::u16_from_bytes, verify_checksum, ParseError, Request, Response, ResponseSource,
+        StaticResponses,
+    };
+}
+
+use crate::{calculate_checksum, util::Xorshift};
+use serde::Deserialize;
+use std::{collections::HashMap, time::Duration};
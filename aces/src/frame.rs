@@ -0,0 +1,217 @@
+/// A frame of the protocol, without interpreting its payload.
+///
+/// Requests and responses share the layout
+/// `dd <a> <b> <len> <payload> <checksum> 77`: requests carry the command and
+/// the register, responses the register and the status. The checksum covers
+/// `<b>`, the length and the payload (see `calculate_checksum`).
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Frame {
+    pub direction: Direction,
+    pub register: u8,
+    /// The command of a request (`READ` or `WRITE`), the status of a
+    /// response (`0x00` when ok).
+    pub status: u8,
+    pub payload: Vec<u8>,
+    pub checksum: u16,
+}
+
+/// Reassembles frames from chunks of a byte stream.
+///
+/// Bytes before the start of a frame (e.g. the `Clear` request) are skipped.
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+pub struct FrameAssembler {
+    buffer: Vec<u8>,
+}
+
+impl Frame {
+    pub const START: u8 = 0xdd;
+    pub const END: u8 = 0x77;
+    /// The command of a request reading a register.
+    pub const READ: u8 = 0xa5;
+    /// The command of a request writing a register.
+    pub const WRITE: u8 = 0x5a;
+
+    /// A request reading `register`.
+    pub fn read(register: u8) -> Self {
+        Self::request(Self::READ, register, Vec::new())
+    }
+
+    /// A request writing `payload` to `register`.
+    pub fn write(register: u8, payload: Vec<u8>) -> Self {
+        Self::request(Self::WRITE, register, payload)
+    }
+
+    fn request(command: u8, register: u8, payload: Vec<u8>) -> Self {
+        Frame {
+            direction: Direction::Write,
+            register,
+            status: command,
+            checksum: calculate_checksum(&payload, register),
+            payload,
+        }
+    }
+
+    /// The length of the frame at the start of `bytes`, once its header is
+    /// complete.
+    pub fn length(bytes: &[u8]) -> Option<usize> {
+        bytes.get(3).map(|len| 7 + *len as usize)
+    }
+
+    /// Parses a single frame, without verifying the checksum.
+    pub fn parse(bytes: &[u8]) -> ParseResult<Self> {
+        let length = Self::length(bytes).ok_or(ParseError::NotEnoughData)?;
+        if bytes.len() < length {
+            return Err(ParseError::NotEnoughData);
+        }
+        if bytes.len() > length || bytes[0] != Self::START || bytes[length - 1] != Self::END {
+            return Err(ParseError::InvalidData);
+        }
+
+        let (direction, register, status) = match bytes[1] {
+            Self::READ | Self::WRITE => (Direction::Write, bytes[2], bytes[1]),
+            _ => (Direction::Notify, bytes[1], bytes[2]),
+        };
+        Ok(Frame {
+            direction,
+            register,
+            status,
+            payload: bytes[4..length - 3].to_vec(),
+            checksum: u16_from_bytes(&bytes[length - 3..length - 1]),
+        })
+    }
+
+    /// Whether the frame is a request writing a register.
+    pub fn is_write(&self) -> bool {
+        self.direction == Direction::Write && self.status == Self::WRITE
+    }
+
+    /// The checksum the frame should carry.
+    pub fn expected_checksum(&self) -> u16 {
+        let control = match self.direction {
+            Direction::Write => self.register,
+            Direction::Notify => self.status,
+        };
+        calculate_checksum(&self.payload, control)
+    }
+
+    pub fn is_checksum_valid(&self) -> bool {
+        self.checksum == self.expected_checksum()
+    }
+
+    /// Encodes the frame, with the checksum it carries.
+    pub fn bytes(&self) -> Vec<u8> {
+        let (a, b) = match self.direction {
+            Direction::Write => (self.status, self.register),
+            Direction::Notify => (self.register, self.status),
+        };
+        let mut frame = vec![Self::START, a, b, self.payload.len() as u8];
+        frame.extend_from_slice(&self.payload);
+        frame.extend_from_slice(&self.checksum.to_be_bytes());
+        frame.push(Self::END);
+        frame
+    }
+}
+
+impl FrameAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a chunk, returning the frames it completed.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<Vec<u8>> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut frames = Vec::new();
+        loop {
+            let start = self
+                .buffer
+                .iter()
+                .position(|b| *b == Frame::START)
+                .unwrap_or(self.buffer.len());
+            if start > 0 {
+                log::debug!("skipping {}", to_hex(&self.buffer[..start]));
+                self.buffer.drain(..start);
+            }
+
+            match Frame::length(&self.buffer) {
+                Some(length) if self.buffer.len() >= length => {
+                    frames.push(self.buffer.drain(..length).collect());
+                }
+                _ => return frames,
+            }
+        }
+    }
+
+    /// The bytes of the incomplete frame.
+    pub fn pending(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_parse() {
+        let frame = Frame::parse(Request::BatteryProtect.bytes()).unwrap();
+        assert_eq!(
+            frame,
+            Frame {
+                direction: Direction::Write,
+                register: 0xaa,
+                status: Frame::READ,
+                payload: vec![],
+                checksum: 0xff56,
+            }
+        );
+        assert!(frame.is_checksum_valid());
+        assert!(!frame.is_write());
+        assert_eq!(frame.bytes(), Request::BatteryProtect.bytes());
+
+        let bytes = from_hex("dd040008 0de20ddc0dec0ded cafe 77").unwrap();
+        let frame = Frame::parse(&bytes).unwrap();
+        assert_eq!(frame.direction, Direction::Notify);
+        assert_eq!(frame.register, 0x04);
+        assert_eq!(frame.payload.len(), 8);
+        assert!(!frame.is_checksum_valid());
+        assert_eq!(frame.expected_checksum(), 0xfc2d);
+        assert_eq!(frame.bytes(), bytes);
+
+        let write = from_hex("dd5ae1020000ff1d77").unwrap();
+        assert!(Frame::parse(&write).unwrap().is_write());
+        assert_eq!(Frame::write(0xe1, vec![0x00, 0x00]).bytes(), write);
+        assert_eq!(Frame::read(0x03).bytes(), Request::BatteryDetail.bytes());
+
+        assert_eq!(Frame::parse(&[0xdd, 0x03]), Err(ParseError::NotEnoughData));
+        assert_eq!(
+            Frame::parse(&from_hex("dd03000000000078").unwrap()),
+            Err(ParseError::InvalidData)
+        );
+    }
+
+    #[test]
+    fn test_assembler() {
+        let mut assembler = FrameAssembler::new();
+        assert!(assembler.push(Request::Clear.bytes()).is_empty());
+        assert!(assembler.push(&[0xdd, 0x04, 0x00, 0x08]).is_empty());
+        assert!(assembler
+            .push(&[0x0d, 0xe2, 0x0d, 0xdc, 0x0d, 0xec])
+            .is_empty());
+
+        let mut chunk = vec![0x0d, 0xed, 0xfc, 0x2d, 0x77];
+        chunk.extend_from_slice(Request::BatteryDetail.bytes());
+        chunk.extend_from_slice(&[0xdd, 0x03]);
+        let frames = assembler.push(&chunk);
+        assert_eq!(frames.len(), 2);
+        assert!(Response::parse_response(&frames[0]).is_ok());
+        assert_eq!(frames[1], Request::BatteryDetail.bytes());
+        assert_eq!(assembler.pending(), &[0xdd, 0x03]);
+    }
+
+    use super::*;
+    use crate::util::from_hex;
+    use crate::{Request, Response};
+}
+
+use crate::checksum::calculate_checksum;
+use crate::util::{to_hex, u16_from_bytes};
+use crate::{Direction, ParseError, ParseResult};
@@ -0,0 +1,521 @@
+/// How finely samples are kept.
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Hash, PartialOrd, Ord)]
+pub enum Resolution {
+    /// Every snapshot.
+    Raw,
+    /// The minimum, maximum and average of each minute.
+    Minute,
+    /// The minimum, maximum and average of each hour.
+    Hour,
+}
+
+/// A value of a field over a period, or of a single snapshot at
+/// `Resolution::Raw`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct HistorySample {
+    /// The start of the period (seconds since the Unix epoch).
+    pub timestamp: i64,
+    pub field: String,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    /// The snapshots in the period.
+    pub count: i64,
+}
+
+/// The minimum, maximum and average of a field over a range.
+#[derive(PartialEq, Clone, Debug)]
+pub struct FieldStats {
+    pub field: String,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub count: i64,
+}
+
+/// Records snapshots in an SQLite database, downsampling them as they age.
+///
+/// Every snapshot is split into fields (see `fields`) kept at three
+/// resolutions side by side: raw for `raw_days`, per minute for
+/// `minute_days` and per hour forever. `downsample` aggregates the periods
+/// that ended and drops expired rows; the `Sink` implementation calls it
+/// once a minute.
+pub struct HistoryStore {
+    connection: Connection,
+    raw_days: u32,
+    minute_days: u32,
+    last_downsample: Option<i64>,
+}
+
+impl Resolution {
+    pub const ALL: [Resolution; 3] = [Resolution::Raw, Resolution::Minute, Resolution::Hour];
+
+    /// The length of a period (seconds), 0 for raw samples.
+    pub fn seconds(self) -> i64 {
+        match self {
+            Resolution::Raw => 0,
+            Resolution::Minute => 60,
+            Resolution::Hour => 3600,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Resolution::Raw => "raw",
+            Resolution::Minute => "minute",
+            Resolution::Hour => "hour",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|r| r.name() == name)
+    }
+}
+
+impl HistoryStore {
+    /// Opens or creates the database.
+    pub fn open<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::new(Connection::open(path)?)
+    }
+
+    pub fn in_memory() -> Result<Self> {
+        Self::new(Connection::open_in_memory()?)
+    }
+
+    pub fn from_config(config: &HistoryConfig) -> Result<Self> {
+        Ok(Self::open(&config.path)?
+            .raw_days(config.raw_days)
+            .minute_days(config.minute_days))
+    }
+
+    fn new(connection: Connection) -> Result<Self> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(HistoryStore {
+            connection,
+            raw_days: 7,
+            minute_days: 90,
+            last_downsample: None,
+        })
+    }
+
+    /// How long raw samples are kept (days).
+    pub fn raw_days(mut self, days: u32) -> Self {
+        self.raw_days = days;
+        self
+    }
+
+    /// How long per minute samples are kept (days).
+    pub fn minute_days(mut self, days: u32) -> Self {
+        self.minute_days = days;
+        self
+    }
+
+    /// Stores the fields of a snapshot as raw samples.
+    pub fn record(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let timestamp = snapshot.timestamp.timestamp();
+        let transaction = self.connection.transaction()?;
+        {
+            let mut insert = transaction.prepare_cached(
+                "INSERT OR REPLACE INTO samples (resolution, field, timestamp, min, max, avg, count)
+                 VALUES (0, ?1, ?2, ?3, ?3, ?3, 1)",
+            )?;
+            for (field, value) in fields(snapshot) {
+                insert.execute(params![field, timestamp, value])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Aggregates the minutes and hours that ended before `now` (seconds since
+    /// the Unix epoch), then drops the raw and per minute samples past their
+    /// retention.
+    pub fn downsample(&mut self, now: i64) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        for (from, to) in [
+            (Resolution::Raw, Resolution::Minute),
+            (Resolution::Minute, Resolution::Hour),
+        ] {
+            let period = to.seconds();
+            let end = now - now.rem_euclid(period);
+            let start: i64 = transaction
+                .query_row(
+                    "SELECT timestamp FROM watermarks WHERE resolution = ?1",
+                    [period],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .unwrap_or(i64::MIN);
+            transaction.execute(
+                "INSERT OR REPLACE INTO samples (resolution, field, timestamp, min, max, avg, count)
+                 SELECT ?2, field, timestamp - timestamp % ?2, MIN(min), MAX(max),
+                        SUM(avg * count) / SUM(count), SUM(count)
+                 FROM samples
+                 WHERE resolution = ?1 AND timestamp >= ?3 AND timestamp < ?4
+                 GROUP BY field, timestamp - timestamp % ?2",
+                params![from.seconds(), period, start, end],
+            )?;
+            transaction.execute(
+                "INSERT OR REPLACE INTO watermarks (resolution, timestamp) VALUES (?1, ?2)",
+                params![period, end.max(start)],
+            )?;
+        }
+        for (resolution, days) in [
+            (Resolution::Raw, self.raw_days),
+            (Resolution::Minute, self.minute_days),
+        ] {
+            transaction.execute(
+                "DELETE FROM samples WHERE resolution = ?1 AND timestamp < ?2",
+                params![resolution.seconds(), now - days as i64 * DAY],
+            )?;
+        }
+        transaction.commit()?;
+        self.last_downsample = Some(now);
+        Ok(())
+    }
+
+    /// The finest resolution still holding samples from `since`.
+    pub fn resolution_for(&self, since: i64, now: i64) -> Resolution {
+        let age = now - since;
+        if age <= self.raw_days as i64 * DAY {
+            Resolution::Raw
+        } else if age <= self.minute_days as i64 * DAY {
+            Resolution::Minute
+        } else {
+            Resolution::Hour
+        }
+    }
+
+    /// The samples of `range` (seconds since the Unix epoch), ordered by time
+    /// then field. An empty `fields` selects every field.
+    pub fn query(
+        &self,
+        resolution: Resolution,
+        range: Range<i64>,
+        fields: &[String],
+    ) -> Result<Vec<HistorySample>> {
+        let mut statement = self.connection.prepare_cached(
+            "SELECT timestamp, field, min, max, avg, count FROM samples
+             WHERE resolution = ?1 AND timestamp >= ?2 AND timestamp < ?3
+             ORDER BY timestamp, field",
+        )?;
+        let rows = statement.query_map(
+            params![resolution.seconds(), range.start, range.end],
+            |row| {
+                Ok(HistorySample {
+                    timestamp: row.get(0)?,
+                    field: row.get(1)?,
+                    min: row.get(2)?,
+                    max: row.get(3)?,
+                    avg: row.get(4)?,
+                    count: row.get(5)?,
+                })
+            },
+        )?;
+        let mut samples = Vec::new();
+        for sample in rows {
+            let sample = sample?;
+            if fields.is_empty() || fields.contains(&sample.field) {
+                samples.push(sample);
+            }
+        }
+        Ok(samples)
+    }
+
+    /// The minimum, maximum and average of each field over `range`, by field.
+    pub fn stats(
+        &self,
+        resolution: Resolution,
+        range: Range<i64>,
+        fields: &[String],
+    ) -> Result<Vec<FieldStats>> {
+        let mut stats: BTreeMap<String, FieldStats> = BTreeMap::new();
+        for sample in self.query(resolution, range, fields)? {
+            let entry = stats
+                .entry(sample.field.clone())
+                .or_insert_with(|| FieldStats {
+                    field: sample.field.clone(),
+                    min: f64::INFINITY,
+                    max: f64::NEG_INFINITY,
+                    avg: 0.0,
+                    count: 0,
+                });
+            entry.min = entry.min.min(sample.min);
+            entry.max = entry.max.max(sample.max);
+            entry.avg += sample.avg * sample.count as f64;
+            entry.count += sample.count;
+        }
+        Ok(stats
+            .into_values()
+            .map(|mut stats| {
+                stats.avg /= stats.count as f64;
+                stats
+            })
+            .collect())
+    }
+}
+
+impl Sink for HistoryStore {
+    fn handle(&mut self, event: &Event) -> Result<()> {
+        let Event::Snapshot(snapshot) = event else {
+            return Ok(());
+        };
+        self.record(snapshot)?;
+        let now = snapshot.timestamp.timestamp();
+        if self.last_downsample.is_none_or(|last| now - last >= 60) {
+            self.downsample(now)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits a snapshot into named values, in the units of the snapshot: the
+/// fields of `BatteryDetail`, `cell_<n>` (mV), `ntc_<n>` (0.1 °C) and the
+/// counters of `BatteryProtect`.
+pub fn fields(snapshot: &Snapshot) -> Vec<(String, f64)> {
+    let detail = &snapshot.detail;
+    let mut fields: Vec<(String, f64)> = [
+        ("total_voltage", detail.total_voltage as f64),
+        ("current", detail.current as f64),
+        ("residual_capacity", detail.residual_capacity as f64),
+        ("standard_capacity", detail.standard_capacity as f64),
+        (
+            "residual_capacity_percent",
+            detail.residual_capacity_percent as f64,
+        ),
+        ("cycles", detail.cycles as f64),
+        ("protection_of_state", detail.protection_of_state.0 as f64),
+        ("charge", detail.charge as u8 as f64),
+        ("discharge", detail.discharge as u8 as f64),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value))
+    .collect();
+    for (idx, voltage) in snapshot.voltage.iter().enumerate() {
+        fields.push((format!("cell_{}", idx + 1), *voltage as f64));
+    }
+    for (idx, temperature) in detail.list_ntc.iter().enumerate() {
+        fields.push((format!("ntc_{}", idx + 1), *temperature as f64));
+    }
+    for (idx, name) in BatteryProtect::FIELD_NAMES.iter().enumerate() {
+        let count = snapshot.protect.value_at(idx).unwrap_or_default();
+        fields.push((name.to_string(), count as f64));
+    }
+    fields
+}
+
+/// Writes samples as CSV, one line per sample.
+pub fn write_samples_csv<W>(samples: &[HistorySample], mut writer: W) -> Result<()>
+where
+    W: Write,
+{
+    writeln!(writer, "timestamp,field,min,max,avg,count")?;
+    for sample in samples {
+        let timestamp = DateTime::from_timestamp(sample.timestamp, 0)
+            .map_or_else(|| sample.timestamp.to_string(), |t| t.to_rfc3339());
+        writeln!(
+            writer,
+            "{},{},{},{},{},{}",
+            timestamp, sample.field, sample.min, sample.max, sample.avg, sample.count
+        )?;
+    }
+    Ok(())
+}
+
+const DAY: i64 = 24 * 3600;
+
+/// `resolution` is the length of the period (see `Resolution::seconds`),
+/// `watermarks` the end of the periods already aggregated.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS samples (
+    resolution INTEGER NOT NULL,
+    field TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    min REAL NOT NULL,
+    max REAL NOT NULL,
+    avg REAL NOT NULL,
+    count INTEGER NOT NULL,
+    PRIMARY KEY (resolution, timestamp, field)
+) WITHOUT ROWID;
+CREATE TABLE IF NOT EXISTS watermarks (
+    resolution INTEGER PRIMARY KEY,
+    timestamp INTEGER NOT NULL
+);
+";
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_fields() {
+        let fields = fields(&snapshot(0, 3300));
+        assert_eq!(fields.len(), 9 + 2 + 1 + 11);
+        assert_eq!(fields[0], ("total_voltage".to_string(), 1336.0));
+        assert_eq!(fields[9], ("cell_1".to_string(), 3300.0));
+        assert_eq!(fields[10], ("cell_2".to_string(), 3310.0));
+        assert_eq!(fields[11], ("ntc_1".to_string(), 212.0));
+        assert_eq!(fields[12], ("short_circuit".to_string(), 0.0));
+    }
+
+    #[test]
+    fn test_record_query_stats() {
+        let mut store = HistoryStore::in_memory().unwrap();
+        for (t, cell) in [(0, 3300), (10, 3320), (20, 3340)] {
+            store.record(&snapshot(t, cell)).unwrap();
+        }
+
+        let cells = vec!["cell_1".to_string()];
+        let samples = store
+            .query(Resolution::Raw, START..START + 20, &cells)
+            .unwrap();
+        assert_eq!(
+            samples.iter().map(|s| s.avg).collect::<Vec<_>>(),
+            [3300.0, 3320.0]
+        );
+
+        let stats = store
+            .stats(Resolution::Raw, START..START + 60, &[])
+            .unwrap();
+        let cell = stats.iter().find(|s| s.field == "cell_1").unwrap();
+        assert_eq!(
+            (cell.min, cell.max, cell.avg, cell.count),
+            (3300.0, 3340.0, 3320.0, 3)
+        );
+
+        let mut csv = Vec::new();
+        write_samples_csv(&samples, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "timestamp,field,min,max,avg,count
+2026-10-18T08:00:00+00:00,cell_1,3300,3300,3300,1
+2026-10-18T08:00:10+00:00,cell_1,3320,3320,3320,1
+"
+        );
+    }
+
+    #[test]
+    fn test_downsample() {
+        let mut store = HistoryStore::in_memory()
+            .unwrap()
+            .raw_days(1)
+            .minute_days(2);
+        // two snapshots in each of the first two minutes, one in the third
+        for (t, cell) in [(0, 3300), (30, 3310), (60, 3320), (90, 3360), (120, 3400)] {
+            store.record(&snapshot(t, cell)).unwrap();
+        }
+
+        store.downsample(START + 125).unwrap();
+        let cells = vec!["cell_1".to_string()];
+        let minutes = store
+            .query(Resolution::Minute, START..START + DAY, &cells)
+            .unwrap();
+        assert_eq!(
+            minutes
+                .iter()
+                .map(|s| (s.timestamp - START, s.min, s.max, s.avg, s.count))
+                .collect::<Vec<_>>(),
+            [
+                (0, 3300.0, 3310.0, 3305.0, 2),
+                (60, 3320.0, 3360.0, 3340.0, 2)
+            ]
+        );
+        // the hour has not ended yet
+        assert_eq!(
+            store
+                .query(Resolution::Hour, START..START + DAY, &cells)
+                .unwrap(),
+            []
+        );
+
+        // downsampling again does not count the same snapshots twice
+        store.downsample(START + 3600).unwrap();
+        let hours = store
+            .query(Resolution::Hour, START..START + DAY, &cells)
+            .unwrap();
+        assert_eq!(hours.len(), 1);
+        assert_eq!(
+            (hours[0].min, hours[0].max, hours[0].avg, hours[0].count),
+            (3300.0, 3400.0, 3338.0, 5)
+        );
+
+        // raw samples expire after a day, per minute samples after two
+        store.downsample(START + DAY + 121).unwrap();
+        assert_eq!(store.query(Resolution::Raw, 0..i64::MAX, &[]).unwrap(), []);
+        assert_eq!(
+            store
+                .query(Resolution::Minute, 0..i64::MAX, &cells)
+                .unwrap()
+                .len(),
+            3
+        );
+        store.downsample(START + 3 * DAY).unwrap();
+        assert_eq!(
+            store.query(Resolution::Minute, 0..i64::MAX, &[]).unwrap(),
+            []
+        );
+        assert_eq!(
+            store.query(Resolution::Hour, 0..i64::MAX, &cells).unwrap(),
+            hours
+        );
+    }
+
+    #[test]
+    fn test_resolution_for() {
+        let store = HistoryStore::in_memory().unwrap();
+        assert_eq!(store.resolution_for(START, START + DAY), Resolution::Raw);
+        assert_eq!(
+            store.resolution_for(START, START + 30 * DAY),
+            Resolution::Minute
+        );
+        assert_eq!(
+            store.resolution_for(START, START + 365 * DAY),
+            Resolution::Hour
+        );
+    }
+
+    #[test]
+    fn test_sink() {
+        let mut store = HistoryStore::in_memory().unwrap();
+        for t in (0..=180).step_by(10) {
+            store.handle(&Event::Snapshot(snapshot(t, 3300))).unwrap();
+        }
+        let minutes = store
+            .query(Resolution::Minute, 0..i64::MAX, &["cell_1".to_string()])
+            .unwrap();
+        assert_eq!(minutes.len(), 3);
+        assert_eq!(minutes[0].count, 6);
+    }
+
+    /// 2026-10-18T08:00:00Z
+    const START: i64 = 1_792_310_400;
+
+    fn snapshot(t: i64, cell: i16) -> Snapshot {
+        SnapshotBuilder::at(0)
+            .timestamp(
+                DateTime::from_timestamp(START + t, 0)
+                    .unwrap()
+                    .fixed_offset(),
+            )
+            .cells(&[cell, cell + 10])
+            .detail(BatteryDetail {
+                total_voltage: 1336,
+                list_ntc: vec![212],
+                ..Default::default()
+            })
+            .build()
+    }
+
+    use super::*;
+    use crate::snapshot::SnapshotBuilder;
+    use crate::BatteryDetail;
+}
+
+use crate::{BatteryProtect, Event, HistoryConfig, Result, Sink, Snapshot};
+use chrono::DateTime;
+use rusqlite::{params, Connection, OptionalExtension as _};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::ops::Range;
+use std::path::Path;
@@ -0,0 +1,510 @@
+/// The imbalance of one ISO week, see `ImbalanceTracker::weeks`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct WeeklyImbalance {
+    /// E.g. `2026-W42`.
+    pub label: String,
+    /// The snapshots seen that week.
+    pub samples: u32,
+    /// The mean difference between the highest and lowest cell (mV).
+    pub mean_delta: f64,
+    /// The largest difference between the highest and lowest cell (mV).
+    pub max_delta: i16,
+    /// The share of the snapshots during which the BMS balanced a cell.
+    pub balancing: f64,
+}
+
+/// How a cell compares to the others, see `ImbalanceTracker::health`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct CellHealth {
+    /// The cell, from 1.
+    pub cell: usize,
+    /// From 0 for a cell always out of line to 100 for one in line with the
+    /// others.
+    pub score: f64,
+    /// The ends of charge at which it was the highest cell.
+    pub highest: u32,
+    /// The ends of discharge at which it was the lowest cell.
+    pub lowest: u32,
+    /// How far above the mean it was at the ends of charge (mV).
+    pub charge_deviation: f64,
+    /// How far below the mean it was at the ends of discharge (mV).
+    pub discharge_deviation: f64,
+    /// The share of the snapshots during which the BMS balanced it.
+    pub balancing: f64,
+    /// Why the score is lower than 100.
+    pub reasons: Vec<String>,
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum EpisodeKind {
+    EndOfCharge,
+    EndOfDischarge,
+}
+
+/// The top of a charge or the bottom of a discharge, keeping the cell
+/// voltages of its most imbalanced snapshot.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+struct Episode {
+    kind: EpisodeKind,
+    voltage: Vec<i16>,
+    delta: i16,
+}
+
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct CellStats {
+    highest: u32,
+    lowest: u32,
+    /// The sum of the deviations above the mean at the ends of charge (mV).
+    charge_deviation: f64,
+    /// The sum of the deviations below the mean at the ends of discharge (mV).
+    discharge_deviation: f64,
+    /// The snapshots during which it was balanced.
+    balancing: u32,
+}
+
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct WeekStats {
+    samples: u32,
+    delta_sum: f64,
+    max_delta: i16,
+    balancing: u32,
+}
+
+/// What the tracker keeps between restarts.
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ImbalanceState {
+    cells: Vec<CellStats>,
+    charges: u32,
+    discharges: u32,
+    samples: u32,
+    /// Keyed by the Monday of each week.
+    weeks: BTreeMap<NaiveDate, WeekStats>,
+    episode: Option<Episode>,
+}
+
+/// Follows the imbalance of the cells over time to find a weak cell.
+///
+/// While charging with a cell at `end_of_charge_voltage` or at 100 %, and
+/// while discharging with a cell at `end_of_discharge_voltage` or at
+/// `end_of_discharge_soc`, the most imbalanced snapshot is kept. Once such
+/// an episode ends, its highest or lowest cell is counted and each cell's
+/// distance to the mean is added up. A cell with less capacity than the
+/// others is highest at the end of charge and lowest at the end of
+/// discharge, so both lower its score, next to how far it strays. The
+/// balancing flags of `BatteryDetail::equilibrium` are counted per cell and
+/// per week to show whether the BMS keeps up.
+pub struct ImbalanceTracker {
+    config: ImbalanceConfig,
+    state: ImbalanceState,
+    file: Option<StateFile>,
+}
+
+impl ImbalanceTracker {
+    pub fn new(config: ImbalanceConfig) -> Self {
+        ImbalanceTracker {
+            config,
+            state: ImbalanceState::default(),
+            file: None,
+        }
+    }
+
+    /// Creates a tracker keeping its statistics in the configured state
+    /// file, loading them when it exists. The file is written at most once
+    /// a minute.
+    pub fn from_config(config: &ImbalanceConfig) -> Result<Self> {
+        let mut tracker = Self::new(config.clone());
+        (tracker.state, tracker.file) =
+            StateFile::open(config.state.as_ref(), chrono::Duration::minutes(1))?;
+        Ok(tracker)
+    }
+
+    pub fn update(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let Some(delta) = snapshot.cell_delta() else {
+            return Ok(());
+        };
+        let cells = snapshot.voltage.len();
+        if self.state.cells.len() < cells {
+            self.state.cells.resize(cells, CellStats::default());
+        }
+
+        let balancing: Vec<usize> = (0..cells)
+            .filter(|cell| snapshot.detail.is_balancing(*cell))
+            .collect();
+        for cell in &balancing {
+            self.state.cells[*cell].balancing += 1;
+        }
+        self.state.samples += 1;
+        let monday = snapshot
+            .timestamp
+            .date_naive()
+            .week(Weekday::Mon)
+            .first_day();
+        let week = self.state.weeks.entry(monday).or_default();
+        week.samples += 1;
+        week.delta_sum += delta as f64;
+        week.max_delta = week.max_delta.max(delta);
+        week.balancing += !balancing.is_empty() as u32;
+
+        let kind = self.episode_kind(snapshot);
+        match &mut self.state.episode {
+            Some(episode) if Some(episode.kind) == kind => {
+                if delta > episode.delta {
+                    episode.voltage = snapshot.voltage.clone();
+                    episode.delta = delta;
+                }
+            }
+            _ => {
+                if let Some(episode) = self.state.episode.take() {
+                    self.record(&episode);
+                }
+                self.state.episode = kind.map(|kind| Episode {
+                    kind,
+                    voltage: snapshot.voltage.clone(),
+                    delta,
+                });
+            }
+        }
+        if let Some(file) = &mut self.file {
+            file.save(&self.state, snapshot.timestamp)?;
+        }
+        Ok(())
+    }
+
+    /// The imbalance per week, oldest first.
+    pub fn weeks(&self) -> Vec<WeeklyImbalance> {
+        self.state
+            .weeks
+            .iter()
+            .map(|(monday, week)| {
+                let iso = monday.iso_week();
+                WeeklyImbalance {
+                    label: format!("{}-W{:02}", iso.year(), iso.week()),
+                    samples: week.samples,
+                    mean_delta: week.delta_sum / week.samples.max(1) as f64,
+                    max_delta: week.max_delta,
+                    balancing: week.balancing as f64 / week.samples.max(1) as f64,
+                }
+            })
+            .collect()
+    }
+
+    /// How fast the mean imbalance grows (mV per week).
+    pub fn growth(&self) -> Option<f64> {
+        let first = *self.state.weeks.keys().next()?;
+        let points: Vec<(f64, f64, f64)> = self
+            .state
+            .weeks
+            .iter()
+            .map(|(monday, week)| {
+                let x = (*monday - first).num_days() as f64 / 7.0;
+                let mean = week.delta_sum / week.samples.max(1) as f64;
+                (x, mean, week.samples as f64)
+            })
+            .collect();
+        weighted_slope(&points)
+    }
+
+    /// The health of each cell.
+    pub fn health(&self) -> Vec<CellHealth> {
+        let state = &self.state;
+        let count = state.cells.len();
+        let fair = 1.0 / count.max(1) as f64;
+        let excess = |times: u32, total: u32| {
+            if total == 0 || count < 2 {
+                return 0.0;
+            }
+            ((times as f64 / total as f64 - fair) / (1.0 - fair)).max(0.0)
+        };
+        let mean = |sum: f64, total: u32| sum / total.max(1) as f64;
+        let balancing = |cell: &CellStats| cell.balancing as f64 / state.samples.max(1) as f64;
+        let mut shares: Vec<f64> = state.cells.iter().map(balancing).collect();
+        shares.sort_by(f64::total_cmp);
+        let median = shares.get(count / 2).copied().unwrap_or_default();
+        let limit = self.config.deviation as f64;
+
+        state
+            .cells
+            .iter()
+            .enumerate()
+            .map(|(idx, cell)| {
+                let highest = excess(cell.highest, state.charges);
+                let lowest = excess(cell.lowest, state.discharges);
+                let charge_deviation = mean(cell.charge_deviation, state.charges);
+                let discharge_deviation = mean(cell.discharge_deviation, state.discharges);
+                let score = (100.0
+                    * (1.0
+                        - 0.3 * highest
+                        - 0.3 * lowest
+                        - 0.2 * (charge_deviation / limit).clamp(0.0, 1.0)
+                        - 0.2 * (discharge_deviation / limit).clamp(0.0, 1.0)))
+                .max(0.0);
+
+                let mut reasons = Vec::new();
+                if highest > 0.0 {
+                    reasons.push(format!(
+                        "highest at {} of {} ends of charge, {:+.0} mV from the mean",
+                        cell.highest, state.charges, charge_deviation
+                    ));
+                }
+                if lowest > 0.0 {
+                    reasons.push(format!(
+                        "lowest at {} of {} ends of discharge, {:+.0} mV from the mean",
+                        cell.lowest, state.discharges, -discharge_deviation
+                    ));
+                }
+                let share = balancing(cell);
+                if share > 0.0 && share > 2.0 * median {
+                    reasons.push(format!(
+                        "balanced during {:.0} % of the polls, the median cell {:.0} %",
+                        share * 100.0,
+                        median * 100.0
+                    ));
+                }
+
+                CellHealth {
+                    cell: idx + 1,
+                    score,
+                    highest: cell.highest,
+                    lowest: cell.lowest,
+                    charge_deviation,
+                    discharge_deviation,
+                    balancing: share,
+                    reasons,
+                }
+            })
+            .collect()
+    }
+
+    /// The cells scoring below `weak_score`, once `min_episodes` ends of
+    /// charge and discharge were seen.
+    pub fn weak_cells(&self) -> Vec<CellHealth> {
+        if self.state.charges + self.state.discharges < self.config.min_episodes {
+            return Vec::new();
+        }
+        self.health()
+            .into_iter()
+            .filter(|health| health.score < self.config.weak_score)
+            .collect()
+    }
+
+    fn episode_kind(&self, snapshot: &Snapshot) -> Option<EpisodeKind> {
+        let detail = &snapshot.detail;
+        let soc = detail.residual_capacity_percent;
+        if detail.current > 0
+            && (soc >= 100
+                || snapshot
+                    .max_cell_voltage()
+                    .is_some_and(|voltage| voltage >= self.config.end_of_charge_voltage))
+        {
+            Some(EpisodeKind::EndOfCharge)
+        } else if detail.current < 0
+            && (soc <= self.config.end_of_discharge_soc
+                || snapshot
+                    .min_cell_voltage()
+                    .is_some_and(|voltage| voltage <= self.config.end_of_discharge_voltage))
+        {
+            Some(EpisodeKind::EndOfDischarge)
+        } else {
+            None
+        }
+    }
+
+    fn record(&mut self, episode: &Episode) {
+        let voltage = &episode.voltage;
+        if voltage.is_empty() {
+            return;
+        }
+        let mean = voltage.iter().map(|v| *v as f64).sum::<f64>() / voltage.len() as f64;
+        match episode.kind {
+            EpisodeKind::EndOfCharge => {
+                self.state.charges += 1;
+                if let Some(cell) = position(voltage, Iterator::max) {
+                    self.state.cells[cell].highest += 1;
+                }
+                for (cell, v) in voltage.iter().enumerate() {
+                    self.state.cells[cell].charge_deviation += *v as f64 - mean;
+                }
+            }
+            EpisodeKind::EndOfDischarge => {
+                self.state.discharges += 1;
+                if let Some(cell) = position(voltage, Iterator::min) {
+                    self.state.cells[cell].lowest += 1;
+                }
+                for (cell, v) in voltage.iter().enumerate() {
+                    self.state.cells[cell].discharge_deviation += mean - *v as f64;
+                }
+            }
+        }
+    }
+}
+
+impl Sink for ImbalanceTracker {
+    fn handle(&mut self, event: &Event) -> Result<()> {
+        match event {
+            Event::Snapshot(snapshot) => self.update(snapshot),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Display for WeeklyImbalance {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<10}mean {:>5.1} mV  max {:>4} mV  balancing {:>5.1} %",
+            self.label,
+            self.mean_delta,
+            self.max_delta,
+            self.balancing * 100.0
+        )
+    }
+}
+
+impl Display for CellHealth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "cell {:<3}score {:>3.0}", self.cell, self.score)?;
+        if !self.reasons.is_empty() {
+            write!(f, ": {}", self.reasons.join("; "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The first cell holding the value `pick` selects.
+fn position<'a, F>(voltage: &'a [i16], pick: F) -> Option<usize>
+where
+    F: FnOnce(std::slice::Iter<'a, i16>) -> Option<&'a i16>,
+{
+    let value = pick(voltage.iter())?;
+    voltage.iter().position(|v| v == value)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_episodes() {
+        let mut tracker = ImbalanceTracker::new(ImbalanceConfig::default());
+        // charging to the top, cell 2 runs ahead until the charger stops
+        for (seconds, cells) in [
+            (0, [3350, 3360, 3350, 3350]),
+            (60, [3440, 3470, 3445, 3440]),
+            (120, [3450, 3520, 3455, 3450]),
+            (180, [3455, 3490, 3460, 3455]),
+        ] {
+            tracker
+                .update(&snapshot(seconds, 1000, 90, cells, 0))
+                .unwrap();
+        }
+        tracker
+            .update(&snapshot(240, 0, 100, [3400; 4], 0))
+            .unwrap();
+
+        assert_eq!(tracker.state.charges, 1);
+        assert_eq!(tracker.state.cells[1].highest, 1);
+        // the snapshot at 120 s, with a mean of 3468.75 mV
+        assert_eq!(tracker.state.cells[1].charge_deviation, 51.25);
+        assert_eq!(tracker.state.cells[0].charge_deviation, -18.75);
+
+        // discharging to the bottom, cell 4 drops first
+        tracker
+            .update(&snapshot(300, -2000, 8, [3100, 3100, 3090, 2950], 0))
+            .unwrap();
+        tracker.update(&snapshot(360, 0, 8, [3200; 4], 0)).unwrap();
+        assert_eq!(tracker.state.discharges, 1);
+        assert_eq!(tracker.state.cells[3].lowest, 1);
+    }
+
+    #[test]
+    fn test_weak_cell() {
+        let mut tracker = ImbalanceTracker::new(ImbalanceConfig::default());
+        // cell 3 is highest at every end of charge and lowest at every end of
+        // discharge, and balanced while charging
+        for cycle in 0..4 {
+            let t = cycle * 600;
+            tracker
+                .update(&snapshot(t, 1000, 95, [3450, 3450, 3530, 3450], 0b0100))
+                .unwrap();
+            tracker
+                .update(&snapshot(t + 60, 0, 100, [3400; 4], 0))
+                .unwrap();
+            tracker
+                .update(&snapshot(t + 120, -2000, 5, [3050, 3050, 2960, 3040], 0))
+                .unwrap();
+            tracker
+                .update(&snapshot(t + 180, 0, 5, [3200; 4], 0))
+                .unwrap();
+        }
+
+        let health = tracker.health();
+        assert_eq!(health[0].score, 100.0);
+        assert_eq!(health[2].highest, 4);
+        assert_eq!(health[2].lowest, 4);
+        assert_eq!(health[2].balancing, 0.25);
+
+        let weak = tracker.weak_cells();
+        assert_eq!(weak.len(), 1);
+        assert_eq!(weak[0].cell, 3);
+        assert_eq!(
+            weak[0].to_string(),
+            "cell 3  score   0: highest at 4 of 4 ends of charge, +60 mV from the mean; \
+lowest at 4 of 4 ends of discharge, -65 mV from the mean; \
+balanced during 25 % of the polls, the median cell 0 %"
+        );
+    }
+
+    #[test]
+    fn test_weeks() {
+        let mut tracker = ImbalanceTracker::new(ImbalanceConfig::default());
+        // the imbalance grows by 5 mV every week
+        for week in 0..4 {
+            let delta = 10 + 5 * week as i16;
+            tracker
+                .update(&snapshot(
+                    week * 7 * 24 * 3600,
+                    0,
+                    50,
+                    [3300, 3300, 3300, 3300 + delta],
+                    0,
+                ))
+                .unwrap();
+        }
+
+        let weeks = tracker.weeks();
+        assert_eq!(
+            weeks.iter().map(|w| w.label.as_str()).collect::<Vec<_>>(),
+            ["2026-W42", "2026-W43", "2026-W44", "2026-W45"]
+        );
+        assert_eq!(weeks[3].max_delta, 25);
+        assert!((tracker.growth().unwrap() - 5.0).abs() < 1e-9);
+        assert_eq!(tracker.weak_cells(), []);
+    }
+
+    fn snapshot(seconds: i64, current: i16, soc: u8, cells: [i16; 4], balancing: i16) -> Snapshot {
+        SnapshotBuilder::at(seconds)
+            .cells(&cells)
+            .detail(BatteryDetail {
+                current,
+                residual_capacity_percent: soc,
+                equilibrium: balancing,
+                ..Default::default()
+            })
+            .build()
+    }
+
+    use super::*;
+    use crate::snapshot::SnapshotBuilder;
+    use crate::BatteryDetail;
+}
+
+use crate::{
+    util::{weighted_slope, StateFile},
+    Event, ImbalanceConfig, Result, Sink, Snapshot,
+};
+use chrono::{Datelike, NaiveDate, Weekday};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
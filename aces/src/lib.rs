@@ -1,20 +1,86 @@
+// imports are placed at the bottom of each module, after the tests
+#![allow(clippy::items_after_test_module)]
+
+mod alert;
+mod btsnoop;
+mod capacity;
+mod capture;
+mod charger;
 mod checksum;
+mod clock;
+mod config;
+mod console;
 mod detail;
+mod discovery;
+mod dissect;
+mod eeprom;
+mod energy;
+mod factory;
+mod faults;
+mod frame;
+#[cfg(feature = "history")]
+mod history;
+mod imbalance;
 mod ntc;
 mod protect;
+mod protection_history;
 mod protection_of_state;
 mod request;
+mod resistance;
+mod responder;
 mod response;
+mod safe_write;
+mod scanner;
+mod scenario;
+mod scheduler;
+mod session;
+mod sink;
+mod snapshot;
+mod supervisor;
+mod telemetry;
 mod util;
+mod validation;
 mod voltage;
 
+pub use alert::*;
+pub use btsnoop::*;
+pub use capacity::*;
+pub use capture::*;
+pub use charger::*;
 pub use checksum::*;
+pub use clock::*;
+pub use config::*;
+pub use console::*;
 pub use detail::*;
+pub use discovery::*;
+pub use dissect::*;
+pub use eeprom::*;
+pub use energy::*;
+pub use factory::*;
+pub use faults::*;
+pub use frame::*;
+#[cfg(feature = "history")]
+pub use history::*;
+pub use imbalance::*;
 pub use ntc::*;
 pub use protect::*;
+pub use protection_history::*;
 pub use protection_of_state::*;
 pub use request::*;
+pub use resistance::*;
+pub use responder::*;
 pub use response::*;
+pub use safe_write::*;
+pub use scanner::*;
+pub use scenario::*;
+pub use scheduler::*;
+pub use session::*;
+pub use sink::*;
+pub use snapshot::*;
+pub use supervisor::*;
+pub use telemetry::*;
+pub use util::{from_hex, hex_dump, to_hex};
+pub use validation::*;
 pub use voltage::*;
 
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
@@ -22,6 +88,26 @@ type ParseResult<T> = std::result::Result<T, ParseError>;
 
 pub trait NotificationsReceiver {
     fn next(&mut self) -> Vec<u8>;
+
+    /// Waits at most `timeout` for the next notification.
+    ///
+    /// The default waits for `next` without a limit, except that a zero
+    /// timeout returns `None` as it cannot tell whether one is pending.
+    fn next_timeout(&mut self, timeout: Duration) -> Option<Vec<u8>> {
+        if timeout.is_zero() {
+            None
+        } else {
+            Some(self.next())
+        }
+    }
+
+    /// When the notification returned last arrived, `None` when unknown.
+    ///
+    /// Receivers queueing notifications should keep their arrival time, as
+    /// they may be read much later.
+    fn arrived(&self) -> Option<Instant> {
+        None
+    }
 }
 
 #[derive(Eq, PartialEq, Debug, thiserror::Error)]
@@ -118,6 +204,25 @@ where
     resp
 }
 
+/// Reads the next complete response from `register`, discarding those from
+/// other registers (e.g. a late reply to an earlier request), and gives up
+/// when no notification arrives within `timeout`.
+fn read_reply_timeout<N>(receiver: &mut N, register: u8, timeout: Duration) -> Option<Vec<u8>>
+where
+    N: NotificationsReceiver,
+{
+    let mut assembler = FrameAssembler::new();
+    loop {
+        let fragment = receiver.next_timeout(timeout)?;
+        for frame in assembler.push(&fragment) {
+            if frame[1] == register {
+                return Some(frame);
+            }
+            log::warn!("discarding a reply from register {:02x}", frame[1]);
+        }
+    }
+}
+
 // commands
 
 pub const SERVICE_UUID: u16 = 0xff00;
@@ -131,9 +236,35 @@ pub const TX_UUID: u16 = 0xff02;
 struct WrongNotificationReceived;
 
 use std::future::Future;
+use std::time::{Duration, Instant};
 
 #[cfg(test)]
 mod tests {
+    #[test]
+    fn test_read_reply_timeout() {
+        let mut recv = receiver(vec![vec![0xdd, 0x04, 0x00, 0x08], vec![0x0d, 0xe2]]);
+        assert_eq!(
+            read_reply_timeout(&mut recv, 0x04, Duration::from_secs(1)),
+            None
+        );
+
+        let mut recv = receiver(vec![vec![0xdd, 0x03, 0x00, 0x00], vec![0xff, 0xfd, 0x77]]);
+        assert_eq!(
+            read_reply_timeout(&mut recv, 0x03, Duration::from_secs(1)),
+            Some(vec![0xdd, 0x03, 0x00, 0x00, 0xff, 0xfd, 0x77])
+        );
+
+        // a late reply from another register is skipped
+        let mut recv = receiver(vec![
+            vec![0xdd, 0x04, 0x00, 0x00, 0xff, 0xfc, 0x77, 0xdd, 0x03],
+            vec![0x00, 0x00, 0xff, 0xfd, 0x77],
+        ]);
+        assert_eq!(
+            read_reply_timeout(&mut recv, 0x03, Duration::from_secs(1)),
+            Some(vec![0xdd, 0x03, 0x00, 0x00, 0xff, 0xfd, 0x77])
+        );
+    }
+
     #[test]
     fn test_read_complete_response() {
         let mut recv = receiver(vec![
@@ -172,6 +303,10 @@ mod tests {
         fn next(&mut self) -> Vec<u8> {
             self.0.pop_front().unwrap()
         }
+
+        fn next_timeout(&mut self, _timeout: Duration) -> Option<Vec<u8>> {
+            self.0.pop_front()
+        }
     }
 
     use super::*;
@@ -21,6 +21,15 @@ impl NtcList {
 
         Ok(NtcList(list))
     }
+
+    pub fn to_message(&self) -> Vec<u8> {
+        let mut msg = vec![self.0.len() as u8];
+        for t in &self.0 {
+            // device uses big endian encoding
+            msg.extend_from_slice(&(t + 2731).to_be_bytes());
+        }
+        msg
+    }
 }
 
 #[cfg(test)]
@@ -47,6 +56,15 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_to_message() {
+        assert_eq!(NtcList(Vec::new()).to_message(), [0]);
+        assert_eq!(
+            NtcList(vec![0x0102, 0x0304]).to_message(),
+            [2, 0x0b, 0xad, 0x0d, 0xaf]
+        );
+    }
+
     use super::*;
 }
 
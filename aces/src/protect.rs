@@ -1,4 +1,4 @@
-#[derive(Eq, PartialEq, Debug, Default)]
+#[derive(Eq, PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
 pub struct BatteryProtect {
     pub short_circuit: i16,
     pub over_current_charging: i16,
@@ -14,6 +14,38 @@ pub struct BatteryProtect {
 }
 
 impl BatteryProtect {
+    /// The names of the counters, in the order they are reported by the device.
+    pub const FIELD_NAMES: [&'static str; 11] = [
+        "short_circuit",
+        "over_current_charging",
+        "over_current_discharging",
+        "cell_overvoltage",
+        "cell_undervoltage",
+        "high_temp_charging",
+        "low_temp_charging",
+        "high_temp_discharging",
+        "low_temp_discharging",
+        "pack_overvoltage",
+        "pack_undervoltage",
+    ];
+
+    pub fn value_at(&self, idx: usize) -> Option<i16> {
+        match idx {
+            0 => Some(self.short_circuit),
+            1 => Some(self.over_current_charging),
+            2 => Some(self.over_current_discharging),
+            3 => Some(self.cell_overvoltage),
+            4 => Some(self.cell_undervoltage),
+            5 => Some(self.high_temp_charging),
+            6 => Some(self.low_temp_charging),
+            7 => Some(self.high_temp_discharging),
+            8 => Some(self.low_temp_discharging),
+            9 => Some(self.pack_overvoltage),
+            10 => Some(self.pack_undervoltage),
+            _ => None,
+        }
+    }
+
     pub fn set_value_at(&mut self, idx: usize, value: i16) {
         match idx {
             0 => self.short_circuit = value,
@@ -43,6 +75,13 @@ impl BatteryProtect {
         }
         Ok(protect)
     }
+
+    pub fn to_message(&self) -> Vec<u8> {
+        // device uses big endian encoding
+        (0..11)
+            .flat_map(|i| self.value_at(i).unwrap_or_default().to_be_bytes())
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -70,7 +109,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_to_message() {
+        let protect = BatteryProtect {
+            cell_undervoltage: 4,
+            pack_undervoltage: 0x0102,
+            ..Default::default()
+        };
+        let msg = protect.to_message();
+        assert_eq!(msg.len(), 22);
+        assert_eq!(BatteryProtect::parse_message(&msg), Ok(protect));
+    }
+
     use super::*;
 }
 
 use super::{util::i16_from_bytes, ParseError, ParseResult};
+use serde::{Deserialize, Serialize};
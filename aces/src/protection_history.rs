@@ -0,0 +1,365 @@
+/// An increment of a protection counter of `BatteryProtect` between two
+/// polls, with the state of the battery when it was seen.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct ProtectionEvent {
+    /// The time of the poll that saw the increment.
+    pub timestamp: DateTime<FixedOffset>,
+    pub battery: String,
+    /// One of `BatteryProtect::FIELD_NAMES`.
+    pub counter: String,
+    /// How many times the counter incremented since the previous poll.
+    pub increment: i32,
+    /// The count after the increment.
+    pub count: i16,
+    pub protection_of_state: ProtectionOfState,
+    /// Cell voltages (mV).
+    pub cell_voltages: Vec<i16>,
+    /// NTC temperatures (0.1 °C).
+    pub ntc: Vec<i16>,
+}
+
+/// Remembers the previous `BatteryProtect` of each battery to turn counter
+/// increments into `ProtectionEvent`s.
+///
+/// With a state file the counters survive restarts, so increments that
+/// happened while nothing was monitoring are reported on the first poll.
+/// With a timeline file every event is appended to it (JSON lines), see
+/// `ProtectionTimeline`.
+#[derive(Default)]
+pub struct ProtectionTracker {
+    previous: BTreeMap<String, BatteryProtect>,
+    state: Option<StateFile>,
+    timeline: Option<PathBuf>,
+}
+
+/// The recorded protection events, oldest first.
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+pub struct ProtectionTimeline {
+    pub events: Vec<ProtectionEvent>,
+}
+
+/// Selects events of a `ProtectionTimeline`, every field set must match.
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+pub struct TimelineFilter {
+    pub battery: Option<String>,
+    pub counter: Option<String>,
+    /// The first time included.
+    pub since: Option<DateTime<FixedOffset>>,
+    /// The first time excluded.
+    pub until: Option<DateTime<FixedOffset>>,
+}
+
+impl ProtectionTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a tracker using the configured files, loading the counters
+    /// from the state file when it exists. The state file is written when
+    /// the counters change.
+    pub fn from_config(config: &ProtectionConfig) -> Result<Self> {
+        let mut tracker = Self::new();
+        (tracker.previous, tracker.state) =
+            StateFile::open(config.state.as_ref(), chrono::Duration::zero())?;
+        tracker.timeline = config.timeline.clone();
+        Ok(tracker)
+    }
+
+    /// The counters last seen for a battery.
+    pub fn previous(&self, battery: &str) -> Option<&BatteryProtect> {
+        self.previous.get(battery)
+    }
+
+    /// Compares the counters of the snapshot with the previous ones of the
+    /// battery, returning an event for each counter that incremented.
+    ///
+    /// The first snapshot of a battery only sets the baseline. A counter
+    /// going down (the counters were cleared) is not an event either.
+    pub fn update(&mut self, battery: &str, snapshot: &Snapshot) -> Result<Vec<ProtectionEvent>> {
+        let current = &snapshot.protect;
+        let mut events = Vec::new();
+        if let Some(previous) = self.previous.get(battery) {
+            if previous == current {
+                return Ok(events);
+            }
+            for (idx, counter) in BatteryProtect::FIELD_NAMES.iter().enumerate() {
+                let (before, after) = (previous.value_at(idx), current.value_at(idx));
+                if after < before {
+                    log::info!("{} counter {} was reset", battery, counter);
+                }
+                let increment =
+                    after.unwrap_or_default() as i32 - before.unwrap_or_default() as i32;
+                if increment > 0 {
+                    events.push(ProtectionEvent {
+                        timestamp: snapshot.timestamp,
+                        battery: battery.to_string(),
+                        counter: counter.to_string(),
+                        increment,
+                        count: after.unwrap_or_default(),
+                        protection_of_state: snapshot.detail.protection_of_state,
+                        cell_voltages: snapshot.voltage.clone(),
+                        ntc: snapshot.detail.list_ntc.clone(),
+                    });
+                }
+            }
+        }
+
+        self.previous.insert(battery.to_string(), current.clone());
+        if let Some(state) = &mut self.state {
+            state.save(&self.previous, snapshot.timestamp)?;
+        }
+        if let Some(path) = &self.timeline {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            for event in &events {
+                writeln!(file, "{}", serde_json::to_string(event)?)?;
+            }
+        }
+        Ok(events)
+    }
+}
+
+impl ProtectionTimeline {
+    /// Reads a timeline written by `ProtectionTracker`, one event per line.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut events = Vec::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            events.push(serde_json::from_str(line)?);
+        }
+        Ok(ProtectionTimeline { events })
+    }
+
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// The events matching the filter, oldest first.
+    pub fn query<'t>(
+        &'t self,
+        filter: &'t TimelineFilter,
+    ) -> impl Iterator<Item = &'t ProtectionEvent> + 't {
+        self.events.iter().filter(|event| filter.matches(event))
+    }
+
+    /// The total increments of each counter among the events matching the
+    /// filter.
+    pub fn totals(&self, filter: &TimelineFilter) -> BTreeMap<String, i64> {
+        let mut totals = BTreeMap::new();
+        for event in self.query(filter) {
+            *totals.entry(event.counter.clone()).or_default() += event.increment as i64;
+        }
+        totals
+    }
+}
+
+impl TimelineFilter {
+    pub fn matches(&self, event: &ProtectionEvent) -> bool {
+        self.battery.as_ref().is_none_or(|b| *b == event.battery)
+            && self.counter.as_ref().is_none_or(|c| *c == event.counter)
+            && self.since.is_none_or(|since| event.timestamp >= since)
+            && self.until.is_none_or(|until| event.timestamp < until)
+    }
+}
+
+impl Display for ProtectionEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let state = ProtectionOfState::NAMES
+            .get(self.protection_of_state.0 as usize)
+            .map_or_else(|| self.protection_of_state.0.to_string(), |n| n.to_string());
+        write!(
+            f,
+            "{} {} {} +{} ({}) state {}",
+            self.timestamp.to_rfc3339(),
+            self.battery,
+            self.counter,
+            self.increment,
+            self.count,
+            state
+        )?;
+        if let (Some(min), Some(max)) = (
+            self.cell_voltages.iter().min(),
+            self.cell_voltages.iter().max(),
+        ) {
+            write!(f, " cells {}..{} mV", min, max)?;
+        }
+        if !self.ntc.is_empty() {
+            let ntc: Vec<_> = self
+                .ntc
+                .iter()
+                .map(|t| format!("{:.1}", *t as f32 / 10.0))
+                .collect();
+            write!(f, " ntc {} °C", ntc.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_update() {
+        let mut tracker = ProtectionTracker::new();
+        let mut protect = BatteryProtect {
+            cell_overvoltage: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            tracker.update("a", &snapshot(0, protect.clone())).unwrap(),
+            []
+        );
+        assert_eq!(
+            tracker.update("a", &snapshot(1, protect.clone())).unwrap(),
+            []
+        );
+
+        protect.cell_overvoltage = 4;
+        protect.short_circuit = 1;
+        let events = tracker.update("a", &snapshot(2, protect.clone())).unwrap();
+        assert_eq!(
+            events.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            [
+                "2026-10-18T10:02:00+02:00 a short_circuit +1 (1) state cov \
+cells 3412..3650 mV ntc 25.0 24.8 °C",
+                "2026-10-18T10:02:00+02:00 a cell_overvoltage +2 (4) state cov \
+cells 3412..3650 mV ntc 25.0 24.8 °C",
+            ]
+        );
+        assert_eq!(events[1].cell_voltages, [3650, 3412]);
+
+        // another battery has its own baseline, cleared counters are no event
+        assert_eq!(tracker.update("b", &snapshot(3, protect)).unwrap(), []);
+        assert_eq!(
+            tracker
+                .update("a", &snapshot(4, BatteryProtect::default()))
+                .unwrap(),
+            []
+        );
+        assert_eq!(tracker.previous("a"), Some(&BatteryProtect::default()));
+
+        // an increment wider than i16 does not overflow
+        let low = BatteryProtect {
+            cell_undervoltage: -30000,
+            ..Default::default()
+        };
+        tracker.update("c", &snapshot(5, low)).unwrap();
+        let high = BatteryProtect {
+            cell_undervoltage: 30000,
+            ..Default::default()
+        };
+        let events = tracker.update("c", &snapshot(6, high)).unwrap();
+        assert_eq!(events[0].increment, 60000);
+    }
+
+    #[test]
+    fn test_persistence() {
+        let directory =
+            std::env::temp_dir().join(format!("aces-protection-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let config = ProtectionConfig {
+            state: Some(directory.join("state.json")),
+            timeline: Some(directory.join("timeline.jsonl")),
+        };
+
+        let mut tracker = ProtectionTracker::from_config(&config).unwrap();
+        tracker
+            .update("a", &snapshot(0, BatteryProtect::default()))
+            .unwrap();
+
+        // a restart keeps the baseline, so the first poll reports the increment
+        let mut tracker = ProtectionTracker::from_config(&config).unwrap();
+        let protect = BatteryProtect {
+            high_temp_charging: 1,
+            ..Default::default()
+        };
+        let events = tracker.update("a", &snapshot(1, protect.clone())).unwrap();
+        assert_eq!(events.len(), 1);
+        let protect = BatteryProtect {
+            high_temp_charging: 2,
+            ..protect
+        };
+        tracker.update("a", &snapshot(3, protect)).unwrap();
+
+        let timeline = ProtectionTimeline::load(config.timeline.unwrap()).unwrap();
+        assert_eq!(timeline.events.len(), 2);
+        assert_eq!(timeline.events[0], events[0]);
+        let filter = TimelineFilter {
+            since: Some(time(2)),
+            ..Default::default()
+        };
+        assert_eq!(timeline.query(&filter).count(), 1);
+        assert_eq!(timeline.query(&filter).next().unwrap().count, 2);
+    }
+
+    #[test]
+    fn test_query() {
+        let mut tracker = ProtectionTracker::new();
+        let mut events = Vec::new();
+        for (battery, minute, short_circuit, cell_undervoltage) in [
+            ("a", 0, 0, 0),
+            ("b", 0, 0, 0),
+            ("a", 1, 1, 0),
+            ("b", 2, 0, 3),
+            ("a", 3, 2, 1),
+        ] {
+            let protect = BatteryProtect {
+                short_circuit,
+                cell_undervoltage,
+                ..Default::default()
+            };
+            events.extend(tracker.update(battery, &snapshot(minute, protect)).unwrap());
+        }
+        let timeline = ProtectionTimeline { events };
+        assert_eq!(timeline.events.len(), 4);
+
+        let all = TimelineFilter::default();
+        assert_eq!(
+            timeline.totals(&all),
+            BTreeMap::from([
+                ("cell_undervoltage".to_string(), 4),
+                ("short_circuit".to_string(), 2)
+            ])
+        );
+        let filter = TimelineFilter {
+            battery: Some("a".to_string()),
+            counter: Some("short_circuit".to_string()),
+            until: Some(time(3)),
+            ..Default::default()
+        };
+        let matching: Vec<_> = timeline.query(&filter).collect();
+        assert_eq!(matching.len(), 1);
+        assert_eq!(matching[0].timestamp, time(1));
+    }
+
+    fn time(minute: u32) -> DateTime<FixedOffset> {
+        test_time(minute as i64 * 60)
+    }
+
+    fn snapshot(minute: u32, protect: BatteryProtect) -> Snapshot {
+        SnapshotBuilder::at(minute as i64 * 60)
+            .cells(&[3650, 3412])
+            .detail(BatteryDetail {
+                protection_of_state: ProtectionOfState::COV,
+                list_ntc: vec![250, 248],
+                ..Default::default()
+            })
+            .protect(protect)
+            .build()
+    }
+
+    use super::*;
+    use crate::snapshot::{test_time, SnapshotBuilder};
+    use crate::BatteryDetail;
+}
+
+use crate::util::StateFile;
+use crate::{BatteryProtect, ProtectionConfig, ProtectionOfState, Result, Snapshot};
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, OpenOptions};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
@@ -1,4 +1,4 @@
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
 pub struct ProtectionOfState(pub i16);
 
 impl ProtectionOfState {
@@ -25,4 +25,19 @@ impl ProtectionOfState {
     pub const OCD: Self = ProtectionOfState(10);
     /// Short Circuit
     pub const SCD: Self = ProtectionOfState(11);
+
+    /// The abbreviations, in order of value.
+    pub const NAMES: [&'static str; 12] = [
+        "none", "cov", "cuv", "pov", "puv", "otc", "utc", "otd", "utd", "occ", "ocd", "scd",
+    ];
+
+    /// Looks up a state by its abbreviation (e.g. `otc`), ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .position(|n| n.eq_ignore_ascii_case(name))
+            .map(|idx| ProtectionOfState(idx as i16))
+    }
 }
+
+use serde::{Deserialize, Serialize};
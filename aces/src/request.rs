@@ -1,4 +1,4 @@
-#[derive(Debug)]
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
 pub enum Request {
     Clear,
     BatteryDetail,
@@ -29,6 +29,11 @@ impl Request {
         Err(ParseError::InvalidData)
     }
 
+    /// The register read, answered by a response from the same register.
+    pub fn register(&self) -> u8 {
+        self.bytes()[2]
+    }
+
     pub fn bytes(&self) -> &'static [u8] {
         match self {
             Self::Clear => &[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // "00000000000000"
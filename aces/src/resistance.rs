@@ -0,0 +1,490 @@
+/// The DC internal resistance measured over one current step.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct ResistanceEstimate {
+    /// The second snapshot of the step.
+    pub timestamp: DateTime<FixedOffset>,
+    /// The current step (A), positive towards charging.
+    pub step: f64,
+    /// The pack resistance (mΩ).
+    pub pack: f64,
+    /// How far the pack resistance can be trusted, from 0 to 1.
+    pub confidence: f64,
+    /// The resistance of each cell (mΩ).
+    pub cells: Vec<f64>,
+    /// How far each cell resistance can be trusted, from 0 to 1.
+    pub cell_confidence: Vec<f64>,
+}
+
+/// The resistances averaged over one day, see `ResistanceEstimator::daily`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct DailyResistance {
+    pub date: NaiveDate,
+    /// The current steps seen that day, confident or not.
+    pub estimates: u32,
+    /// The pack resistance (mΩ).
+    pub pack: Option<f64>,
+    /// The resistance of each cell (mΩ).
+    pub cells: Vec<Option<f64>>,
+}
+
+/// How the resistance of the pack or a cell evolved, see
+/// `ResistanceEstimator::trend`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct ResistanceTrend {
+    /// `pack` or `cell <n>`.
+    pub name: String,
+    /// The mean over the first `window` days with estimates (mΩ).
+    pub baseline: f64,
+    /// The mean over the last `window` days with estimates (mΩ).
+    pub latest: f64,
+    /// The slope of the daily means (mΩ per 30 days).
+    pub slope: f64,
+}
+
+/// A confidence weighted mean.
+#[derive(PartialEq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+struct Mean {
+    sum: f64,
+    weight: f64,
+}
+
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ResistanceDay {
+    estimates: u32,
+    pack: Mean,
+    cells: Vec<Mean>,
+}
+
+/// Estimates the DC internal resistance of the pack and of each cell from
+/// the voltage change over a current step, ΔV/ΔI between two consecutive
+/// snapshots.
+///
+/// A step has to change the current by at least `min_step` within
+/// `max_interval`, so the state of charge barely moves in between. The
+/// confidence of an estimate is one minus the relative error the voltage
+/// resolution (10 mV for the pack, 1 mV for a cell) allows, so small steps
+/// and low resistances are trusted less. Estimates below `min_confidence`
+/// are not recorded; the others are averaged per day, weighted by their
+/// confidence, and kept in the state file across restarts.
+///
+/// The cell voltages and the pack current are read from two registers, so
+/// the cell estimates are only as good as those registers are polled
+/// together.
+pub struct ResistanceEstimator {
+    config: ResistanceConfig,
+    days: BTreeMap<NaiveDate, ResistanceDay>,
+    previous: Option<Snapshot>,
+    file: Option<StateFile>,
+}
+
+impl Mean {
+    fn add(&mut self, value: f64, weight: f64) {
+        self.sum += value * weight;
+        self.weight += weight;
+    }
+
+    fn value(&self) -> Option<f64> {
+        (self.weight > 0.0).then(|| self.sum / self.weight)
+    }
+}
+
+impl ResistanceEstimator {
+    pub fn new(config: ResistanceConfig) -> Self {
+        ResistanceEstimator {
+            config,
+            days: BTreeMap::new(),
+            previous: None,
+            file: None,
+        }
+    }
+
+    /// Creates an estimator keeping its daily means in the configured state
+    /// file, loading them when it exists. The file is written with each
+    /// estimate.
+    pub fn from_config(config: &ResistanceConfig) -> Result<Self> {
+        let mut estimator = Self::new(config.clone());
+        (estimator.days, estimator.file) =
+            StateFile::open(config.state.as_ref(), chrono::Duration::zero())?;
+        Ok(estimator)
+    }
+
+    /// The resistance over the step from `previous` to `snapshot`, if both
+    /// have refreshed voltages and the current changed enough and quickly
+    /// enough.
+    pub fn estimate(&self, previous: &Snapshot, snapshot: &Snapshot) -> Option<ResistanceEstimate> {
+        if !is_fresh(previous) || !is_fresh(snapshot) {
+            return None;
+        }
+        let seconds = (snapshot.timestamp - previous.timestamp).num_seconds();
+        if seconds <= 0 || seconds > self.config.max_interval as i64 {
+            return None;
+        }
+        let step = (snapshot.detail.current as f64 - previous.detail.current as f64) / 100.0;
+        if step.abs() < self.config.min_step {
+            return None;
+        }
+
+        let pack_step =
+            (snapshot.detail.total_voltage as f64 - previous.detail.total_voltage as f64) * 10.0;
+        let pack = pack_step / step;
+        let mut cells = Vec::new();
+        let mut cell_confidence = Vec::new();
+        if snapshot.voltage.len() == previous.voltage.len() {
+            for (before, after) in previous.voltage.iter().zip(&snapshot.voltage) {
+                let resistance = (*after as f64 - *before as f64) / step;
+                cells.push(resistance);
+                cell_confidence.push(confidence(resistance, 1.0, step));
+            }
+        }
+        Some(ResistanceEstimate {
+            timestamp: snapshot.timestamp,
+            step,
+            pack,
+            confidence: confidence(pack, 10.0, step),
+            cells,
+            cell_confidence,
+        })
+    }
+
+    /// Estimates the resistance over the step since the previous snapshot,
+    /// recording the estimates confident enough. Snapshots with stale
+    /// voltages are skipped.
+    pub fn update(&mut self, snapshot: &Snapshot) -> Result<Option<ResistanceEstimate>> {
+        if !is_fresh(snapshot) {
+            return Ok(None);
+        }
+        let estimate = match &self.previous {
+            Some(previous) => self.estimate(previous, snapshot),
+            None => None,
+        };
+        self.previous = Some(snapshot.clone());
+        let Some(estimate) = estimate else {
+            return Ok(None);
+        };
+
+        let min_confidence = self.config.min_confidence;
+        let day = self
+            .days
+            .entry(estimate.timestamp.date_naive())
+            .or_default();
+        day.estimates += 1;
+        if estimate.confidence >= min_confidence {
+            day.pack.add(estimate.pack, estimate.confidence);
+        }
+        if day.cells.len() < estimate.cells.len() {
+            day.cells.resize(estimate.cells.len(), Mean::default());
+        }
+        for ((mean, resistance), confidence) in day
+            .cells
+            .iter_mut()
+            .zip(&estimate.cells)
+            .zip(&estimate.cell_confidence)
+        {
+            if *confidence >= min_confidence {
+                mean.add(*resistance, *confidence);
+            }
+        }
+        if let Some(file) = &mut self.file {
+            file.save(&self.days, snapshot.timestamp)?;
+        }
+        Ok(Some(estimate))
+    }
+
+    /// The daily means, oldest first.
+    pub fn daily(&self) -> Vec<DailyResistance> {
+        self.days
+            .iter()
+            .map(|(date, day)| DailyResistance {
+                date: *date,
+                estimates: day.estimates,
+                pack: day.pack.value(),
+                cells: day.cells.iter().map(Mean::value).collect(),
+            })
+            .collect()
+    }
+
+    /// The trend of the pack, then of each cell, for those with estimates.
+    pub fn trend(&self) -> Vec<ResistanceTrend> {
+        let cells = self
+            .days
+            .values()
+            .map(|day| day.cells.len())
+            .max()
+            .unwrap_or(0);
+        let mut trends = Vec::new();
+        trends.extend(self.trend_of("pack".to_string(), |day| day.pack));
+        for cell in 0..cells {
+            trends.extend(self.trend_of(format!("cell {}", cell + 1), |day| {
+                day.cells.get(cell).copied().unwrap_or_default()
+            }));
+        }
+        trends
+    }
+
+    /// Explains which cells rose more than `rise_threshold` above the median
+    /// rise of all cells, the earliest sign of a failing cell.
+    pub fn warnings(&self) -> Vec<String> {
+        let cells: Vec<ResistanceTrend> = self
+            .trend()
+            .into_iter()
+            .filter(|trend| trend.name != "pack")
+            .collect();
+        let mut rises: Vec<f64> = cells.iter().map(ResistanceTrend::rise).collect();
+        rises.sort_by(f64::total_cmp);
+        let Some(median) = rises.get(rises.len() / 2).copied() else {
+            return Vec::new();
+        };
+
+        cells
+            .iter()
+            .filter(|trend| trend.rise() - median > self.config.rise_threshold)
+            .map(|trend| {
+                format!(
+                    "{} rose {:+.1} % from {:.2} to {:.2} mΩ ({:+.3} mΩ/month), the median cell {:+.1} %",
+                    trend.name,
+                    trend.rise() * 100.0,
+                    trend.baseline,
+                    trend.latest,
+                    trend.slope,
+                    median * 100.0
+                )
+            })
+            .collect()
+    }
+
+    fn trend_of<F>(&self, name: String, mean: F) -> Option<ResistanceTrend>
+    where
+        F: Fn(&ResistanceDay) -> Mean,
+    {
+        let days: Vec<(NaiveDate, Mean)> = self
+            .days
+            .iter()
+            .map(|(date, day)| (*date, mean(day)))
+            .filter(|(_, mean)| mean.weight > 0.0)
+            .collect();
+        let first = days.first()?.0;
+        let window = (self.config.window as usize).max(1);
+        let average = |days: &[(NaiveDate, Mean)]| {
+            let mut total = Mean::default();
+            for (_, mean) in days {
+                total.sum += mean.sum;
+                total.weight += mean.weight;
+            }
+            total.value()
+        };
+
+        // weighted least squares over the daily means
+        let points: Vec<(f64, f64, f64)> = days
+            .iter()
+            .filter_map(|(date, mean)| {
+                let x = (*date - first).num_days() as f64;
+                Some((x, mean.value()?, mean.weight))
+            })
+            .collect();
+        let slope = weighted_slope(&points).map_or(0.0, |slope| slope * 30.0);
+
+        Some(ResistanceTrend {
+            name,
+            baseline: average(&days[..window.min(days.len())])?,
+            latest: average(&days[days.len().saturating_sub(window)..])?,
+            slope,
+        })
+    }
+}
+
+impl ResistanceTrend {
+    /// The latest mean relative to the baseline, e.g. 0.1 for 10 % higher.
+    pub fn rise(&self) -> f64 {
+        if self.baseline > 0.0 {
+            self.latest / self.baseline - 1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Sink for ResistanceEstimator {
+    fn handle(&mut self, event: &Event) -> Result<()> {
+        match event {
+            Event::Snapshot(snapshot) => self.update(snapshot).map(|_| ()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Display for ResistanceEstimate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} step {:+.2} A pack {:.2} mΩ ({:.0} %)",
+            self.timestamp.to_rfc3339(),
+            self.step,
+            self.pack,
+            self.confidence * 100.0
+        )?;
+        for (resistance, confidence) in self.cells.iter().zip(&self.cell_confidence) {
+            write!(f, " {:.2} ({:.0} %)", resistance, confidence * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for ResistanceTrend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<8}baseline {:>6.2} mΩ  latest {:>6.2} mΩ  {:>+6.1} %  {:>+7.3} mΩ/month",
+            self.name,
+            self.baseline,
+            self.latest,
+            self.rise() * 100.0,
+            self.slope
+        )
+    }
+}
+
+/// How far a resistance (mΩ) measured over a current step (A) with a
+/// voltage resolution (mV) can be trusted: one minus the relative error of
+/// two readings off by the resolution.
+fn confidence(resistance: f64, resolution: f64, step: f64) -> f64 {
+    if resistance <= 0.0 {
+        return 0.0;
+    }
+    let error = resolution * std::f64::consts::SQRT_2 / step.abs();
+    (1.0 - error / resistance).clamp(0.0, 1.0)
+}
+
+/// Whether the voltages and the current of `snapshot` were all polled for it.
+fn is_fresh(snapshot: &Snapshot) -> bool {
+    snapshot.is_refreshed(Register::Detail) && snapshot.is_refreshed(Register::Voltage)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_estimate() {
+        let estimator = ResistanceEstimator::new(ResistanceConfig::default());
+        // a 50 A load switched on: the pack drops 400 mV, cell 3 twice as
+        // much as the others
+        let before = snapshot(at(0, 0), 1330, 0, [3325, 3325, 3325, 3325]);
+        let after = snapshot(at(0, 5), 1290, -5000, [3245, 3245, 3165, 3245]);
+        let estimate = estimator.estimate(&before, &after).unwrap();
+
+        assert_eq!(estimate.step, -50.0);
+        assert_eq!(estimate.pack, 8.0);
+        assert_eq!(estimate.cells, [1.6, 1.6, 3.2, 1.6]);
+        let error = 10.0 * std::f64::consts::SQRT_2 / 50.0;
+        assert!((estimate.confidence - (1.0 - error / 8.0)).abs() < 1e-12);
+        assert!(estimate.cell_confidence[2] > estimate.cell_confidence[0]);
+
+        // too small a step, or too slow
+        let small = snapshot(at(0, 5), 1326, -400, [3320, 3320, 3320, 3320]);
+        assert_eq!(estimator.estimate(&before, &small), None);
+        let slow = snapshot(at(0, 60), 1290, -5000, [3245, 3245, 3165, 3245]);
+        assert_eq!(estimator.estimate(&before, &slow), None);
+
+        // a step wider than i16 does not overflow
+        let charging = snapshot(at(0, 0), 1330, -30000, [3325; 4]);
+        let discharging = snapshot(at(0, 5), 1390, 30000, [3337; 4]);
+        let estimate = estimator.estimate(&charging, &discharging).unwrap();
+        assert_eq!(estimate.step, 600.0);
+        assert_eq!(estimate.pack, 1.0);
+
+        // the cached cells of a snapshot that only polled the detail
+        let mut stale = after.clone();
+        stale.refreshed = vec![Register::Detail];
+        assert_eq!(estimator.estimate(&before, &stale), None);
+        assert_eq!(estimator.estimate(&stale, &before), None);
+
+        // a stale snapshot does not replace the previous one
+        let mut estimator = ResistanceEstimator::new(ResistanceConfig::default());
+        assert_eq!(estimator.update(&before).unwrap(), None);
+        assert_eq!(estimator.update(&stale).unwrap(), None);
+        assert!(estimator.update(&after).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_confidence() {
+        // a larger step gives a better estimate, noise a useless one
+        assert!(confidence(1.0, 1.0, 100.0) > confidence(1.0, 1.0, 10.0));
+        assert_eq!(confidence(0.1, 1.0, 10.0), 0.0);
+        assert_eq!(confidence(-1.0, 1.0, 100.0), 0.0);
+    }
+
+    #[test]
+    fn test_trend() {
+        let mut estimator = ResistanceEstimator::new(ResistanceConfig {
+            window: 2,
+            ..Default::default()
+        });
+        // cell 4 rises from 1.6 to 3.04 mΩ over ten days, the others stay
+        for day in 0..10 {
+            let cell = 3245 - 8 * day as i16;
+            estimator
+                .update(&snapshot(at(day, 0), 1330, 0, [3325; 4]))
+                .unwrap();
+            estimator
+                .update(&snapshot(
+                    at(day, 5),
+                    1290 - 8 * day as i16 / 10,
+                    -5000,
+                    [3245, 3245, 3245, cell],
+                ))
+                .unwrap();
+        }
+
+        let daily = estimator.daily();
+        assert_eq!(daily.len(), 10);
+        assert_eq!(daily[0].estimates, 1);
+        assert!((daily[9].cells[3].unwrap() - 3.04).abs() < 1e-9);
+
+        let trend = estimator.trend();
+        assert_eq!(
+            trend.iter().map(|t| t.name.as_str()).collect::<Vec<_>>(),
+            ["pack", "cell 1", "cell 2", "cell 3", "cell 4"]
+        );
+        assert!(trend[1].slope.abs() < 1e-9);
+        assert!(trend[1].rise().abs() < 1e-9);
+        assert!((trend[4].slope - 0.16 * 30.0).abs() < 1e-9);
+
+        let warnings = estimator.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("cell 4 rose "), "{}", warnings[0]);
+    }
+
+    /// Seconds after 10:00 on a day after October 1, 2026.
+    fn at(day: i64, seconds: i64) -> DateTime<FixedOffset> {
+        test_time((day - 17) * 24 * 3600 + seconds)
+    }
+
+    fn snapshot(
+        timestamp: DateTime<FixedOffset>,
+        total_voltage: i16,
+        current: i16,
+        cells: [i16; 4],
+    ) -> Snapshot {
+        SnapshotBuilder::at(0)
+            .timestamp(timestamp)
+            .cells(&cells)
+            .detail(BatteryDetail {
+                total_voltage,
+                current,
+                ..Default::default()
+            })
+            .build()
+    }
+
+    use super::*;
+    use crate::snapshot::{test_time, SnapshotBuilder};
+    use crate::BatteryDetail;
+}
+
+use crate::{
+    util::{weighted_slope, StateFile},
+    Event, Register, ResistanceConfig, Result, Sink, Snapshot,
+};
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
@@ -0,0 +1,236 @@
+/// How long the responder waits for a write before it is idle.
+pub const RESPONDER_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Produces the frames a mock battery responds with.
+pub trait ResponseSource {
+    /// The complete response frame for `request`, empty if there is none.
+    fn response(&mut self, request: &Request) -> Vec<u8>;
+}
+
+/// Responds with the same frames, captured from a real battery.
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+pub struct StaticResponses;
+
+#[derive(Eq, PartialEq, Clone)]
+pub enum ResponderState {
+    Disconnected,
+    /// Waiting for the first write, sending unsolicited responses meanwhile.
+    Notify(Request),
+    /// Collecting the chunks of a request.
+    ReadRequest(Vec<u8>),
+}
+
+/// The mock battery logic, independent of the BLE stack and of time.
+///
+/// The adapter feeds it connection changes and written chunks, sends back
+/// every returned notification, and calls `idle` whenever no write arrived
+/// within `RESPONDER_TIMEOUT`.
+pub struct Responder<S>
+where
+    S: ResponseSource,
+{
+    source: S,
+    state: ResponderState,
+}
+
+impl<S> ResponseSource for Box<S>
+where
+    S: ResponseSource + ?Sized,
+{
+    fn response(&mut self, request: &Request) -> Vec<u8> {
+        (**self).response(request)
+    }
+}
+
+impl ResponseSource for StaticResponses {
+    fn response(&mut self, request: &Request) -> Vec<u8> {
+        match request {
+            Request::Clear => Vec::new(),
+            Request::BatteryDetail => vec![
+                0xdd, 0x03, 0x00, 0x1d, 0x05, 0x38, 0x02, 0x83, 0x17, 0x5c, 0x27, 0xde, 0x00, 0x09,
+                0x2b, 0x94, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x3b, 0x03, 0x04, 0x03, 0x0b,
+                0x7f, 0x0b, 0x6c, 0x0b, 0x69, 0xfb, 0x07, 0x77,
+            ],
+            Request::BatteryVoltage => vec![
+                0xdd, 0x04, 0x00, 0x08, 0x0d, 0xe2, 0x0d, 0xdc, 0x0d, 0xec, 0x0d, 0xed, 0xfc, 0x2d,
+                0x77,
+            ],
+            Request::BatteryProtect => vec![
+                0xdd, 0xaa, 0x00, 0x16, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xe6,
+                0x77,
+            ],
+        }
+    }
+}
+
+impl<S> Responder<S>
+where
+    S: ResponseSource,
+{
+    pub fn new(source: S) -> Self {
+        Responder {
+            source,
+            state: ResponderState::Disconnected,
+        }
+    }
+
+    pub fn state(&self) -> &ResponderState {
+        &self.state
+    }
+
+    pub fn source(&mut self) -> &mut S {
+        &mut self.source
+    }
+
+    pub fn connected(&mut self) {
+        if self.state == ResponderState::Disconnected {
+            self.state = ResponderState::Notify(Request::BatteryDetail);
+        }
+    }
+
+    pub fn disconnected(&mut self) {
+        self.state = ResponderState::Disconnected;
+    }
+
+    /// Handles a chunk written by the client, returns the notifications to send.
+    ///
+    /// Every chunk clears the previous response (an empty notification), and
+    /// each complete request is answered. Invalid requests are dropped.
+    pub fn write(&mut self, chunk: &[u8]) -> Vec<Vec<u8>> {
+        let mut buff = match std::mem::replace(&mut self.state, ResponderState::Disconnected) {
+            ResponderState::Disconnected => return Vec::new(),
+            ResponderState::Notify(_) => Vec::new(),
+            ResponderState::ReadRequest(buff) => buff,
+        };
+        buff.extend_from_slice(chunk);
+
+        // clear the response
+        let mut notifications = vec![Vec::new()];
+        while Request::is_complete_request(&buff) {
+            let rest = buff.split_off(REQUEST_LEN);
+            match Request::parse_request(&buff) {
+                Ok(req) => notifications.push(self.source.response(&req)),
+                Err(err) => log::debug!("dropping invalid request {:x?}: {}", buff, err),
+            }
+            buff = rest;
+        }
+
+        self.state = ResponderState::ReadRequest(buff);
+        notifications
+    }
+
+    /// Handles a timeout waiting for a write, returns the notifications to send.
+    ///
+    /// Until the client writes its first request, the battery keeps cycling
+    /// through its responses.
+    pub fn idle(&mut self) -> Vec<Vec<u8>> {
+        let ResponderState::Notify(req) = &self.state else {
+            return Vec::new();
+        };
+
+        let resp = self.source.response(req);
+        let next = match req {
+            Request::BatteryDetail => Request::BatteryProtect,
+            Request::BatteryProtect => Request::BatteryVoltage,
+            _ => Request::BatteryDetail,
+        };
+        self.state = ResponderState::Notify(next);
+        vec![resp]
+    }
+}
+
+impl fmt::Debug for ResponderState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResponderState::Disconnected => write!(f, "Disconnected"),
+            ResponderState::Notify(req) => write!(f, "Notify({:?})", req),
+            ResponderState::ReadRequest(req) => write!(f, "ReadRequest({:x?})", req),
+        }
+    }
+}
+
+/// The length of every request.
+const REQUEST_LEN: usize = 7;
+
+#[cfg(test)]
+mod tests {
+    /// The empty notification clearing the previous response.
+    const CLEAR: Vec<u8> = Vec::new();
+
+    #[test]
+    fn test_unsolicited_notifications() {
+        let mut responder = Responder::new(StaticResponses);
+        assert_eq!(responder.idle(), Vec::<Vec<u8>>::new());
+
+        responder.connected();
+        let detail = responder.idle();
+        let protect = responder.idle();
+        let voltage = responder.idle();
+        assert_eq!(responder.idle(), detail);
+
+        for (notif, identifier) in [(detail, 0x03), (protect, 0xaa), (voltage, 0x04)] {
+            assert_eq!(notif.len(), 1);
+            assert_eq!(notif[0][1], identifier);
+            assert!(Response::parse_response(&notif[0]).is_ok());
+        }
+
+        responder.disconnected();
+        assert_eq!(responder.state(), &ResponderState::Disconnected);
+        assert_eq!(responder.idle(), Vec::<Vec<u8>>::new());
+    }
+
+    #[test]
+    fn test_requests() {
+        let mut responder = Responder::new(StaticResponses);
+        assert_eq!(
+            responder.write(Request::Clear.bytes()),
+            Vec::<Vec<u8>>::new()
+        );
+
+        responder.connected();
+        assert_eq!(responder.write(Request::Clear.bytes()), vec![CLEAR, CLEAR]);
+        // no more unsolicited notifications
+        assert_eq!(responder.idle(), Vec::<Vec<u8>>::new());
+
+        let notif = responder.write(Request::BatteryVoltage.bytes());
+        assert_eq!(
+            notif,
+            vec![CLEAR, StaticResponses.response(&Request::BatteryVoltage)]
+        );
+        assert_eq!(responder.state(), &ResponderState::ReadRequest(Vec::new()));
+    }
+
+    #[test]
+    fn test_partial_requests() {
+        let mut responder = Responder::new(StaticResponses);
+        responder.connected();
+
+        let req = Request::BatteryProtect.bytes();
+        assert_eq!(responder.write(&req[..3]), vec![CLEAR]);
+        assert_eq!(
+            responder.state(),
+            &ResponderState::ReadRequest(req[..3].to_vec())
+        );
+        assert_eq!(
+            responder.write(&req[3..]),
+            vec![CLEAR, StaticResponses.response(&Request::BatteryProtect)]
+        );
+
+        // an invalid request is dropped, the one after it is answered
+        let mut chunk = vec![0xdd, 0xa5, 0x05, 0x00, 0xff, 0xfb, 0x77];
+        chunk.extend_from_slice(Request::BatteryDetail.bytes());
+        chunk.push(0xdd);
+        assert_eq!(
+            responder.write(&chunk),
+            vec![CLEAR, StaticResponses.response(&Request::BatteryDetail)]
+        );
+        assert_eq!(responder.state(), &ResponderState::ReadRequest(vec![0xdd]));
+    }
+
+    use super::*;
+    use crate::Response;
+}
+
+use crate::Request;
+use std::{fmt, time::Duration};
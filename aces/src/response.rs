@@ -43,6 +43,22 @@ impl Response {
 
         Err(ParseError::InvalidData)
     }
+
+    /// Encodes the complete response frame.
+    pub fn bytes(&self) -> Vec<u8> {
+        let (register, payload) = match self {
+            Response::BatteryDetail(detail) => (0x03, detail.to_message()),
+            Response::BatteryProtect(protect) => (0xaa, protect.to_message()),
+            Response::BatteryVoltage(voltage) => (0x04, voltage.to_message()),
+        };
+        let status = 0x00;
+
+        let mut frame = vec![0xdd, register, status, payload.len() as u8];
+        frame.extend_from_slice(&payload);
+        frame.extend_from_slice(&calculate_checksum(&payload, status).to_be_bytes());
+        frame.push(0x77);
+        frame
+    }
 }
 
 #[cfg(test)]
@@ -124,11 +140,23 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_bytes() {
+        for req in [
+            Request::BatteryDetail,
+            Request::BatteryProtect,
+            Request::BatteryVoltage,
+        ] {
+            let frame = StaticResponses.response(&req);
+            assert_eq!(Response::parse_response(&frame).unwrap().bytes(), frame);
+        }
+    }
+
     use super::*;
-    use crate::ProtectionOfState;
+    use crate::{ProtectionOfState, Request, ResponseSource, StaticResponses};
 }
 
 use crate::{
-    util::u16_from_bytes, verify_checksum, BatteryDetail, BatteryProtect, BatteryVoltage,
-    ParseError, ParseResult,
+    calculate_checksum, util::u16_from_bytes, verify_checksum, BatteryDetail, BatteryProtect,
+    BatteryVoltage, ParseError, ParseResult,
 };
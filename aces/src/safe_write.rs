@@ -0,0 +1,437 @@
+/// The layer every frame changing the battery goes through.
+///
+/// Configurations are validated (`BatteryConfig::validate`) before anything
+/// is sent, written registers are read back, and every write, including
+/// entering and leaving factory mode, is appended to the audit log. In dry
+/// run mode nothing is written, the frames are returned instead.
+pub struct SafeWriter {
+    dry_run: bool,
+    audit_log: Option<PathBuf>,
+    user: String,
+    timeout: Duration,
+}
+
+/// The registers changed by `SafeWriter::write_config`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct WriteReport {
+    pub changes: Vec<ConfigChange>,
+    /// The frames sent or, in dry run mode, that would have been sent.
+    pub frames: Vec<Frame>,
+    pub dry_run: bool,
+}
+
+/// A line of the audit log.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub time: DateTime<FixedOffset>,
+    pub user: String,
+    pub register: u8,
+    /// The name of a configuration register.
+    pub name: Option<String>,
+    /// The value before the write, when known.
+    pub from: Option<String>,
+    /// The value written, when known.
+    pub to: Option<String>,
+    /// The frame sent (hex).
+    pub frame: String,
+    /// `verified`, `sent` (not a configuration change, so not read back) or
+    /// the error.
+    pub outcome: String,
+}
+
+#[derive(Eq, PartialEq, Debug, thiserror::Error)]
+pub enum SafeWriteError {
+    #[error("Invalid configuration: {}", join(.0))]
+    Invalid(Vec<Violation>),
+    #[error("Register {0:02x} is a configuration register, write it with restore")]
+    ConfigRegister(u8),
+    #[error("Nothing is written to the configuration in dry run")]
+    DryRun,
+}
+
+impl SafeWriter {
+    pub fn new(timeout: Duration) -> Self {
+        SafeWriter {
+            dry_run: false,
+            audit_log: None,
+            user: std::env::var("USER").unwrap_or_else(|_| "unknown".to_string()),
+            timeout,
+        }
+    }
+
+    pub fn from_config(config: &WriteConfig, timeout: Duration) -> Self {
+        let mut writer = Self::new(timeout).dry_run(config.dry_run);
+        if let Some(path) = &config.audit_log {
+            writer = writer.audit_log(path);
+        }
+        if let Some(user) = &config.user {
+            writer = writer.user(user);
+        }
+        writer
+    }
+
+    /// Only returns the frames instead of sending them.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// The file every write is appended to (JSON lines).
+    pub fn audit_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.audit_log = Some(path.into());
+        self
+    }
+
+    /// Who writes, recorded in the audit log.
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.user = user.into();
+        self
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Writes the registers of `target` that differ from `live` in one
+    /// factory mode session, then reads the configuration back to verify
+    /// them (see `write_battery_config`). Each register is audited when it is
+    /// sent, and once more with the outcome of the verification.
+    pub async fn write_config<T, C>(
+        &self,
+        supervisor: &mut ConnectionSupervisor<T, C>,
+        live: &BatteryConfig,
+        target: &BatteryConfig,
+    ) -> Result<WriteReport>
+    where
+        T: Transport,
+        C: Clock,
+    {
+        let violations = target.validate()?;
+        if !violations.is_empty() {
+            return Err(SafeWriteError::Invalid(violations).into());
+        }
+
+        let changes = live.diff(target)?;
+        let report = WriteReport {
+            frames: write_frames(&changes),
+            changes,
+            dry_run: self.dry_run,
+        };
+        if self.dry_run || report.changes.is_empty() {
+            return Ok(report);
+        }
+
+        self.check_audit_log()?;
+        let written = write_battery_config(supervisor, &report.changes, self).await;
+        let outcome = match &written {
+            Ok(_) => "verified".to_string(),
+            Err(err) => err.to_string(),
+        };
+        for change in &report.changes {
+            self.audit(AuditRecord {
+                time: Local::now().fixed_offset(),
+                user: self.user.clone(),
+                register: change.register.address,
+                name: Some(change.register.name.to_string()),
+                from: Some(change.from.clone()),
+                to: Some(change.to.clone()),
+                frame: to_hex(
+                    &Frame::write(change.register.address, change.payload.clone()).bytes(),
+                ),
+                outcome: outcome.clone(),
+            })?;
+        }
+        written?;
+        Ok(report)
+    }
+
+    /// Sends a single frame, returning the reply fragments, or `None` in dry
+    /// run mode.
+    ///
+    /// Writes of configuration registers are refused: a single register
+    /// cannot be checked against the rules between registers, so they are
+    /// written with `write_config`. Other frames are sent as they are.
+    pub async fn write_frame<T, C>(
+        &self,
+        supervisor: &mut ConnectionSupervisor<T, C>,
+        frame: &[u8],
+    ) -> Result<Option<Vec<Vec<u8>>>>
+    where
+        T: Transport,
+        C: Clock,
+    {
+        let request = Frame::parse(frame).ok().filter(Frame::is_write);
+        if let Some(request) = &request {
+            if BatteryConfig::REGISTERS
+                .iter()
+                .any(|r| r.address == request.register)
+            {
+                return Err(SafeWriteError::ConfigRegister(request.register).into());
+            }
+        }
+        if self.dry_run {
+            return Ok(None);
+        }
+
+        self.check_audit_log()?;
+        let fragments = supervisor.exchange(frame, self.timeout).await;
+        let outcome = match &fragments {
+            Ok(_) => "sent".to_string(),
+            Err(err) => err.to_string(),
+        };
+        self.audit_frame(frame, &outcome)?;
+        fragments.map(Some)
+    }
+
+    /// Appends a sent frame to the audit log, named after its register when
+    /// it is a configuration register.
+    pub(crate) fn audit_frame(&self, frame: &[u8], outcome: &str) -> Result<()> {
+        let register = frame.get(2).copied().unwrap_or_default();
+        self.audit(AuditRecord {
+            time: Local::now().fixed_offset(),
+            user: self.user.clone(),
+            register,
+            name: BatteryConfig::REGISTERS
+                .iter()
+                .find(|r| r.address == register)
+                .map(|r| r.name.to_string()),
+            from: None,
+            to: None,
+            frame: to_hex(frame),
+            outcome: outcome.to_string(),
+        })
+    }
+
+    pub(crate) fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Opens the audit log, so that a write is not sent when it could not
+    /// be recorded.
+    pub(crate) fn check_audit_log(&self) -> Result<()> {
+        if let Some(path) = &self.audit_log {
+            OpenOptions::new().create(true).append(true).open(path)?;
+        }
+        Ok(())
+    }
+
+    /// Appends a record to the audit log, which is opened for each record so
+    /// that earlier lines are never rewritten.
+    fn audit(&self, record: AuditRecord) -> Result<()> {
+        log::info!(
+            "{} wrote {} to register {:02x}: {}",
+            record.user,
+            record.frame,
+            record.register,
+            record.outcome
+        );
+        let Some(path) = &self.audit_log else {
+            return Ok(());
+        };
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", serde_json::to_string(&record)?)?;
+        Ok(())
+    }
+}
+
+impl AuditRecord {
+    /// Reads an audit log.
+    pub fn load(path: &Path) -> Result<Vec<AuditRecord>> {
+        let mut records = Vec::new();
+        for line in fs::read_to_string(path)?.lines() {
+            records.push(serde_json::from_str(line)?);
+        }
+        Ok(records)
+    }
+}
+
+fn join(violations: &[Violation]) -> String {
+    violations
+        .iter()
+        .map(Violation::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_write_config() {
+        let live = valid_config();
+        let target = BatteryConfig {
+            cell_overvoltage: 3600,
+            ..live.clone()
+        };
+        let path = audit_log("config");
+        let writer = SafeWriter::new(TIMEOUT).audit_log(&path).user("tester");
+        let mut supervisor = supervisor(MockEeprom::new(&live));
+
+        let report = block_on(writer.write_config(&mut supervisor, &live, &target)).unwrap();
+        assert_eq!(report.changes.len(), 1);
+        assert_eq!(report.frames.len(), 3);
+        assert_eq!(supervisor.transport().config(), target);
+        assert!(supervisor.transport().saved);
+
+        let records = AuditRecord::load(&path).unwrap();
+        // enter, write, save, enter and discard to read back, then the change
+        assert_eq!(
+            records.iter().map(|r| r.register).collect::<Vec<_>>(),
+            [0x00, 0x24, 0x01, 0x00, 0x01, 0x24]
+        );
+        assert!(records.iter().all(|r| r.user == "tester"));
+        assert_eq!(records[1].name.as_deref(), Some("cell_overvoltage"));
+        assert_eq!(records[1].frame, "dd5a24020e10ffbc77");
+        assert_eq!(records[1].outcome, "sent");
+        assert_eq!(records[2].frame, "dd5a01022828ffad77");
+        let change = &records[5];
+        assert_eq!(change.name.as_deref(), Some("cell_overvoltage"));
+        assert_eq!(change.from.as_deref(), Some("3650"));
+        assert_eq!(change.to.as_deref(), Some("3600"));
+        assert_eq!(change.frame, "dd5a24020e10ffbc77");
+        assert_eq!(change.outcome, "verified");
+
+        // a second write appends
+        block_on(writer.write_config(&mut supervisor, &target, &live)).unwrap();
+        assert_eq!(AuditRecord::load(&path).unwrap().len(), 12);
+    }
+
+    #[test]
+    fn test_write_config_rejects_invalid() {
+        let live = valid_config();
+        let target = BatteryConfig {
+            cell_overvoltage_release: 3700,
+            ..live.clone()
+        };
+        let mut supervisor = supervisor(MockEeprom::new(&live));
+
+        let err = block_on(SafeWriter::new(TIMEOUT).write_config(&mut supervisor, &live, &target))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid configuration: \
+cell_overvoltage_release: 3700 must be below cell_overvoltage (3650)"
+        );
+        assert_eq!(supervisor.transport().written, Vec::<Vec<u8>>::new());
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let live = valid_config();
+        let target = BatteryConfig {
+            cell_overvoltage: 3600,
+            ..live.clone()
+        };
+        let path = audit_log("dry-run");
+        let writer = SafeWriter::new(TIMEOUT).dry_run(true).audit_log(&path);
+        let mut supervisor = supervisor(MockEeprom::new(&live));
+
+        let report = block_on(writer.write_config(&mut supervisor, &live, &target)).unwrap();
+        assert!(report.dry_run);
+        assert_eq!(
+            report
+                .frames
+                .iter()
+                .map(|f| to_hex(&f.bytes()))
+                .collect::<Vec<_>>(),
+            [
+                "dd5a00025678ff3077",
+                "dd5a24020e10ffbc77",
+                "dd5a01022828ffad77"
+            ]
+        );
+        let frame = Frame::write(0xe1, vec![0x00, 0x02]).bytes();
+        assert_eq!(
+            block_on(writer.write_frame(&mut supervisor, &frame)).unwrap(),
+            None
+        );
+        assert_eq!(supervisor.transport().written, Vec::<Vec<u8>>::new());
+        assert!(!path.exists());
+
+        // reading the live configuration is still allowed, and audited
+        block_on(read_battery_config(&mut supervisor, &writer)).unwrap();
+        assert!(!supervisor.transport().saved);
+        assert_eq!(AuditRecord::load(&path).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_write_frame() {
+        let path = audit_log("frame");
+        let writer = SafeWriter::new(TIMEOUT).audit_log(&path).user("tester");
+        let mut battery = MockEeprom::new(&valid_config());
+        battery.factory_mode = true;
+        let mut supervisor = supervisor(battery);
+
+        let frame = Frame::write(0x24, vec![0x0e, 0x10]).bytes();
+        let err = block_on(writer.write_frame(&mut supervisor, &frame)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Register 24 is a configuration register, write it with restore"
+        );
+        assert!(supervisor.transport().written.is_empty());
+
+        let frame = Frame::write(0xe1, vec![0x00, 0x02]).bytes();
+        block_on(writer.write_frame(&mut supervisor, &frame)).unwrap();
+
+        let records = AuditRecord::load(&path).unwrap();
+        assert_eq!(
+            records
+                .iter()
+                .map(|r| (r.register, r.outcome.as_str()))
+                .collect::<Vec<_>>(),
+            [(0xe1, "sent")]
+        );
+    }
+
+    #[test]
+    fn test_audit_log_checked_before_sending() {
+        let path = std::env::temp_dir().join("aces-missing/audit.jsonl");
+        let writer = SafeWriter::new(TIMEOUT).audit_log(&path);
+        let mut battery = MockEeprom::new(&valid_config());
+        battery.factory_mode = true;
+        let mut supervisor = supervisor(battery);
+
+        let frame = Frame::write(0xe1, vec![0x00, 0x02]).bytes();
+        assert!(block_on(writer.write_frame(&mut supervisor, &frame)).is_err());
+        let target = BatteryConfig {
+            cell_overvoltage: 3600,
+            ..valid_config()
+        };
+        assert!(block_on(writer.write_config(&mut supervisor, &valid_config(), &target)).is_err());
+        assert!(supervisor.transport().written.is_empty());
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    fn supervisor(battery: MockEeprom) -> ConnectionSupervisor<MockEeprom, ManualClock> {
+        ConnectionSupervisor::new(
+            battery,
+            ManualClock::default(),
+            &ConnectionConfig::default(),
+        )
+    }
+
+    fn audit_log(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("aces-audit-{}-{}.jsonl", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    use super::*;
+    use crate::eeprom::valid_config;
+    use crate::util::block_on;
+    use crate::{read_battery_config, ConnectionConfig, ManualClock, MockEeprom};
+}
+
+use crate::factory::{write_battery_config, write_frames};
+use crate::util::to_hex;
+use crate::{
+    BatteryConfig, Clock, ConfigChange, ConnectionSupervisor, Frame, Result, Transport, Violation,
+    WriteConfig,
+};
+use chrono::{DateTime, FixedOffset, Local};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
@@ -0,0 +1,297 @@
+/// Sends a read request for every register in a range, recording what the
+/// device answers.
+///
+/// The scanner only ever builds read requests (`Frame::read`), never write
+/// frames.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Scanner {
+    registers: RangeInclusive<u8>,
+    pace: Duration,
+    timeout: Duration,
+}
+
+/// The answer to the read request of one register.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct RegisterScan {
+    pub register: u8,
+    /// The reply fragments, concatenated. Empty when the device did not
+    /// answer.
+    pub reply: Vec<u8>,
+    /// Why the request could not be sent.
+    pub error: Option<String>,
+}
+
+/// The result of a scan, see `Display` for the diffable text form.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct ScanReport {
+    /// `BatteryDetail::software_version`, if it could be read.
+    pub software_version: Option<u8>,
+    pub registers: Vec<RegisterScan>,
+}
+
+impl Scanner {
+    pub fn new(registers: RangeInclusive<u8>) -> Self {
+        Scanner {
+            registers,
+            pace: Duration::from_millis(500),
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    pub fn from_config(config: &ScanConfig) -> Self {
+        Self::new(config.first..=config.last)
+            .pace(Duration::from_millis(config.pace))
+            .timeout(Duration::from_millis(config.timeout))
+    }
+
+    /// The delay between two requests.
+    pub fn pace(mut self, pace: Duration) -> Self {
+        self.pace = pace;
+        self
+    }
+
+    /// How long to wait for the next reply fragment.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub async fn scan<T, C>(&self, supervisor: &mut ConnectionSupervisor<T, C>) -> ScanReport
+    where
+        T: Transport,
+        C: Clock,
+    {
+        let software_version = match supervisor.request_detail().await {
+            Ok(detail) => Some(detail.software_version),
+            Err(err) => {
+                log::warn!("failed to read the software version: {}", err);
+                None
+            }
+        };
+
+        let mut registers = Vec::new();
+        for register in self.registers.clone() {
+            supervisor.clock().sleep(self.pace).await;
+
+            let request = Frame::read(register);
+            log::info!("reading register {:02x}", register);
+            let scan = match supervisor.exchange(&request.bytes(), self.timeout).await {
+                Ok(fragments) => RegisterScan {
+                    register,
+                    reply: fragments.concat(),
+                    error: None,
+                },
+                Err(err) => RegisterScan {
+                    register,
+                    reply: Vec::new(),
+                    error: Some(err.to_string()),
+                },
+            };
+            registers.push(scan);
+        }
+
+        ScanReport {
+            software_version,
+            registers,
+        }
+    }
+}
+
+impl RegisterScan {
+    /// The reply, if it is a frame.
+    pub fn frame(&self) -> Option<Frame> {
+        Frame::parse(&self.reply).ok()
+    }
+}
+
+impl ScanReport {
+    /// The scans of the registers that answered.
+    pub fn responding(&self) -> impl Iterator<Item = &RegisterScan> {
+        self.registers.iter().filter(|scan| !scan.reply.is_empty())
+    }
+}
+
+impl Display for ScanReport {
+    /// One line per register, so reports of two firmware versions can be
+    /// compared with `diff`.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.software_version {
+            Some(version) => writeln!(
+                f,
+                "# software version {}.{} ({:02x})",
+                version >> 4,
+                version & 0x0f,
+                version
+            )?,
+            None => writeln!(f, "# software version unknown")?,
+        }
+        writeln!(
+            f,
+            "# {} of {} registers responded",
+            self.responding().count(),
+            self.registers.len()
+        )?;
+
+        for scan in &self.registers {
+            write!(f, "{:02x} ", scan.register)?;
+            match (&scan.error, scan.frame()) {
+                (Some(err), _) => writeln!(f, "error {}", err)?,
+                (None, _) if scan.reply.is_empty() => writeln!(f, "-")?,
+                (None, None) => writeln!(f, "invalid {}", to_hex(&scan.reply))?,
+                (None, Some(frame)) => {
+                    write!(
+                        f,
+                        "status {:02x} length {} payload {}",
+                        frame.status,
+                        frame.payload.len(),
+                        to_hex(&frame.payload)
+                    )?;
+                    if !frame.is_checksum_valid() {
+                        write!(f, " (invalid checksum)")?;
+                    }
+                    if frame.register != scan.register {
+                        write!(f, " (from register {:02x})", frame.register)?;
+                    }
+                    writeln!(f)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_scan() {
+        let mut supervisor = ConnectionSupervisor::new(
+            MockBattery::default(),
+            ManualClock::default(),
+            &ConnectionConfig::default(),
+        );
+        let scanner = Scanner::new(0x02..=0x05).pace(Duration::from_millis(200));
+
+        let report = block_on(scanner.scan(&mut supervisor));
+        assert_eq!(report.software_version, Some(0x20));
+        assert_eq!(report.responding().count(), 3);
+        assert_eq!(
+            report.to_string(),
+            "\
+# software version 2.0 (20)
+# 3 of 4 registers responded
+02 -
+03 status 00 length 29 payload 0538028317\
+5c27de00092b94000000000000203b0304030b7f0b6c0b69
+04 status 00 length 8 payload 0de20ddc0dec0ded
+05 status 00 length 2 payload 4143 (invalid checksum)
+"
+        );
+
+        // every frame sent is a read request
+        for frame in &supervisor.transport().written[1..] {
+            let frame = Frame::parse(frame).unwrap();
+            assert!(!frame.is_write());
+            assert_eq!(frame.status, Frame::READ);
+        }
+        assert_eq!(supervisor.transport().written.len(), 6);
+        assert_eq!(
+            supervisor.clock().sleeps[1..],
+            [Duration::from_millis(200); 4]
+        );
+    }
+
+    #[test]
+    fn test_scan_discards_late_replies() {
+        let mut supervisor = ConnectionSupervisor::new(
+            MockBattery {
+                slow: true,
+                ..Default::default()
+            },
+            ManualClock::default(),
+            &ConnectionConfig::default(),
+        );
+        let scanner = Scanner::new(0x02..=0x04);
+
+        // the reply of 0x02 arrives with the one of 0x03, every row keeps
+        // its own register
+        let report = block_on(scanner.scan(&mut supervisor));
+        assert_eq!(
+            report.to_string(),
+            "\
+# software version 2.0 (20)
+# 2 of 3 registers responded
+02 -
+03 status 00 length 29 payload 0538028317\
+5c27de00092b94000000000000203b0304030b7f0b6c0b69
+04 status 00 length 8 payload 0de20ddc0dec0ded
+"
+        );
+    }
+
+    /// A battery answering reads of 0x03, 0x04 and 0x05, and when `slow`, of
+    /// 0x02 only together with the next reply.
+    #[derive(Default)]
+    struct MockBattery {
+        written: Vec<Vec<u8>>,
+        pending: Rc<RefCell<VecDeque<Vec<u8>>>>,
+        slow: bool,
+        late: Vec<u8>,
+    }
+
+    struct MockReceiver(Rc<RefCell<VecDeque<Vec<u8>>>>);
+
+    impl Transport for MockBattery {
+        type Receiver = MockReceiver;
+
+        async fn connect(&mut self) -> Result<MockReceiver> {
+            Ok(MockReceiver(self.pending.clone()))
+        }
+
+        async fn is_connected(&mut self) -> bool {
+            true
+        }
+
+        async fn write(&mut self, value: &[u8]) -> Result<()> {
+            self.written.push(value.to_vec());
+            let reply = match Request::parse_request(value) {
+                Ok(request) => StaticResponses.response(&request),
+                Err(_) if value == Frame::read(0x05).bytes() => {
+                    from_hex("dd0500024143cafe77").unwrap()
+                }
+                Err(_) if self.slow && value == Frame::read(0x02).bytes() => {
+                    self.late = from_hex("dd0200024243ff7977").unwrap();
+                    return Ok(());
+                }
+                Err(_) => return Ok(()),
+            };
+            let mut late = std::mem::take(&mut self.late);
+            late.extend(reply);
+            self.pending.borrow_mut().push_back(late);
+            Ok(())
+        }
+    }
+
+    impl NotificationsReceiver for MockReceiver {
+        fn next(&mut self) -> Vec<u8> {
+            self.0.borrow_mut().pop_front().unwrap()
+        }
+
+        fn next_timeout(&mut self, _timeout: Duration) -> Option<Vec<u8>> {
+            self.0.borrow_mut().pop_front()
+        }
+    }
+
+    use super::*;
+    use crate::util::{block_on, from_hex};
+    use crate::{
+        ConnectionConfig, ManualClock, NotificationsReceiver, Request, ResponseSource, Result,
+        StaticResponses,
+    };
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+}
+
+use crate::util::to_hex;
+use crate::{Clock, ConnectionSupervisor, Frame, ScanConfig, Transport};
+use std::fmt::{self, Display, Formatter};
+use std::ops::RangeInclusive;
+use std::time::Duration;
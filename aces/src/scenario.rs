@@ -0,0 +1,509 @@
+/// A hand-authored battery story, replayed by the simulator.
+///
+/// Keyframes set some of the values at a point in time. Between the
+/// keyframes setting a value it is interpolated, before the first and after
+/// the last one it is held. Protection state, MOSFETs and counters are
+/// always held. See `scenario.example.toml`.
+///
+/// ```toml
+/// speed = 60
+///
+/// [[keyframe]]
+/// at = 0
+/// current = 20
+/// cells = [3300, 3300, 3300, 3300]
+///
+/// [[keyframe]]
+/// at = "2h"
+/// current = 0
+/// cells = [3400, 3400, 3400, 3400]
+/// ```
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Scenario {
+    /// How many seconds of the scenario pass per second (e.g. 8640 runs a day in 10 s).
+    pub speed: f64,
+    /// Start over after the last keyframe.
+    pub repeat: bool,
+    pub battery: BatteryInfo,
+    #[serde(rename = "keyframe")]
+    pub keyframes: Vec<Keyframe>,
+}
+
+/// The values that don't change during a scenario.
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct BatteryInfo {
+    /// Standard capacity (Ah).
+    pub capacity: f32,
+    pub cycles: i16,
+    pub date_of_production: i16,
+    pub software_version: u8,
+}
+
+#[derive(PartialEq, Clone, Debug, Default, Deserialize)]
+pub struct Keyframe {
+    /// When the keyframe applies (s), also e.g. `"90s"`, `"15m"`, `"2h"` or `"1d"`.
+    #[serde(deserialize_with = "deserialize_time")]
+    pub at: f64,
+    /// How values change towards the next keyframe setting them.
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    /// Cell voltages (mV).
+    pub cells: Option<Vec<f32>>,
+    /// Current, positive while charging (A).
+    pub current: Option<f32>,
+    /// NTC temperatures (°C).
+    pub temperatures: Option<Vec<f32>>,
+    /// State of charge (%).
+    pub soc: Option<f32>,
+    /// The active protection (e.g. `"otc"`), see `ProtectionOfState::NAMES`.
+    pub protection: Option<String>,
+    pub charge: Option<bool>,
+    pub discharge: Option<bool>,
+    /// Protection counters by name, see `BatteryProtect::FIELD_NAMES`.
+    /// Counters keep their value until a later keyframe sets them again.
+    #[serde(default)]
+    pub protect: HashMap<String, i16>,
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    /// Hold the value until the next keyframe.
+    Step,
+}
+
+/// The register values of a scenario at a point in time.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Sample {
+    /// Cell voltages (mV).
+    pub voltage: Vec<i16>,
+    pub detail: BatteryDetail,
+    pub protect: BatteryProtect,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScenarioError {
+    #[error("Keyframe at {0} s is before the previous keyframe")]
+    Unordered(f64),
+    #[error("Unknown protection state {0}")]
+    UnknownProtection(String),
+    #[error("Unknown protect counter {0}")]
+    UnknownCounter(String),
+    #[error("Temperature {0} °C is outside -273.1..=3003.6 °C")]
+    Temperature(f32),
+}
+
+/// The temperatures the NTC list can encode (°C), as 0.1 K in an `i16`.
+const TEMPERATURES: RangeInclusive<f32> = -273.1..=3003.6;
+
+/// Responds with the values of a scenario, at the time of `clock`.
+pub struct ScenarioResponses<C>
+where
+    C: Clock,
+{
+    scenario: Scenario,
+    clock: C,
+}
+
+impl Scenario {
+    pub fn parse(s: &str) -> Result<Self> {
+        let scenario: Scenario = toml::from_str(s)?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// The time of the last keyframe (s).
+    pub fn duration(&self) -> f64 {
+        self.keyframes.last().map(|k| k.at).unwrap_or_default()
+    }
+
+    /// The values after `elapsed` real time, scaled by `speed`.
+    pub fn sample(&self, elapsed: Duration) -> Sample {
+        let mut t = elapsed.as_secs_f64() * self.speed;
+        if self.repeat && self.duration() > 0.0 {
+            t %= self.duration();
+        }
+
+        let cells = self
+            .value_at(t, |k| k.cells.as_ref(), lerp_vec)
+            .unwrap_or_else(|| vec![3300.0; 4]);
+        let temperatures = self
+            .value_at(t, |k| k.temperatures.as_ref(), lerp_vec)
+            .unwrap_or_else(|| vec![25.0; 3]);
+        let current = self
+            .value_at(t, |k| k.current.as_ref(), lerp)
+            .unwrap_or(0.0);
+        let soc = self.value_at(t, |k| k.soc.as_ref(), lerp).unwrap_or(50.0);
+
+        let held = self.keyframes.iter().filter(|k| k.at <= t);
+        let mut protection = ProtectionOfState::NONE;
+        let mut charge = true;
+        let mut discharge = true;
+        let mut protect = BatteryProtect::default();
+        for keyframe in held {
+            if let Some(name) = &keyframe.protection {
+                protection = ProtectionOfState::from_name(name).unwrap_or(protection);
+            }
+            charge = keyframe.charge.unwrap_or(charge);
+            discharge = keyframe.discharge.unwrap_or(discharge);
+            for (name, value) in &keyframe.protect {
+                if let Some(idx) = BatteryProtect::FIELD_NAMES.iter().position(|n| n == name) {
+                    protect.set_value_at(idx, *value);
+                }
+            }
+        }
+
+        let voltage: Vec<i16> = cells.iter().map(|v| v.round() as i16).collect();
+        let capacity = self.battery.capacity * 100.0;
+        let detail = BatteryDetail {
+            total_voltage: (cells.iter().sum::<f32>() / 10.0).round() as i16,
+            current: (current * 100.0).round() as i16,
+            residual_capacity: (capacity * soc / 100.0).round() as i16,
+            standard_capacity: capacity.round() as i16,
+            cycles: self.battery.cycles,
+            date_of_production: self.battery.date_of_production,
+            equilibrium: 0,
+            equilibrium_high: 0,
+            protection_of_state: protection,
+            software_version: self.battery.software_version,
+            residual_capacity_percent: soc.round().clamp(0.0, 100.0) as u8,
+            control_state: charge as u8 | (discharge as u8) << 1,
+            charge,
+            discharge,
+            battery_number: voltage.len() as u8,
+            list_ntc: temperatures
+                .iter()
+                .map(|t| (t * 10.0).round() as i16)
+                .collect(),
+        };
+
+        Sample {
+            voltage,
+            detail,
+            protect,
+        }
+    }
+
+    /// Interpolates the value selected by `field` at `t`.
+    fn value_at<T, F, L>(&self, t: f64, field: F, lerp: L) -> Option<T>
+    where
+        T: Clone,
+        F: Fn(&Keyframe) -> Option<&T>,
+        L: Fn(&T, &T, f32) -> T,
+    {
+        let prev = self
+            .keyframes
+            .iter()
+            .rev()
+            .find(|k| k.at <= t && field(k).is_some());
+        let next = self
+            .keyframes
+            .iter()
+            .find(|k| k.at > t && field(k).is_some());
+
+        match (prev, next) {
+            (Some(prev), Some(next)) if prev.interpolation == Interpolation::Linear => {
+                let frac = ((t - prev.at) / (next.at - prev.at)) as f32;
+                Some(lerp(field(prev)?, field(next)?, frac))
+            }
+            (Some(prev), _) => field(prev).cloned(),
+            (None, Some(next)) => field(next).cloned(),
+            (None, None) => None,
+        }
+    }
+
+    fn validate(&self) -> std::result::Result<(), ScenarioError> {
+        for pair in self.keyframes.windows(2) {
+            if pair[1].at < pair[0].at {
+                return Err(ScenarioError::Unordered(pair[1].at));
+            }
+        }
+        for keyframe in &self.keyframes {
+            if let Some(name) = &keyframe.protection {
+                if ProtectionOfState::from_name(name).is_none() {
+                    return Err(ScenarioError::UnknownProtection(name.clone()));
+                }
+            }
+            for name in keyframe.protect.keys() {
+                if !BatteryProtect::FIELD_NAMES.contains(&name.as_str()) {
+                    return Err(ScenarioError::UnknownCounter(name.clone()));
+                }
+            }
+            for t in keyframe.temperatures.iter().flatten() {
+                if !TEMPERATURES.contains(t) {
+                    return Err(ScenarioError::Temperature(*t));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Scenario {
+            speed: 1.0,
+            repeat: false,
+            battery: BatteryInfo::default(),
+            keyframes: Vec::new(),
+        }
+    }
+}
+
+impl Default for BatteryInfo {
+    fn default() -> Self {
+        BatteryInfo {
+            capacity: 100.0,
+            cycles: 0,
+            date_of_production: 0x2b94,
+            software_version: 0x20,
+        }
+    }
+}
+
+impl<C> ScenarioResponses<C>
+where
+    C: Clock,
+{
+    /// The scenario starts at the current time of `clock`, usually zero.
+    pub fn new(scenario: Scenario, clock: C) -> Self {
+        ScenarioResponses { scenario, clock }
+    }
+
+    pub fn sample(&self) -> Sample {
+        self.scenario.sample(self.clock.now())
+    }
+}
+
+impl<C> ResponseSource for ScenarioResponses<C>
+where
+    C: Clock,
+{
+    fn response(&mut self, request: &Request) -> Vec<u8> {
+        let sample = self.sample();
+        match request {
+            Request::Clear => Vec::new(),
+            Request::BatteryDetail => Response::BatteryDetail(sample.detail).bytes(),
+            Request::BatteryProtect => Response::BatteryProtect(sample.protect).bytes(),
+            Request::BatteryVoltage => {
+                Response::BatteryVoltage(BatteryVoltage(sample.voltage)).bytes()
+            }
+        }
+    }
+}
+
+fn lerp(a: &f32, b: &f32, frac: f32) -> f32 {
+    a + (b - a) * frac
+}
+
+// takes `&Vec` to be usable as the `lerp` of `value_at`
+#[allow(clippy::ptr_arg)]
+fn lerp_vec(a: &Vec<f32>, b: &Vec<f32>, frac: f32) -> Vec<f32> {
+    if a.len() != b.len() {
+        return a.clone();
+    }
+    a.iter().zip(b).map(|(a, b)| lerp(a, b, frac)).collect()
+}
+
+/// Parses a time in seconds, optionally with units (e.g. `90`, `1.5m` or `3h 30m`).
+fn parse_time(s: &str) -> Option<f64> {
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return None;
+    }
+
+    let mut total = 0.0;
+    while !rest.is_empty() {
+        let end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let value: f64 = rest[..end].parse().ok()?;
+        rest = rest[end..].trim_start();
+
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let scale = match &rest[..end] {
+            "" | "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3_600.0,
+            "d" => 86_400.0,
+            _ => return None,
+        };
+        rest = rest[end..].trim_start();
+
+        total += value * scale;
+    }
+    Some(total)
+}
+
+fn deserialize_time<'de, D>(deserializer: D) -> std::result::Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Time {
+        Seconds(f64),
+        Text(String),
+    }
+
+    match Time::deserialize(deserializer)? {
+        Time::Seconds(seconds) => Ok(seconds),
+        Time::Text(text) => {
+            parse_time(&text).ok_or_else(|| D::Error::custom(format!("invalid time {}", text)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn scenario() -> Scenario {
+        Scenario::parse(
+            r#"
+            [[keyframe]]
+            at = 0
+            current = 20
+            cells = [3300, 3300]
+            soc = 20
+
+            [[keyframe]]
+            at = "1m"
+            interpolation = "step"
+            soc = 40
+            protection = "otc"
+            protect = { high_temp_charging = 1 }
+
+            [[keyframe]]
+            at = "2m"
+            current = 0
+            cells = [3400, 3500]
+            soc = 60
+            charge = false
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("90"), Some(90.0));
+        assert_eq!(parse_time("90s"), Some(90.0));
+        assert_eq!(parse_time("1.5m"), Some(90.0));
+        assert_eq!(parse_time("2h"), Some(7_200.0));
+        assert_eq!(parse_time("1 d"), Some(86_400.0));
+        assert_eq!(parse_time("3h 30m"), Some(12_600.0));
+        assert_eq!(parse_time("1w"), None);
+        assert_eq!(parse_time("h"), None);
+    }
+
+    #[test]
+    fn test_sample() {
+        let scenario = scenario();
+
+        let start = scenario.sample(Duration::ZERO);
+        assert_eq!(start.voltage, vec![3300, 3300]);
+        assert_eq!(start.detail.total_voltage, 660);
+        assert_eq!(start.detail.current, 2000);
+        assert_eq!(start.detail.residual_capacity_percent, 20);
+        assert_eq!(start.detail.residual_capacity, 2000);
+        assert_eq!(start.detail.battery_number, 2);
+        assert_eq!(start.detail.list_ntc, vec![250, 250, 250]);
+        assert_eq!(start.detail.protection_of_state, ProtectionOfState::NONE);
+
+        // linear between the keyframes setting a value
+        let middle = scenario.sample(Duration::from_secs(60));
+        assert_eq!(middle.voltage, vec![3350, 3400]);
+        assert_eq!(middle.detail.current, 1000);
+        assert_eq!(middle.detail.residual_capacity_percent, 40);
+        assert_eq!(middle.detail.protection_of_state, ProtectionOfState::OTC);
+        assert_eq!(middle.protect.high_temp_charging, 1);
+
+        // step from the second keyframe
+        let step = scenario.sample(Duration::from_secs(90));
+        assert_eq!(step.detail.residual_capacity_percent, 40);
+        assert!(step.detail.charge);
+
+        // held after the last keyframe
+        let end = scenario.sample(Duration::from_secs(600));
+        assert_eq!(end.voltage, vec![3400, 3500]);
+        assert_eq!(end.detail.residual_capacity_percent, 60);
+        assert!(!end.detail.charge);
+        assert_eq!(end.detail.control_state, 2);
+        assert_eq!(end.protect.high_temp_charging, 1);
+    }
+
+    #[test]
+    fn test_speed_and_repeat() {
+        let expected = scenario().sample(Duration::from_secs(60));
+
+        let mut fast = scenario();
+        fast.speed = 60.0;
+        assert_eq!(fast.sample(Duration::from_secs(1)), expected);
+
+        fast.repeat = true;
+        assert_eq!(fast.sample(Duration::from_secs(3)), expected);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Scenario::parse("[[keyframe]]\nat = 10\n[[keyframe]]\nat = 5\n").is_err());
+        assert!(Scenario::parse("[[keyframe]]\nat = \"10x\"\n").is_err());
+        assert!(Scenario::parse("[[keyframe]]\nat = 0\nprotection = \"nope\"\n").is_err());
+        assert!(Scenario::parse("[[keyframe]]\nat = 0\nprotect = { nope = 1 }\n").is_err());
+        assert!(Scenario::parse("[[keyframe]]\nat = 0\ntemperatures = [3100]\n").is_err());
+        assert!(Scenario::parse("[[keyframe]]\nat = 0\ntemperatures = [-300]\n").is_err());
+    }
+
+    #[test]
+    fn test_responses() {
+        let clock = ManualClock {
+            elapsed: Duration::from_secs(120),
+            ..Default::default()
+        };
+        let mut responses = ScenarioResponses::new(scenario(), clock);
+
+        let frame = responses.response(&Request::BatteryVoltage);
+        assert_eq!(
+            Response::parse_response(&frame),
+            Ok(Response::BatteryVoltage(BatteryVoltage(vec![3400, 3500])))
+        );
+        let frame = responses.response(&Request::BatteryDetail);
+        assert_eq!(
+            Response::parse_response(&frame),
+            Ok(Response::BatteryDetail(responses.sample().detail))
+        );
+        assert!(responses.response(&Request::Clear).is_empty());
+    }
+
+    #[test]
+    fn test_parse_example_scenario() {
+        let mut scenario = Scenario::parse(include_str!("../../scenario.example.toml")).unwrap();
+        scenario.repeat = false;
+        let end = scenario.sample(Duration::from_secs_f64(
+            scenario.duration() / scenario.speed,
+        ));
+        assert_eq!(end.detail.protection_of_state, ProtectionOfState::OTC);
+    }
+
+    use super::*;
+    use crate::ManualClock;
+}
+
+use crate::{
+    BatteryDetail, BatteryProtect, BatteryVoltage, Clock, ProtectionOfState, Request, Response,
+    ResponseSource, Result,
+};
+use serde::{de::Error as _, Deserialize, Deserializer};
+use std::{collections::HashMap, fs, ops::RangeInclusive, path::Path, time::Duration};
@@ -0,0 +1,299 @@
+/// A register polled by the `Scheduler`.
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Hash)]
+pub enum Register {
+    Voltage,
+    Detail,
+    Protect,
+}
+
+/// Decides which registers to poll, and when.
+///
+/// Every register is polled on its own interval, delayed by a random jitter
+/// of up to `jitter` times the interval. Registers that are due at the same
+/// time are returned highest priority first. After `fast_poll` every register
+/// is polled at least every `fast_interval` until the fast period ends.
+pub struct Scheduler {
+    entries: Vec<Entry>,
+    jitter: f32,
+    fast_interval: Duration,
+    fast_until: Option<Duration>,
+    rng: Xorshift,
+}
+
+struct Entry {
+    register: Register,
+    interval: Duration,
+    priority: u8,
+    due: Duration,
+}
+
+impl Register {
+    pub const ALL: [Register; 3] = [Register::Voltage, Register::Detail, Register::Protect];
+
+    pub fn request(&self) -> Request {
+        match self {
+            Self::Voltage => Request::BatteryVoltage,
+            Self::Detail => Request::BatteryDetail,
+            Self::Protect => Request::BatteryProtect,
+        }
+    }
+}
+
+impl Scheduler {
+    /// The shortest interval between polls of a register, shorter intervals
+    /// (e.g. `0` in the configuration) are raised to it.
+    pub const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Creates a scheduler without any registers.
+    pub fn new() -> Self {
+        Scheduler {
+            entries: Vec::new(),
+            jitter: 0.0,
+            fast_interval: Duration::from_secs(1),
+            fast_until: None,
+            rng: Xorshift::from_time(),
+        }
+    }
+
+    /// Creates a scheduler polling every register on its configured interval.
+    ///
+    /// The detail register (current, state of charge) has the highest
+    /// priority, the protect counters the lowest.
+    pub fn from_config(config: &PollConfig) -> Self {
+        Self::new()
+            .register(Register::Detail, Duration::from_secs(config.detail), 2)
+            .register(Register::Voltage, Duration::from_secs(config.voltage), 1)
+            .register(Register::Protect, Duration::from_secs(config.protect), 0)
+            .jitter(config.jitter)
+            .fast_interval(Duration::from_secs(config.fast_interval))
+    }
+
+    /// Uses a fixed seed, for reproducible jitter.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Xorshift::new(seed);
+        self
+    }
+
+    /// Polls `register` every `interval`, replacing any earlier schedule for it.
+    ///
+    /// The register is due immediately.
+    ///
+    /// # Parameters
+    /// ---
+    /// * `priority` - Registers with a higher priority are polled first.
+    pub fn register(mut self, register: Register, interval: Duration, priority: u8) -> Self {
+        self.entries.retain(|entry| entry.register != register);
+        self.entries.push(Entry {
+            register,
+            interval: interval.max(Self::MIN_INTERVAL),
+            priority,
+            due: Duration::ZERO,
+        });
+        // stable, so equal priorities keep their registration order
+        self.entries.sort_by_key(|entry| Reverse(entry.priority));
+        self
+    }
+
+    /// Delays every poll by up to `fraction` of its interval.
+    pub fn jitter(mut self, fraction: f32) -> Self {
+        self.jitter = fraction.max(0.0);
+        self
+    }
+
+    /// The longest interval between polls while fast polling.
+    pub fn fast_interval(mut self, interval: Duration) -> Self {
+        self.fast_interval = interval.max(Self::MIN_INTERVAL);
+        self
+    }
+
+    /// Polls every register immediately, and then at least every
+    /// `fast_interval` until `duration` has passed.
+    pub fn fast_poll(&mut self, now: Duration, duration: Duration) {
+        let until = now + duration;
+        self.fast_until = Some(self.fast_until.map_or(until, |u| u.max(until)));
+        for entry in &mut self.entries {
+            entry.due = entry.due.min(now);
+        }
+    }
+
+    pub fn is_fast(&self, now: Duration) -> bool {
+        self.fast_until.is_some_and(|until| now < until)
+    }
+
+    /// When the next register is due, `None` without any registers.
+    pub fn next_due(&self) -> Option<Duration> {
+        self.entries.iter().map(|entry| entry.due).min()
+    }
+
+    /// The registers due at `now`, highest priority first.
+    ///
+    /// Each returned register is rescheduled, so it is only returned once.
+    pub fn due(&mut self, now: Duration) -> Vec<Register> {
+        let fast = self.is_fast(now);
+        if !fast {
+            self.fast_until = None;
+        }
+
+        let mut due = Vec::new();
+        for idx in 0..self.entries.len() {
+            if self.entries[idx].due > now {
+                continue;
+            }
+
+            let mut interval = self.entries[idx].interval;
+            if fast {
+                interval = interval.min(self.fast_interval);
+            }
+            let jitter =
+                interval.mul_f32(self.jitter) * (self.rng.next_u64() % 1_000) as u32 / 1_000;
+
+            let entry = &mut self.entries[idx];
+            entry.due = now + interval + jitter;
+            due.push(entry.register);
+        }
+        due
+    }
+
+    /// Sleeps until at least one register is due, and returns the due registers.
+    ///
+    /// Returns immediately without any registers.
+    pub async fn wait<C>(&mut self, clock: &mut C) -> Vec<Register>
+    where
+        C: Clock,
+    {
+        loop {
+            let now = clock.now();
+            let due = self.due(now);
+            if !due.is_empty() {
+                return due;
+            }
+
+            let Some(next) = self.next_due() else {
+                return due;
+            };
+            clock.sleep(next.saturating_sub(now)).await;
+        }
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn scheduler() -> Scheduler {
+        Scheduler::new()
+            .register(Register::Voltage, Duration::from_secs(5), 1)
+            .register(Register::Protect, Duration::from_secs(60), 0)
+            .register(Register::Detail, Duration::from_secs(5), 2)
+            .fast_interval(Duration::from_secs(1))
+            .with_seed(42)
+    }
+
+    fn run(
+        scheduler: &mut Scheduler,
+        clock: &mut ManualClock,
+        until: Duration,
+    ) -> Vec<(Duration, Register)> {
+        let mut polls = Vec::new();
+        while clock.now() < until {
+            for register in block_on(scheduler.wait(clock)) {
+                polls.push((clock.now(), register));
+            }
+        }
+        polls
+    }
+
+    fn count(polls: &[(Duration, Register)], register: Register) -> usize {
+        polls.iter().filter(|(_, r)| *r == register).count()
+    }
+
+    #[test]
+    fn test_intervals() {
+        let mut scheduler = scheduler();
+        let mut clock = ManualClock::default();
+        let polls = run(&mut scheduler, &mut clock, Duration::from_secs(120));
+
+        assert_eq!(count(&polls, Register::Voltage), 25);
+        assert_eq!(count(&polls, Register::Detail), 25);
+        assert_eq!(count(&polls, Register::Protect), 3);
+        assert!(clock.sleeps.iter().all(|d| *d == Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_priority() {
+        let mut scheduler = scheduler();
+        assert_eq!(
+            scheduler.due(Duration::ZERO),
+            vec![Register::Detail, Register::Voltage, Register::Protect]
+        );
+        assert_eq!(scheduler.due(Duration::ZERO), vec![]);
+        assert_eq!(scheduler.next_due(), Some(Duration::from_secs(5)));
+        assert_eq!(
+            scheduler.due(Duration::from_secs(5)),
+            vec![Register::Detail, Register::Voltage]
+        );
+    }
+
+    #[test]
+    fn test_jitter() {
+        let mut scheduler = scheduler().jitter(0.5);
+        let mut clock = ManualClock::default();
+        let polls = run(&mut scheduler, &mut clock, Duration::from_secs(600));
+
+        let voltage: Vec<Duration> = polls
+            .iter()
+            .filter(|(_, r)| *r == Register::Voltage)
+            .map(|(t, _)| *t)
+            .collect();
+        let gaps: Vec<Duration> = voltage.windows(2).map(|w| w[1] - w[0]).collect();
+        assert!(gaps.iter().all(|gap| *gap >= Duration::from_secs(5)));
+        assert!(gaps.iter().all(|gap| *gap < Duration::from_millis(7_500)));
+        assert!(gaps.iter().any(|gap| *gap != gaps[0]));
+    }
+
+    #[test]
+    fn test_fast_poll() {
+        let mut scheduler = scheduler();
+        let mut clock = ManualClock::default();
+        run(&mut scheduler, &mut clock, Duration::from_secs(12));
+
+        scheduler.fast_poll(clock.now(), Duration::from_secs(10));
+        assert!(scheduler.is_fast(clock.now()));
+        let polls = run(&mut scheduler, &mut clock, Duration::from_secs(22));
+        assert_eq!(polls[0].0, Duration::from_secs(15));
+        assert_eq!(count(&polls, Register::Protect), 8);
+        assert_eq!(count(&polls, Register::Voltage), 8);
+
+        // back to the normal intervals
+        assert!(!scheduler.is_fast(Duration::from_secs(25)));
+        let polls = run(&mut scheduler, &mut clock, Duration::from_secs(60));
+        assert_eq!(count(&polls, Register::Protect), 3);
+        assert_eq!(count(&polls, Register::Voltage), 10);
+    }
+
+    #[test]
+    fn test_zero_interval() {
+        let mut scheduler = Scheduler::new()
+            .register(Register::Voltage, Duration::ZERO, 0)
+            .fast_interval(Duration::ZERO);
+        assert_eq!(scheduler.due(Duration::ZERO), vec![Register::Voltage]);
+        assert_eq!(scheduler.next_due(), Some(Scheduler::MIN_INTERVAL));
+
+        scheduler.fast_poll(Duration::from_secs(1), Duration::from_secs(10));
+        assert_eq!(
+            scheduler.due(Duration::from_secs(1)),
+            vec![Register::Voltage]
+        );
+        assert_eq!(scheduler.next_due(), Some(Duration::from_secs(2)));
+    }
+
+    use super::*;
+    use crate::{util::block_on, ManualClock};
+}
+
+use crate::{util::Xorshift, Clock, PollConfig, Request};
+use std::{cmp::Reverse, time::Duration};
@@ -0,0 +1,402 @@
+/// What the battery is doing during a session.
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionKind {
+    Charge,
+    Discharge,
+    Idle,
+}
+
+/// A stretch of telemetry during which the battery was charging,
+/// discharging or idle.
+#[derive(PartialEq, Clone, Debug, Serialize)]
+pub struct Session {
+    pub kind: SessionKind,
+    /// The first snapshot of the session.
+    pub start: DateTime<FixedOffset>,
+    /// The first snapshot of the next session, or the last one of this
+    /// session when it was closed by `SessionDetector::finish`.
+    pub end: DateTime<FixedOffset>,
+    /// State of charge at the start and end (%).
+    pub start_soc: u8,
+    pub end_soc: u8,
+    /// Charge moved (Ah), positive when charged.
+    pub ah: f64,
+    /// Energy moved (Wh), positive when charged.
+    pub wh: f64,
+    /// The highest current, either way (A).
+    pub peak_current: f64,
+    /// The lowest and highest cell voltage (mV).
+    pub min_cell_voltage: Option<i16>,
+    pub max_cell_voltage: Option<i16>,
+    /// The highest total voltage (10 mV).
+    pub max_total_voltage: i16,
+    /// The highest NTC temperature (0.1 °C).
+    pub max_temperature: Option<i16>,
+}
+
+/// Segments snapshots into charge, discharge and idle sessions.
+///
+/// A session starts charging above `start_current` and keeps charging until
+/// the current drops to `stop_current` (the same for discharging, with
+/// negative currents), so a current hovering around one threshold does not
+/// flap. A new kind must also last `min_duration` before the session
+/// changes; shorter excursions stay in the current session. The switch is
+/// dated to the first snapshot of the new kind.
+pub struct SessionDetector {
+    config: SessionConfig,
+    session: Option<Session>,
+    /// Snapshots of a different kind than the session, not yet lasting
+    /// `min_duration`.
+    pending: Vec<Snapshot>,
+    /// The time, power (W) and current (A) of the previous snapshot.
+    last: Option<(DateTime<FixedOffset>, f64, f64)>,
+}
+
+impl SessionDetector {
+    pub fn new(config: SessionConfig) -> Self {
+        SessionDetector {
+            config,
+            session: None,
+            pending: Vec::new(),
+            last: None,
+        }
+    }
+
+    /// The session in progress.
+    pub fn current(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    /// Adds a snapshot, returning the session it ended, if any.
+    pub fn update(&mut self, snapshot: &Snapshot) -> Option<Session> {
+        let Some(session_kind) = self.session.as_ref().map(|session| session.kind) else {
+            let kind = self.classify(SessionKind::Idle, snapshot);
+            self.session = Some(Session::start(kind, snapshot));
+            self.add(snapshot);
+            return None;
+        };
+        let kind = self.classify(session_kind, snapshot);
+
+        if kind == session_kind {
+            // an excursion too short to count stays in the session
+            for pending in std::mem::take(&mut self.pending) {
+                self.add(&pending);
+            }
+            self.add(snapshot);
+            return None;
+        }
+        if self
+            .pending
+            .first()
+            .is_some_and(|first| self.classify(session_kind, first) != kind)
+        {
+            for pending in std::mem::take(&mut self.pending) {
+                self.add(&pending);
+            }
+        }
+        self.pending.push(snapshot.clone());
+
+        let since = self.pending[0].timestamp;
+        let lasted = (snapshot.timestamp - since).num_seconds();
+        if lasted < self.config.min_duration as i64 {
+            return None;
+        }
+        let mut ended = self
+            .session
+            .replace(Session::start(kind, &self.pending[0]))?;
+        ended.end = since;
+        for pending in std::mem::take(&mut self.pending) {
+            self.add(&pending);
+        }
+        Some(ended)
+    }
+
+    /// Ends the session in progress, e.g. when monitoring stops.
+    pub fn finish(&mut self) -> Option<Session> {
+        for pending in std::mem::take(&mut self.pending) {
+            self.add(&pending);
+        }
+        self.last = None;
+        self.session.take()
+    }
+
+    /// The kind of a snapshot following a session of kind `kind`.
+    fn classify(&self, kind: SessionKind, snapshot: &Snapshot) -> SessionKind {
+        let current = snapshot.detail.current as f64 / 100.0;
+        let charging = match kind {
+            SessionKind::Charge => self.config.stop_current,
+            _ => self.config.start_current,
+        };
+        let discharging = match kind {
+            SessionKind::Discharge => self.config.stop_current,
+            _ => self.config.start_current,
+        };
+        if current > charging {
+            SessionKind::Charge
+        } else if current < -discharging {
+            SessionKind::Discharge
+        } else {
+            SessionKind::Idle
+        }
+    }
+
+    /// Adds a snapshot to the session in progress, integrating the interval
+    /// since the previous snapshot with a refreshed detail unless it is
+    /// longer than `max_gap`.
+    fn add(&mut self, snapshot: &Snapshot) {
+        let detail = &snapshot.detail;
+        let current = detail.current as f64 / 100.0;
+        let power = detail.total_voltage as f64 / 100.0 * current;
+        let Some(session) = &mut self.session else {
+            return;
+        };
+
+        // the cached current of a stale detail would count as held until now
+        if snapshot.is_refreshed(Register::Detail) {
+            if let Some((time, last_power, last_current)) = self.last {
+                let seconds = (snapshot.timestamp - time).num_milliseconds() as f64 / 1000.0;
+                if seconds > 0.0 && seconds <= self.config.max_gap as f64 {
+                    session.ah += (last_current + current) / 2.0 * seconds / 3600.0;
+                    session.wh += (last_power + power) / 2.0 * seconds / 3600.0;
+                }
+            }
+            self.last = Some((snapshot.timestamp, power, current));
+        }
+
+        session.end = snapshot.timestamp;
+        session.end_soc = detail.residual_capacity_percent;
+        session.peak_current = session.peak_current.max(current.abs());
+        session.min_cell_voltage = min(session.min_cell_voltage, snapshot.min_cell_voltage());
+        session.max_cell_voltage = max(session.max_cell_voltage, snapshot.max_cell_voltage());
+        session.max_total_voltage = session.max_total_voltage.max(detail.total_voltage);
+        session.max_temperature = max(
+            session.max_temperature,
+            detail.list_ntc.iter().copied().max(),
+        );
+    }
+}
+
+impl Session {
+    fn start(kind: SessionKind, snapshot: &Snapshot) -> Self {
+        Session {
+            kind,
+            start: snapshot.timestamp,
+            end: snapshot.timestamp,
+            start_soc: snapshot.detail.residual_capacity_percent,
+            end_soc: snapshot.detail.residual_capacity_percent,
+            ah: 0.0,
+            wh: 0.0,
+            peak_current: 0.0,
+            min_cell_voltage: None,
+            max_cell_voltage: None,
+            max_total_voltage: 0,
+            max_temperature: None,
+        }
+    }
+
+    pub fn duration(&self) -> chrono::Duration {
+        self.end - self.start
+    }
+}
+
+impl Display for SessionKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SessionKind::Charge => "charge",
+            SessionKind::Discharge => "discharge",
+            SessionKind::Idle => "idle",
+        })
+    }
+}
+
+impl Display for Session {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let minutes = self.duration().num_minutes();
+        write!(
+            f,
+            "{} {} ({}h{:02}m) soc {} -> {} % {:+.2} Ah {:+.1} Wh peak {:.2} A",
+            self.kind,
+            self.start.to_rfc3339(),
+            minutes / 60,
+            minutes % 60,
+            self.start_soc,
+            self.end_soc,
+            self.ah,
+            self.wh,
+            self.peak_current
+        )?;
+        if let (Some(min), Some(max)) = (self.min_cell_voltage, self.max_cell_voltage) {
+            write!(f, " cells {}..{} mV", min, max)?;
+        }
+        write!(f, " max {:.2} V", self.max_total_voltage as f32 / 100.0)?;
+        if let Some(temperature) = self.max_temperature {
+            write!(f, " {:.1} °C", temperature as f32 / 10.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// The lower of two optional values, ignoring `None`.
+fn min<T: Ord>(a: Option<T>, b: Option<T>) -> Option<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// The higher of two optional values.
+fn max<T: Ord>(a: Option<T>, b: Option<T>) -> Option<T> {
+    a.max(b)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_sessions() {
+        let mut detector = SessionDetector::new(SessionConfig {
+            min_duration: 60,
+            max_gap: 600,
+            ..Default::default()
+        });
+        // idle, charging at 10 A for 10 minutes, idle, discharging at 5 A
+        let mut sessions = Vec::new();
+        for (minute, current, soc) in [
+            (0, 0, 50),
+            (1, 10, 50),
+            (2, 1000, 51),
+            (3, 1000, 53),
+            (12, 1000, 68),
+            (13, 1000, 70),
+            (14, 0, 70),
+            (15, 0, 70),
+            (16, 0, 70),
+            (17, -500, 70),
+            (18, -500, 69),
+            (19, -500, 68),
+        ] {
+            sessions.extend(detector.update(&snapshot(minute * 60, current, soc)));
+        }
+        sessions.extend(detector.finish());
+
+        assert_eq!(
+            sessions
+                .iter()
+                .map(|s| (s.kind, s.start_soc, s.end_soc))
+                .collect::<Vec<_>>(),
+            [
+                (SessionKind::Idle, 50, 50),
+                (SessionKind::Charge, 51, 70),
+                (SessionKind::Idle, 70, 70),
+                (SessionKind::Discharge, 70, 68),
+            ]
+        );
+        let charge = &sessions[1];
+        assert_eq!(charge.start, at(120));
+        assert_eq!(charge.end, at(14 * 60));
+        // the minute up from 0.1 A, then 11 minutes at 10 A
+        assert!((charge.ah - (10.1 / 2.0 / 60.0 + 11.0 / 60.0 * 10.0)).abs() < 1e-9);
+        assert!((charge.wh - charge.ah * 13.3).abs() < 1e-9);
+        assert_eq!(charge.peak_current, 10.0);
+        assert_eq!(charge.min_cell_voltage, Some(3300));
+        assert_eq!(charge.max_cell_voltage, Some(3350));
+        assert_eq!(charge.max_temperature, Some(255));
+        assert_eq!(
+            charge.to_string(),
+            "charge 2026-10-18T10:02:00+02:00 (0h12m) soc 51 -> 70 % +1.92 Ah +25.5 Wh \
+peak 10.00 A cells 3300..3350 mV max 13.30 V 25.5 °C"
+        );
+        assert!(sessions[3].ah < 0.0);
+    }
+
+    #[test]
+    fn test_stale_detail() {
+        let config = SessionConfig {
+            min_duration: 0,
+            max_gap: 600,
+            ..Default::default()
+        };
+        // the detail polled at 0 and 120 seconds only, the cells in between
+        let mut stale = snapshot(60, 1000, 50);
+        stale.refreshed = vec![Register::Voltage];
+        let charge = |snapshots: &[Snapshot]| {
+            let mut detector = SessionDetector::new(config.clone());
+            for snapshot in snapshots {
+                detector.update(snapshot);
+            }
+            detector.finish().unwrap()
+        };
+
+        let fresh = [snapshot(0, 1000, 50), snapshot(120, 2000, 51)];
+        let with_stale = [fresh[0].clone(), stale, fresh[1].clone()];
+        assert!((charge(&fresh).ah - 0.5).abs() < 1e-9);
+        assert_eq!(charge(&with_stale).ah, charge(&fresh).ah);
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let mut detector = SessionDetector::new(SessionConfig {
+            start_current: 1.0,
+            stop_current: 0.3,
+            min_duration: 0,
+            ..Default::default()
+        });
+        // 0.8 A does not start charging, but keeps a charge going
+        let mut sessions = Vec::new();
+        for (minute, current) in [(0, 80), (1, 150), (2, 80), (3, 50), (4, 20), (5, 80)] {
+            sessions.extend(detector.update(&snapshot(minute * 60, current, 50)));
+        }
+        assert_eq!(
+            sessions
+                .iter()
+                .map(|s| (s.kind, s.start, s.end))
+                .collect::<Vec<_>>(),
+            [
+                (SessionKind::Idle, at(0), at(60)),
+                (SessionKind::Charge, at(60), at(240)),
+            ]
+        );
+        assert_eq!(detector.current().unwrap().kind, SessionKind::Idle);
+    }
+
+    #[test]
+    fn test_min_duration() {
+        let mut detector = SessionDetector::new(SessionConfig {
+            min_duration: 120,
+            ..Default::default()
+        });
+        // a one minute load spike during a charge
+        let mut sessions = Vec::new();
+        for (minute, current) in [(0, 1000), (1, 1000), (2, -2000), (3, 1000), (4, 1000)] {
+            sessions.extend(detector.update(&snapshot(minute * 60, current, 50)));
+        }
+        assert_eq!(sessions, []);
+        let charge = detector.current().unwrap();
+        assert_eq!(charge.kind, SessionKind::Charge);
+        assert_eq!(charge.peak_current, 20.0);
+        assert_eq!(charge.end, at(240));
+    }
+
+    fn snapshot(seconds: i64, current: i16, soc: u8) -> Snapshot {
+        let cell = 3300 + (current.max(0) / 20);
+        SnapshotBuilder::at(seconds)
+            .cells(&[3300, cell])
+            .detail(BatteryDetail {
+                total_voltage: 1330,
+                current,
+                residual_capacity_percent: soc,
+                list_ntc: vec![250, 250 + (current.max(0) / 200)],
+                ..Default::default()
+            })
+            .build()
+    }
+
+    use super::*;
+    use crate::snapshot::{test_time as at, SnapshotBuilder};
+    use crate::BatteryDetail;
+}
+
+use crate::{Register, SessionConfig, Snapshot};
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+use std::fmt::{self, Display, Formatter};
@@ -0,0 +1,30 @@
+/// An event produced while monitoring a battery.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Event {
+    /// The battery was polled.
+    Snapshot(Snapshot),
+    /// An alert fired or cleared.
+    Alert(Alert),
+    /// The connection to the battery changed.
+    Connection(ConnectionState),
+    /// A protection counter incremented.
+    Protection(ProtectionEvent),
+    /// A charge, discharge or idle session ended.
+    Session(Session),
+}
+
+/// A destination for events, e.g. a log file.
+pub trait Sink {
+    fn handle(&mut self, event: &Event) -> Result<()>;
+}
+
+impl<S> Sink for Box<S>
+where
+    S: Sink + ?Sized,
+{
+    fn handle(&mut self, event: &Event) -> Result<()> {
+        (**self).handle(event)
+    }
+}
+
+use crate::{Alert, ConnectionState, ProtectionEvent, Result, Session, Snapshot};
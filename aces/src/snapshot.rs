@@ -0,0 +1,110 @@
+/// The values read from the battery during a single poll.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize)]
+pub struct Snapshot {
+    /// The local time at which the battery was polled.
+    pub timestamp: DateTime<FixedOffset>,
+    /// Cell voltages (mV).
+    pub voltage: Vec<i16>,
+    pub detail: BatteryDetail,
+    pub protect: BatteryProtect,
+    /// The registers read for this snapshot, the others repeat the values of
+    /// an earlier poll.
+    #[serde(skip)]
+    pub refreshed: Vec<Register>,
+}
+
+impl Snapshot {
+    /// Create a snapshot timestamped with the current local time.
+    pub fn now(voltage: Vec<i16>, detail: BatteryDetail, protect: BatteryProtect) -> Self {
+        Snapshot {
+            timestamp: Local::now().fixed_offset(),
+            voltage,
+            detail,
+            protect,
+            refreshed: Register::ALL.to_vec(),
+        }
+    }
+
+    /// Whether `register` was read for this snapshot rather than repeated.
+    pub fn is_refreshed(&self, register: Register) -> bool {
+        self.refreshed.contains(&register)
+    }
+
+    /// The highest cell voltage (mV).
+    pub fn max_cell_voltage(&self) -> Option<i16> {
+        self.voltage.iter().copied().max()
+    }
+
+    /// The lowest cell voltage (mV).
+    pub fn min_cell_voltage(&self) -> Option<i16> {
+        self.voltage.iter().copied().min()
+    }
+
+    /// The difference between the highest and lowest cell voltage (mV).
+    pub fn cell_delta(&self) -> Option<i16> {
+        Some(self.max_cell_voltage()? - self.min_cell_voltage()?)
+    }
+
+    /// The NTC temperatures (°C).
+    pub fn temperatures(&self) -> Vec<f32> {
+        self.detail
+            .list_ntc
+            .iter()
+            .map(|t| *t as f32 / 10.0)
+            .collect()
+    }
+}
+
+/// `seconds` after the time test snapshots start at.
+#[cfg(test)]
+pub(crate) fn test_time(seconds: i64) -> DateTime<FixedOffset> {
+    DateTime::parse_from_rfc3339("2026-10-18T10:00:00+02:00").unwrap()
+        + chrono::Duration::seconds(seconds)
+}
+
+/// Builds snapshots for tests, with every register refreshed.
+#[cfg(test)]
+pub(crate) struct SnapshotBuilder(Snapshot);
+
+#[cfg(test)]
+impl SnapshotBuilder {
+    /// A snapshot `seconds` after `test_time(0)`, without cells.
+    pub fn at(seconds: i64) -> Self {
+        SnapshotBuilder(Snapshot {
+            timestamp: test_time(seconds),
+            voltage: Vec::new(),
+            detail: BatteryDetail::default(),
+            protect: BatteryProtect::default(),
+            refreshed: Register::ALL.to_vec(),
+        })
+    }
+
+    pub fn timestamp(mut self, timestamp: DateTime<FixedOffset>) -> Self {
+        self.0.timestamp = timestamp;
+        self
+    }
+
+    /// The cell voltages (mV).
+    pub fn cells(mut self, cells: &[i16]) -> Self {
+        self.0.voltage = cells.to_vec();
+        self
+    }
+
+    pub fn detail(mut self, detail: BatteryDetail) -> Self {
+        self.0.detail = detail;
+        self
+    }
+
+    pub fn protect(mut self, protect: BatteryProtect) -> Self {
+        self.0.protect = protect;
+        self
+    }
+
+    pub fn build(self) -> Snapshot {
+        self.0
+    }
+}
+
+use crate::{BatteryDetail, BatteryProtect, Register};
+use chrono::{DateTime, FixedOffset, Local};
+use serde::Serialize;
@@ -0,0 +1,465 @@
+/// The format of a telemetry log.
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TelemetryFormat {
+    /// Comma-separated values with a fixed header.
+    Csv,
+    /// One JSON object per line.
+    JsonLines,
+}
+
+/// When to start a new telemetry log file.
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rotation {
+    /// Start a new file when the local date changes.
+    Daily,
+    /// Start a new file when the current one reaches the given size (bytes).
+    Size(u64),
+}
+
+/// A sink that appends one row per snapshot to a rotating log file.
+///
+/// Files are named `telemetry-<date>.<ext>`, with a sequence number added
+/// (`telemetry-<date>.<n>.<ext>`) when a file is rotated more than once a day.
+/// Sessions are appended to `sessions.<ext>`, which is never rotated.
+pub struct TelemetryLogger {
+    directory: PathBuf,
+    format: TelemetryFormat,
+    rotation: Rotation,
+    compress: bool,
+    current: Option<LogFile>,
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    date: NaiveDate,
+    size: u64,
+}
+
+impl TelemetryFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::JsonLines => "jsonl",
+        }
+    }
+}
+
+impl TelemetryLogger {
+    /// # Parameters
+    /// ---
+    /// * `directory` - The directory to write the log files to.
+    /// * `format` - The format of the log files.
+    /// * `rotation` - When to start a new log file.
+    /// * `compress` - Whether to gzip log files once they are rotated.
+    pub fn new<P>(directory: P, format: TelemetryFormat, rotation: Rotation, compress: bool) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        TelemetryLogger {
+            directory: directory.into(),
+            format,
+            rotation,
+            compress,
+            current: None,
+        }
+    }
+
+    /// Appends a snapshot to the log, rotating the log file if needed.
+    pub fn append(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let date = snapshot.timestamp.date_naive();
+        if self.needs_rotation(date) {
+            self.rotate()?;
+        }
+
+        if self.current.is_none() {
+            self.current = Some(self.open(date)?);
+        }
+        let current = self.current.as_mut().unwrap();
+
+        let mut row = String::new();
+        if current.size == 0 && self.format == TelemetryFormat::Csv {
+            row.push_str(CSV_HEADER);
+            row.push('\n');
+        }
+        match self.format {
+            TelemetryFormat::Csv => row.push_str(&csv_row(snapshot)),
+            TelemetryFormat::JsonLines => row.push_str(&serde_json::to_string(snapshot)?),
+        }
+        row.push('\n');
+
+        // write the row in one go so a partial write never interleaves rows
+        current.file.write_all(row.as_bytes())?;
+        current.file.flush()?;
+        current.size += row.len() as u64;
+
+        Ok(())
+    }
+
+    /// Appends a session to the sessions file.
+    pub fn append_session(&mut self, session: &Session) -> Result<()> {
+        fs::create_dir_all(&self.directory)?;
+        let path = self
+            .directory
+            .join(format!("{}.{}", SESSIONS_FILE, self.format.extension()));
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        let mut row = String::new();
+        if file.metadata()?.len() == 0 && self.format == TelemetryFormat::Csv {
+            row.push_str(SESSIONS_CSV_HEADER);
+            row.push('\n');
+        }
+        match self.format {
+            TelemetryFormat::Csv => row.push_str(&session_csv_row(session)),
+            TelemetryFormat::JsonLines => row.push_str(&serde_json::to_string(session)?),
+        }
+        row.push('\n');
+
+        file.write_all(row.as_bytes())?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Closes the current log file, compressing it if requested.
+    pub fn rotate(&mut self) -> Result<()> {
+        let Some(current) = self.current.take() else {
+            return Ok(());
+        };
+        drop(current.file);
+
+        if self.compress {
+            log::info!("compressing {}", current.path.display());
+            compress(&current.path)?;
+        }
+        Ok(())
+    }
+
+    fn needs_rotation(&self, date: NaiveDate) -> bool {
+        match (&self.current, self.rotation) {
+            (None, _) => false,
+            (Some(current), _) if current.date != date => true,
+            (Some(current), Rotation::Size(max)) => current.size >= max,
+            (Some(_), Rotation::Daily) => false,
+        }
+    }
+
+    fn open(&self, date: NaiveDate) -> Result<LogFile> {
+        fs::create_dir_all(&self.directory)?;
+
+        for seq in 0.. {
+            let path = self.path_for(date, seq);
+            if gz_path(&path).exists() {
+                continue;
+            }
+
+            let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+            if let Rotation::Size(max) = self.rotation {
+                if size >= max {
+                    continue;
+                }
+            }
+
+            log::info!("writing telemetry to {}", path.display());
+            let file = OpenOptions::new().create(true).append(true).open(&path)?;
+            return Ok(LogFile {
+                path,
+                file,
+                date,
+                size,
+            });
+        }
+        unreachable!()
+    }
+
+    fn path_for(&self, date: NaiveDate, seq: usize) -> PathBuf {
+        let name = match seq {
+            0 => format!("{}-{}.{}", FILE_PREFIX, date, self.format.extension()),
+            _ => format!(
+                "{}-{}.{}.{}",
+                FILE_PREFIX,
+                date,
+                seq,
+                self.format.extension()
+            ),
+        };
+        self.directory.join(name)
+    }
+}
+
+impl Sink for TelemetryLogger {
+    fn handle(&mut self, event: &Event) -> Result<()> {
+        match event {
+            Event::Snapshot(snapshot) => self.append(snapshot),
+            Event::Session(session) => self.append_session(session),
+            _ => Ok(()),
+        }
+    }
+}
+
+const FILE_PREFIX: &str = "telemetry";
+const SESSIONS_FILE: &str = "sessions";
+
+const CSV_HEADER: &str = "timestamp,total_voltage,current,residual_capacity,standard_capacity,\
+residual_capacity_percent,cycles,charge,discharge,protection_of_state,cell_voltages,ntc,\
+short_circuit,over_current_charging,over_current_discharging,cell_overvoltage,cell_undervoltage,\
+high_temp_charging,low_temp_charging,high_temp_discharging,low_temp_discharging,\
+pack_overvoltage,pack_undervoltage";
+
+/// Formats a snapshot as a CSV row matching `CSV_HEADER`.
+///
+/// Cell voltages and NTC temperatures are joined with `;` to keep the
+/// header independent of the number of cells and sensors.
+fn csv_row(snapshot: &Snapshot) -> String {
+    let detail = &snapshot.detail;
+    let protect = &snapshot.protect;
+    let join = |values: &[i16]| {
+        values
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join(";")
+    };
+
+    format!(
+        "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+        snapshot.timestamp.to_rfc3339(),
+        detail.total_voltage,
+        detail.current,
+        detail.residual_capacity,
+        detail.standard_capacity,
+        detail.residual_capacity_percent,
+        detail.cycles,
+        detail.charge,
+        detail.discharge,
+        detail.protection_of_state.0,
+        join(&snapshot.voltage),
+        join(&detail.list_ntc),
+        protect.short_circuit,
+        protect.over_current_charging,
+        protect.over_current_discharging,
+        protect.cell_overvoltage,
+        protect.cell_undervoltage,
+        protect.high_temp_charging,
+        protect.low_temp_charging,
+        protect.high_temp_discharging,
+        protect.low_temp_discharging,
+        protect.pack_overvoltage,
+        protect.pack_undervoltage,
+    )
+}
+
+const SESSIONS_CSV_HEADER: &str = "kind,start,end,start_soc,end_soc,ah,wh,peak_current,\
+min_cell_voltage,max_cell_voltage,max_total_voltage,max_temperature";
+
+/// Formats a session as a CSV row matching `SESSIONS_CSV_HEADER`, leaving
+/// unknown values empty.
+fn session_csv_row(session: &Session) -> String {
+    let optional = |value: Option<i16>| value.map(|v| v.to_string()).unwrap_or_default();
+
+    format!(
+        "{},{},{},{},{},{:.3},{:.1},{:.2},{},{},{},{}",
+        session.kind,
+        session.start.to_rfc3339(),
+        session.end.to_rfc3339(),
+        session.start_soc,
+        session.end_soc,
+        session.ah,
+        session.wh,
+        session.peak_current,
+        optional(session.min_cell_voltage),
+        optional(session.max_cell_voltage),
+        session.max_total_voltage,
+        optional(session.max_temperature),
+    )
+}
+
+fn gz_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+fn compress(path: &Path) -> Result<()> {
+    let mut input = File::open(path)?;
+    let output = File::create(gz_path(path))?;
+    let mut encoder = GzEncoder::new(output, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_csv_row() {
+        let row = csv_row(&snapshot("2024-03-01T12:00:00+01:00"));
+        assert_eq!(
+            row,
+            "2024-03-01T12:00:00+01:00,1336,643,5980,10206,59,9,true,true,0,3554;3548;3564;3565,\
+212;193;190,0,0,0,0,4,0,0,0,0,0,0"
+        );
+        assert_eq!(
+            row.split(',').count(),
+            CSV_HEADER.split(',').count(),
+            "row does not match header"
+        );
+    }
+
+    #[test]
+    fn test_rotate_daily() {
+        let dir = test_directory("daily");
+        let mut logger = TelemetryLogger::new(&dir, TelemetryFormat::Csv, Rotation::Daily, false);
+        logger
+            .append(&snapshot("2024-03-01T23:59:00+01:00"))
+            .unwrap();
+        logger
+            .append(&snapshot("2024-03-02T00:00:30+01:00"))
+            .unwrap();
+        logger
+            .append(&snapshot("2024-03-02T00:01:00+01:00"))
+            .unwrap();
+
+        let first = fs::read_to_string(dir.join("telemetry-2024-03-01.csv")).unwrap();
+        let second = fs::read_to_string(dir.join("telemetry-2024-03-02.csv")).unwrap();
+        assert_eq!(first.lines().count(), 2);
+        assert_eq!(second.lines().count(), 3);
+        assert!(second.starts_with(CSV_HEADER));
+    }
+
+    #[test]
+    fn test_rotate_size_and_compress() {
+        let dir = test_directory("size");
+        let mut logger =
+            TelemetryLogger::new(&dir, TelemetryFormat::JsonLines, Rotation::Size(1), true);
+        logger
+            .append(&snapshot("2024-03-01T12:00:00+01:00"))
+            .unwrap();
+        logger
+            .append(&snapshot("2024-03-01T12:00:30+01:00"))
+            .unwrap();
+
+        assert!(dir.join("telemetry-2024-03-01.jsonl.gz").exists());
+        assert!(!dir.join("telemetry-2024-03-01.jsonl").exists());
+
+        let current = fs::read_to_string(dir.join("telemetry-2024-03-01.1.jsonl")).unwrap();
+        let value: serde_json::Value = serde_json::from_str(current.trim()).unwrap();
+        assert_eq!(value["timestamp"], "2024-03-01T12:00:30+01:00");
+        assert_eq!(value["detail"]["total_voltage"], 1336);
+
+        let mut decoded = String::new();
+        GzDecoder::new(File::open(dir.join("telemetry-2024-03-01.jsonl.gz")).unwrap())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded.lines().count(), 1);
+    }
+
+    #[test]
+    fn test_sessions() {
+        let dir = test_directory("sessions");
+        let mut logger = TelemetryLogger::new(&dir, TelemetryFormat::Csv, Rotation::Daily, false);
+        let charge = session();
+        logger.handle(&Event::Session(charge.clone())).unwrap();
+        logger
+            .handle(&Event::Session(Session {
+                kind: SessionKind::Idle,
+                max_temperature: None,
+                ..charge.clone()
+            }))
+            .unwrap();
+
+        let csv = fs::read_to_string(dir.join("sessions.csv")).unwrap();
+        assert_eq!(
+            csv.lines().collect::<Vec<_>>(),
+            [
+                SESSIONS_CSV_HEADER,
+                "charge,2024-03-01T12:00:00+01:00,2024-03-01T14:30:00+01:00,40,95,54.250,\
+707.3,20.50,3310,3540,1420,215",
+                "idle,2024-03-01T12:00:00+01:00,2024-03-01T14:30:00+01:00,40,95,54.250,\
+707.3,20.50,3310,3540,1420,",
+            ]
+        );
+        assert_eq!(
+            SESSIONS_CSV_HEADER.split(',').count(),
+            csv.lines().nth(1).unwrap().split(',').count()
+        );
+
+        let mut logger =
+            TelemetryLogger::new(&dir, TelemetryFormat::JsonLines, Rotation::Daily, false);
+        logger.append_session(&charge).unwrap();
+        let json = fs::read_to_string(dir.join("sessions.jsonl")).unwrap();
+        let value: serde_json::Value = serde_json::from_str(json.trim()).unwrap();
+        assert_eq!(value["kind"], "charge");
+        assert_eq!(value["ah"], 54.25);
+    }
+
+    fn session() -> Session {
+        Session {
+            kind: SessionKind::Charge,
+            start: DateTime::parse_from_rfc3339("2024-03-01T12:00:00+01:00").unwrap(),
+            end: DateTime::parse_from_rfc3339("2024-03-01T14:30:00+01:00").unwrap(),
+            start_soc: 40,
+            end_soc: 95,
+            ah: 54.25,
+            wh: 707.3,
+            peak_current: 20.5,
+            min_cell_voltage: Some(3310),
+            max_cell_voltage: Some(3540),
+            max_total_voltage: 1420,
+            max_temperature: Some(215),
+        }
+    }
+
+    fn snapshot(timestamp: &str) -> Snapshot {
+        SnapshotBuilder::at(0)
+            .timestamp(DateTime::parse_from_rfc3339(timestamp).unwrap())
+            .cells(&[3554, 3548, 3564, 3565])
+            .detail(BatteryDetail {
+                total_voltage: 1336,
+                current: 643,
+                residual_capacity: 5980,
+                standard_capacity: 10206,
+                cycles: 9,
+                date_of_production: 11156,
+                software_version: 32,
+                residual_capacity_percent: 59,
+                control_state: 3,
+                charge: true,
+                discharge: true,
+                battery_number: 4,
+                list_ntc: vec![212, 193, 190],
+                ..Default::default()
+            })
+            .protect(BatteryProtect {
+                cell_undervoltage: 4,
+                ..Default::default()
+            })
+            .build()
+    }
+
+    fn test_directory(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("aces-telemetry-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    use super::*;
+    use crate::snapshot::SnapshotBuilder;
+    use crate::{BatteryDetail, BatteryProtect, SessionKind};
+    use chrono::DateTime;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+}
+
+use crate::{Event, Result, Session, Sink, Snapshot};
+use chrono::NaiveDate;
+use flate2::{write::GzEncoder, Compression};
+use serde::Deserialize;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
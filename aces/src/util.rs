@@ -16,6 +16,159 @@ pub fn u16_from_bytes(b: &[u8]) -> u16 {
     u16::from_be_bytes([b[0], b[1]])
 }
 
+/// Formats bytes as lowercase hex, without separators (e.g. `dda50300fffd77`).
+pub fn to_hex(b: &[u8]) -> String {
+    b.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parses hex bytes, ignoring case, whitespace and `:` separators.
+pub fn from_hex(s: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = s
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':')
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<_>>()?;
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    Some(digits.chunks(2).map(|d| d[0] << 4 | d[1]).collect())
+}
+
+/// Formats bytes as a hex dump, 16 bytes per line with their offset and ASCII.
+pub fn hex_dump(b: &[u8]) -> String {
+    let mut dump = String::new();
+    for (i, line) in b.chunks(16).enumerate() {
+        let hex: Vec<_> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = line
+            .iter()
+            .map(|b| match b {
+                0x20..=0x7e => *b as char,
+                _ => '.',
+            })
+            .collect();
+        dump += &format!("{:04x}  {:<47}  |{}|\n", i * 16, hex.join(" "), ascii);
+    }
+    dump
+}
+
+/// The slope of a weighted least squares line through `(x, y, weight)`
+/// points, `None` when all points share the same `x`.
+pub fn weighted_slope(points: &[(f64, f64, f64)]) -> Option<f64> {
+    let weight: f64 = points.iter().map(|(_, _, w)| w).sum();
+    if weight <= 0.0 {
+        return None;
+    }
+    let mean_x = points.iter().map(|(x, _, w)| x * w).sum::<f64>() / weight;
+    let mean_y = points.iter().map(|(_, y, w)| y * w).sum::<f64>() / weight;
+    let covariance: f64 = points
+        .iter()
+        .map(|(x, y, w)| w * (x - mean_x) * (y - mean_y))
+        .sum();
+    let variance: f64 = points
+        .iter()
+        .map(|(x, _, w)| w * (x - mean_x).powi(2))
+        .sum();
+    (variance > 0.0).then(|| covariance / variance)
+}
+
+/// A xorshift pseudo-random number generator, for jitter and simulated
+/// faults rather than anything secret.
+#[derive(Clone, Debug)]
+pub struct Xorshift(u64);
+
+impl Xorshift {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck on zero
+        Xorshift(seed | 1)
+    }
+
+    /// Seeds the generator from the system time.
+    pub fn from_time() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        Self::new(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// A JSON file keeping state across restarts.
+///
+/// The file is replaced only once the new contents are complete, and only
+/// when they changed. State changing on every poll is written at most once
+/// per `interval`, so a restart may lose up to the last interval.
+pub(crate) struct StateFile {
+    path: PathBuf,
+    interval: chrono::Duration,
+    /// When the file was last written, and what with.
+    written: Option<(DateTime<FixedOffset>, String)>,
+}
+
+impl StateFile {
+    /// Opens the state file at `path`, if any, returning the state it holds
+    /// or the default one when it does not exist yet.
+    pub fn open<S>(path: Option<&PathBuf>, interval: chrono::Duration) -> Result<(S, Option<Self>)>
+    where
+        S: DeserializeOwned + Default,
+    {
+        let Some(path) = path else {
+            return Ok((S::default(), None));
+        };
+        let state = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(path)?)?
+        } else {
+            S::default()
+        };
+        let file = StateFile {
+            path: path.clone(),
+            interval,
+            written: None,
+        };
+        Ok((state, Some(file)))
+    }
+
+    /// Writes `state` if it changed since the last write, and that write is
+    /// at least the interval before `now`.
+    pub fn save<S: Serialize>(&mut self, state: &S, now: DateTime<FixedOffset>) -> Result<()> {
+        let contents = serde_json::to_string_pretty(state)?;
+        if let Some((time, written)) = &self.written {
+            if *written == contents || now - *time < self.interval {
+                return Ok(());
+            }
+        }
+        let temporary = self.path.with_extension("tmp");
+        fs::write(&temporary, &contents)?;
+        fs::rename(temporary, &self.path)?;
+        self.written = Some((now, contents));
+        Ok(())
+    }
+}
+
+/// Runs a future that never has to wait, e.g. one driven by mocks.
+///
+/// # Panics
+///
+/// This function will panic if the future is not ready immediately.
+#[cfg(test)]
+pub fn block_on<F>(future: F) -> F::Output
+where
+    F: std::future::Future,
+{
+    let mut future = std::pin::pin!(future);
+    let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+    match future.as_mut().poll(&mut cx) {
+        std::task::Poll::Ready(output) => output,
+        std::task::Poll::Pending => panic!("future is not ready"),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -32,5 +185,82 @@ mod tests {
         assert_eq!(u16_from_bytes(&[0xff, 0xfe]), 0xfffe);
     }
 
+    #[test]
+    fn test_hex() {
+        assert_eq!(to_hex(&[0xdd, 0xa5, 0x03, 0x00]), "dda50300");
+        assert_eq!(
+            from_hex("DDA50300FFFD77"),
+            Some(vec![0xdd, 0xa5, 0x03, 0x00, 0xff, 0xfd, 0x77])
+        );
+        assert_eq!(from_hex("dd a5:03"), Some(vec![0xdd, 0xa5, 0x03]));
+        assert_eq!(from_hex("dda"), None);
+        assert_eq!(from_hex("zz"), None);
+        assert_eq!(from_hex(""), Some(vec![]));
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        assert_eq!(
+            hex_dump(b"\xdd\x05\x00\x13ACES-12V100Ah-LFP\x01"),
+            "0000  dd 05 00 13 41 43 45 53 2d 31 32 56 31 30 30 41  |....ACES-12V100A|\n\
+             0010  68 2d 4c 46 50 01                                |h-LFP.|\n"
+        );
+        assert_eq!(hex_dump(&[]), "");
+    }
+
+    #[test]
+    fn test_weighted_slope() {
+        let points = [(0.0, 1.0, 1.0), (1.0, 3.0, 2.0), (2.0, 5.0, 0.5)];
+        assert!((weighted_slope(&points).unwrap() - 2.0).abs() < 1e-12);
+        assert_eq!(weighted_slope(&[(1.0, 1.0, 1.0), (1.0, 2.0, 1.0)]), None);
+        assert_eq!(weighted_slope(&[]), None);
+    }
+
+    #[test]
+    fn test_xorshift() {
+        let mut a = Xorshift::new(42);
+        let mut b = Xorshift::new(42);
+        let values: Vec<u64> = (0..3).map(|_| a.next_u64()).collect();
+        assert_eq!(values, (0..3).map(|_| b.next_u64()).collect::<Vec<_>>());
+        assert_ne!(values[0], values[1]);
+        // a zero seed does not get stuck
+        assert_ne!(Xorshift::new(0).next_u64(), 0);
+    }
+
+    #[test]
+    fn test_state_file() {
+        let path = std::env::temp_dir().join(format!("aces-state-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let start = DateTime::parse_from_rfc3339("2026-10-18T10:00:00+02:00").unwrap();
+        let at = |seconds| start + chrono::Duration::seconds(seconds);
+        let read = || serde_json::from_str::<u32>(&fs::read_to_string(&path).unwrap()).unwrap();
+
+        let (state, file) = StateFile::open::<u32>(None, chrono::Duration::zero()).unwrap();
+        assert_eq!((state, file.is_none()), (0, true));
+
+        let (state, file) =
+            StateFile::open::<u32>(Some(&path), chrono::Duration::seconds(60)).unwrap();
+        let mut file = file.unwrap();
+        assert_eq!(state, 0);
+        file.save(&1, at(0)).unwrap();
+        assert_eq!(read(), 1);
+        // a change within the interval waits for a later one
+        file.save(&2, at(30)).unwrap();
+        assert_eq!(read(), 1);
+        file.save(&3, at(60)).unwrap();
+        assert_eq!(read(), 3);
+
+        let (state, _) = StateFile::open::<u32>(Some(&path), chrono::Duration::zero()).unwrap();
+        assert_eq!(state, 3);
+        fs::remove_file(&path).unwrap();
+    }
+
     use super::*;
 }
+
+use crate::Result;
+use chrono::{DateTime, FixedOffset};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
@@ -0,0 +1,282 @@
+/// A configuration value the BMS should not be given, see
+/// `BatteryConfig::validate`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Violation {
+    /// The name of the offending register.
+    pub register: &'static str,
+    pub message: String,
+}
+
+/// Cell voltages (mV), wide enough for every lithium chemistry.
+const CELL_VOLTAGE: RangeInclusive<i64> = 1000..=4500;
+/// Temperatures (0.1 °C).
+const TEMPERATURE: RangeInclusive<i64> = -400..=1000;
+
+/// The values accepted for each register, by name. Registers not listed
+/// accept any value of their type.
+const RANGES: &[(&str, RangeInclusive<i64>)] = &[
+    ("design_capacity", 1..=65535),
+    ("cycle_capacity", 1..=65535),
+    ("cell_full_voltage", CELL_VOLTAGE),
+    ("cell_empty_voltage", CELL_VOLTAGE),
+    ("self_discharge_rate", 0..=1000),
+    ("charge_over_temp", TEMPERATURE),
+    ("charge_over_temp_release", TEMPERATURE),
+    ("charge_under_temp", TEMPERATURE),
+    ("charge_under_temp_release", TEMPERATURE),
+    ("discharge_over_temp", TEMPERATURE),
+    ("discharge_over_temp_release", TEMPERATURE),
+    ("discharge_under_temp", TEMPERATURE),
+    ("discharge_under_temp_release", TEMPERATURE),
+    ("pack_overvoltage", 1..=65535),
+    ("pack_overvoltage_release", 1..=65535),
+    ("pack_undervoltage", 1..=65535),
+    ("pack_undervoltage_release", 1..=65535),
+    ("cell_overvoltage", CELL_VOLTAGE),
+    ("cell_overvoltage_release", CELL_VOLTAGE),
+    ("cell_undervoltage", CELL_VOLTAGE),
+    ("cell_undervoltage_release", CELL_VOLTAGE),
+    ("charge_overcurrent", 1..=32767),
+    ("discharge_overcurrent", -32768..=-1),
+    ("balance_start_voltage", CELL_VOLTAGE),
+    ("balance_window", 0..=500),
+    ("shunt_resistance", 1..=65535),
+    ("cell_count", 1..=32),
+    ("cell_voltage_80", CELL_VOLTAGE),
+    ("cell_voltage_60", CELL_VOLTAGE),
+    ("cell_voltage_40", CELL_VOLTAGE),
+    ("cell_voltage_20", CELL_VOLTAGE),
+    ("hard_cell_overvoltage", CELL_VOLTAGE),
+    ("hard_cell_undervoltage", CELL_VOLTAGE),
+];
+
+/// The longest text the BMS keeps in a string register (bytes).
+const TEXT_LENGTH: usize = 31;
+
+/// Pairs of registers where the first must be below the second.
+const ORDER: &[(&str, &str)] = &[
+    ("cell_empty_voltage", "cell_full_voltage"),
+    ("cell_undervoltage", "cell_overvoltage"),
+    ("cell_overvoltage_release", "cell_overvoltage"),
+    ("cell_undervoltage", "cell_undervoltage_release"),
+    ("pack_undervoltage", "pack_overvoltage"),
+    ("pack_overvoltage_release", "pack_overvoltage"),
+    ("pack_undervoltage", "pack_undervoltage_release"),
+    ("charge_under_temp", "charge_over_temp"),
+    ("charge_over_temp_release", "charge_over_temp"),
+    ("charge_under_temp", "charge_under_temp_release"),
+    ("discharge_under_temp", "discharge_over_temp"),
+    ("discharge_over_temp_release", "discharge_over_temp"),
+    ("discharge_under_temp", "discharge_under_temp_release"),
+    ("cell_voltage_20", "cell_voltage_40"),
+    ("cell_voltage_40", "cell_voltage_60"),
+    ("cell_voltage_60", "cell_voltage_80"),
+    ("cell_overvoltage", "hard_cell_overvoltage"),
+    ("hard_cell_undervoltage", "cell_undervoltage"),
+];
+
+/// Pack limits (10 mV) and the cell limits (mV) they should match once
+/// divided by `cell_count`.
+const PACK_LIMITS: &[(&str, &str)] = &[
+    ("pack_overvoltage", "cell_overvoltage"),
+    ("pack_undervoltage", "cell_undervoltage"),
+];
+
+/// How far a pack limit may be from the matching cell limit (mV per cell).
+const PACK_TOLERANCE: i64 = 300;
+
+impl BatteryConfig {
+    /// Checks every register against its range and the rules between
+    /// registers: releases on the safe side of their protections, ordered
+    /// limits and pack limits consistent with the cell count.
+    pub fn validate(&self) -> Result<Vec<Violation>> {
+        let values = self.values()?;
+        let value = |name: &str| values[name].as_integer().unwrap();
+
+        let mut violations = check_ranges(&values, |_| true);
+        violations.extend(check_texts(&values, |_| true));
+        for (low, high) in ORDER {
+            if value(low) >= value(high) {
+                violations.push(Violation {
+                    register: register_name(low),
+                    message: format!("{} must be below {} ({})", value(low), high, value(high)),
+                });
+            }
+        }
+
+        let cells = value("cell_count");
+        if cells > 0 {
+            for (pack, cell) in PACK_LIMITS {
+                let per_cell = value(pack) * 10 / cells;
+                if (per_cell - value(cell)).abs() > PACK_TOLERANCE {
+                    violations.push(Violation {
+                        register: register_name(pack),
+                        message: format!(
+                            "{} is {} mV per cell for {} cells, more than {} mV from {} ({})",
+                            value(pack),
+                            per_cell,
+                            cells,
+                            PACK_TOLERANCE,
+                            cell,
+                            value(cell)
+                        ),
+                    });
+                }
+            }
+        }
+        Ok(violations)
+    }
+
+    /// Checks the payload of a single register against its range, for writes
+    /// of one register where the rules between registers can not be checked.
+    pub fn validate_register(register: u8, payload: &[u8]) -> Result<Vec<Violation>> {
+        let Some(name) = Self::REGISTERS
+            .iter()
+            .find(|r| r.address == register)
+            .map(|r| r.name)
+        else {
+            return Ok(Vec::new());
+        };
+        let mut config = BatteryConfig::default();
+        if let Err(err) = config.decode(register, payload) {
+            return Ok(vec![Violation {
+                register: name,
+                message: format!("invalid payload {}: {}", to_hex(payload), err),
+            }]);
+        }
+        let values = config.values()?;
+        let mut violations = check_ranges(&values, |n| n == name);
+        violations.extend(check_texts(&values, |n| n == name));
+        Ok(violations)
+    }
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.register, self.message)
+    }
+}
+
+fn check_ranges(values: &toml::Table, checked: impl Fn(&str) -> bool) -> Vec<Violation> {
+    RANGES
+        .iter()
+        .filter(|(name, _)| checked(name))
+        .filter_map(|(name, range)| {
+            let value = values[*name].as_integer().unwrap();
+            (!range.contains(&value)).then(|| Violation {
+                register: register_name(name),
+                message: format!("{} is outside {}..={}", value, range.start(), range.end()),
+            })
+        })
+        .collect()
+}
+
+/// Text registers longer than `TEXT_LENGTH`.
+fn check_texts(values: &toml::Table, checked: impl Fn(&str) -> bool) -> Vec<Violation> {
+    BatteryConfig::REGISTERS
+        .iter()
+        .filter(|register| checked(register.name))
+        .filter_map(|register| {
+            let text = values[register.name].as_str()?;
+            (text.len() > TEXT_LENGTH).then(|| Violation {
+                register: register.name,
+                message: format!("{} bytes is longer than {}", text.len(), TEXT_LENGTH),
+            })
+        })
+        .collect()
+}
+
+/// The `'static` name of a register from the tables above.
+fn register_name(name: &str) -> &'static str {
+    BatteryConfig::register(name).unwrap().name
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_tables() {
+        for (name, _) in RANGES {
+            assert!(BatteryConfig::register(name).is_some(), "{}", name);
+        }
+        for (low, high) in ORDER.iter().chain(PACK_LIMITS) {
+            assert!(BatteryConfig::register(low).is_some(), "{}", low);
+            assert!(BatteryConfig::register(high).is_some(), "{}", high);
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(valid_config().validate().unwrap(), []);
+
+        let config = BatteryConfig {
+            cell_overvoltage_release: 3700,
+            balance_window: 600,
+            discharge_overcurrent: 10000,
+            ..valid_config()
+        };
+        assert_eq!(
+            messages(config.validate().unwrap()),
+            [
+                "discharge_overcurrent: 10000 is outside -32768..=-1",
+                "balance_window: 600 is outside 0..=500",
+                "cell_overvoltage_release: 3700 must be below cell_overvoltage (3650)",
+            ]
+        );
+
+        let config = BatteryConfig {
+            manufacturer: "A".repeat(300),
+            ..valid_config()
+        };
+        assert_eq!(
+            messages(config.validate().unwrap()),
+            ["manufacturer: 300 bytes is longer than 31"]
+        );
+
+        // limits of a 4 cell pack on an 8 cell pack
+        let config = BatteryConfig {
+            cell_count: 8,
+            ..valid_config()
+        };
+        assert_eq!(
+            messages(config.validate().unwrap()),
+            [
+                "pack_overvoltage: 1460 is 1825 mV per cell for 8 cells, \
+more than 300 mV from cell_overvoltage (3650)",
+                "pack_undervoltage: 1000 is 1250 mV per cell for 8 cells, \
+more than 300 mV from cell_undervoltage (2500)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_register() {
+        assert_eq!(
+            BatteryConfig::validate_register(0x24, &[0x0e, 0x42]).unwrap(),
+            []
+        );
+        assert_eq!(
+            messages(BatteryConfig::validate_register(0x24, &[0x13, 0x88]).unwrap()),
+            ["cell_overvoltage: 5000 is outside 1000..=4500"]
+        );
+        assert_eq!(
+            messages(BatteryConfig::validate_register(0x24, &[0x13]).unwrap()),
+            ["cell_overvoltage: invalid payload 13: Invalid data"]
+        );
+        assert_eq!(
+            BatteryConfig::validate_register(0xe1, &[0x00, 0x02]).unwrap(),
+            []
+        );
+    }
+
+    fn messages(violations: Vec<Violation>) -> Vec<String> {
+        violations.iter().map(Violation::to_string).collect()
+    }
+
+    use super::*;
+    use crate::eeprom::valid_config;
+}
+
+use crate::util::to_hex;
+use crate::{BatteryConfig, Result};
+use std::fmt::{self, Display, Formatter};
+use std::ops::RangeInclusive;
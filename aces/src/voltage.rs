@@ -6,7 +6,7 @@ impl BatteryVoltage {
         if msg.is_empty() {
             return Err(ParseError::NotEnoughData);
         }
-        if msg.len() % 2 != 0 {
+        if !msg.len().is_multiple_of(2) {
             return Err(ParseError::NotEnoughData);
         }
 
@@ -20,6 +20,11 @@ impl BatteryVoltage {
 
         Ok(BatteryVoltage(list))
     }
+
+    pub fn to_message(&self) -> Vec<u8> {
+        // device uses big endian encoding
+        self.0.iter().flat_map(|v| v.to_be_bytes()).collect()
+    }
 }
 
 #[cfg(test)]
@@ -42,6 +47,15 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_to_message() {
+        let msg = [0x0d, 0x0b, 0x0d, 0x0d, 0x0d, 0x0f];
+        assert_eq!(
+            BatteryVoltage::parse_message(&msg).unwrap().to_message(),
+            msg
+        );
+    }
+
     use super::*;
 }
 use super::{util::i16_from_bytes, ParseError, ParseResult};
@@ -1,14 +1,10 @@
 #![no_main]
 
 mod notifications;
+mod transport;
 
 // source: https://github.com/taks/esp32-nimble/blob/develop/examples/ble_client.rs
 
-/// The name of the target device (e.g. the device to connect to).
-const TARGET_DEVICE_NAME: &str = "AL12V100HFA0191";
-/// The sleep duration in seconds.
-const SLEEP_DURATION: u64 = 30;
-
 esp_idf_sys::esp_app_desc!();
 
 #[no_mangle]
@@ -16,110 +12,73 @@ fn app_main() {
     esp_idf_sys::link_patches();
     esp_idf_svc::log::EspLogger::initialize_default();
 
+    // there is no command line or environment on the device, so the
+    // configuration is written to NVS instead of compiled in
+    let config = aces::ConfigSource::load_stored(read_config());
+
     let peripherals = Peripherals::take().unwrap();
-    let mut timer = TimerDriver::new(peripherals.timer00, &TimerConfig::new()).unwrap();
+    let timer = TimerDriver::new(peripherals.timer00, &TimerConfig::new()).unwrap();
 
     task::block_on(async {
-        let adapter = BLEDevice::take();
-        let device = find_target_device(adapter).await;
-
-        let mut client = BLEClient::new();
-        connect_to_device(device.addr(), &mut client).await;
-
-        let service = client
-            .get_service(Uuid16(aces::SERVICE_UUID))
-            .await
-            .unwrap();
-        let mut characteristics = service.get_characteristics().await.unwrap();
-        let rx = characteristics
-            .find(|char| char.uuid() == Uuid16(aces::RX_UUID))
-            .expect("RX characteristic not found");
-        let tx = characteristics
-            .find(|char| char.uuid() == Uuid16(aces::TX_UUID))
-            .expect("TX characteristic not found");
-
-        let mut notif = notifications::Notifications::subscribe(rx).await;
+        let transport = transport::NimbleTransport::new(config.clone());
+        let clock = transport::TimerClock::new(timer);
+        let mut supervisor = aces::ConnectionSupervisor::new(transport, clock, &config.connection);
 
-        tx.write_value(aces::Request::Clear.bytes(), false)
-            .await
-            .unwrap();
+        // the BLE transport is never closed, so this only returns connected
+        supervisor.connect().await.unwrap();
 
-        timer.delay(timer.tick_hz()).await.unwrap();
-        // clear any stale notifications
-        notif.clear();
+        let mut scheduler = aces::Scheduler::from_config(&config.poll);
 
         loop {
-            tx.write_value(aces::Request::BatteryVoltage.bytes(), false)
-                .await
-                .unwrap();
-            let voltage = aces::read_voltage(&mut notif).await.unwrap();
-            println!("voltage: {:#?}", voltage);
-
-            tx.write_value(aces::Request::BatteryDetail.bytes(), false)
-                .await
-                .unwrap();
-            let detail = aces::read_detail(&mut notif).await.unwrap();
-            println!("detail: {:#?}", detail);
-
-            tx.write_value(aces::Request::BatteryProtect.bytes(), false)
-                .await
-                .unwrap();
-            let protect = aces::read_protect(&mut notif).await.unwrap();
-            println!("protect: {:#?}", protect);
+            for register in scheduler.wait(supervisor.clock()).await {
+                match poll(&mut supervisor, register).await {
+                    Ok(value) if config.output.stdout => {
+                        println!("{:?}: {}", register, value)
+                    }
+                    Ok(_) => {}
+                    Err(err) => log::error!("failed to poll {:?}: {}", register, err),
+                }
+            }
+            // there are no sinks on the device, the supervisor already logs its events
+            supervisor.take_events();
 
             task::do_yield();
-
-            log::info!("sleeping for {} seconds", SLEEP_DURATION);
-            timer.delay(timer.tick_hz() * SLEEP_DURATION).await.unwrap();
         }
-
-        // client.disconnect().unwrap();
     });
 }
 
-async fn find_target_device(adapter: &BLEDevice) -> BLEAdvertisedDevice {
-    log::info!("starting scan ...");
-
-    let scan = adapter.get_scan();
-    let device = Arc::new(Mutex::new(None));
-
-    let device0 = device.clone();
-    scan.active_scan(true)
-        .interval(100)
-        .window(99)
-        .on_result(move |scan, device| {
-            if device.name() == TARGET_DEVICE_NAME {
-                scan.stop().unwrap();
-                (*device0.lock()) = Some(device.clone());
-            }
-        });
-    scan.start(3_000).await.unwrap();
+async fn poll<T, C>(
+    supervisor: &mut aces::ConnectionSupervisor<T, C>,
+    register: aces::Register,
+) -> aces::Result<String>
+where
+    T: aces::Transport,
+    C: aces::Clock,
+{
+    Ok(match register {
+        aces::Register::Voltage => format!("{:#?}", supervisor.request_voltage().await?),
+        aces::Register::Detail => format!("{:#?}", supervisor.request_detail().await?),
+        aces::Register::Protect => format!("{:#?}", supervisor.request_protect().await?),
+    })
+}
 
-    log::info!("finished scan");
+/// Reads the configuration stored in NVS, `None` when there is none.
+fn read_config() -> Result<Option<String>, EspError> {
+    let partition = EspDefaultNvsPartition::take()?;
+    let nvs = EspNvs::new(partition, aces::ConfigSource::NVS_NAMESPACE, false)?;
 
-    return match &*device.lock() {
-        Some(device) => device.clone(),
-        None => panic!("ACES battery not found"),
+    let key = aces::ConfigSource::NVS_KEY;
+    let Some(len) = nvs.str_len(key)? else {
+        return Ok(None);
     };
+    let mut buf = vec![0; len];
+    Ok(nvs.get_str(key, &mut buf)?.map(str::to_string))
 }
 
-async fn connect_to_device(address: &BLEAddress, client: &mut BLEClient) {
-    log::info!("connecting to device ...");
-    client.on_connect(|client| {
-        client.update_conn_params(120, 120, 0, 60).unwrap();
-    });
-    client.connect(address).await.unwrap();
-    log::info!("connected to device");
-}
-
-use esp32_nimble::{
-    utilities::{mutex::Mutex, BleUuid::Uuid16},
-    BLEAddress, BLEAdvertisedDevice, BLEClient, BLEDevice,
-};
 use esp_idf_hal::{
     prelude::Peripherals,
     task,
     timer::{TimerConfig, TimerDriver},
 };
-use esp_idf_sys as _;
-use std::sync::Arc;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs};
+use esp_idf_sys::{self as _, EspError};
@@ -3,7 +3,7 @@ pub struct Notifications {
 }
 
 impl Notifications {
-    pub async fn subscribe(characteristic: &mut BLERemoteCharacteristic) -> Self {
+    pub async fn subscribe(characteristic: &mut BLERemoteCharacteristic) -> Result<Self, BLEError> {
         let state = Arc::new((Mutex::new(VecDeque::new()), Condvar::new()));
 
         let state0 = Arc::clone(&state);
@@ -15,16 +15,9 @@ impl Notifications {
                 state0.1.notify_one();
             })
             .subscribe_notify(false)
-            .await
-            .unwrap();
+            .await?;
 
-        Notifications { state }
-    }
-
-    pub fn clear(&mut self) {
-        let mut lock = self.state.0.lock();
-        lock.clear();
-        self.state.1.notify_one();
+        Ok(Notifications { state })
     }
 }
 
@@ -42,10 +35,29 @@ impl aces::NotificationsReceiver for Notifications {
         self.state.1.notify_one();
         val
     }
+
+    fn next_timeout(&mut self, timeout: Duration) -> Option<Vec<u8>> {
+        let deadline = Instant::now() + timeout;
+
+        let mut locked = self.state.0.lock();
+        // protect agains spurious wake-ups
+        while locked.is_empty() {
+            let remaining = deadline.checked_duration_since(Instant::now())?;
+            (locked, _) = self.state.1.wait_timeout(locked, remaining);
+        }
+
+        let val = locked.pop_front().unwrap();
+        self.state.1.notify_one();
+        Some(val)
+    }
 }
 
 use esp32_nimble::{
     utilities::mutex::{Condvar, Mutex},
-    BLERemoteCharacteristic,
+    BLEError, BLERemoteCharacteristic,
+};
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use std::{collections::VecDeque, sync::Arc};
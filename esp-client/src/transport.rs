@@ -0,0 +1,191 @@
+/// A connection to the battery over NimBLE.
+pub struct NimbleTransport {
+    client: BLEClient,
+    config: aces::Config,
+}
+
+/// A clock backed by a hardware timer.
+pub struct TimerClock {
+    timer: TimerDriver<'static>,
+    start: Instant,
+}
+
+impl NimbleTransport {
+    pub fn new(config: aces::Config) -> Self {
+        NimbleTransport {
+            client: BLEClient::new(),
+            config,
+        }
+    }
+}
+
+impl aces::Transport for NimbleTransport {
+    type Receiver = Notifications;
+
+    async fn connect(&mut self) -> Result<Notifications> {
+        if self.client.connected() {
+            let _ = self.client.disconnect();
+        }
+
+        let adapter = BLEDevice::take();
+        let device = find_target_device(adapter, &self.config)
+            .await
+            .ok_or(NotFound)?;
+        connect_to_device(device.addr(), &mut self.client).await?;
+
+        let service = self
+            .client
+            .get_service(Uuid16(aces::SERVICE_UUID))
+            .await
+            .map_err(ble_error)?;
+        let rx = service
+            .get_characteristic(Uuid16(aces::RX_UUID))
+            .await
+            .map_err(ble_error)?;
+
+        Notifications::subscribe(rx).await.map_err(ble_error)
+    }
+
+    async fn is_connected(&mut self) -> bool {
+        self.client.connected()
+    }
+
+    async fn write(&mut self, value: &[u8]) -> Result<()> {
+        let service = self
+            .client
+            .get_service(Uuid16(aces::SERVICE_UUID))
+            .await
+            .map_err(ble_error)?;
+        let tx = service
+            .get_characteristic(Uuid16(aces::TX_UUID))
+            .await
+            .map_err(ble_error)?;
+        tx.write_value(value, false).await.map_err(ble_error)
+    }
+}
+
+impl TimerClock {
+    pub fn new(timer: TimerDriver<'static>) -> Self {
+        TimerClock {
+            timer,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl aces::Clock for TimerClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    async fn sleep(&mut self, duration: Duration) {
+        let ticks = self.timer.tick_hz() * duration.as_millis() as u64 / 1_000;
+        self.timer.delay(ticks).await.unwrap();
+    }
+}
+
+async fn find_target_device(
+    adapter: &BLEDevice,
+    config: &aces::Config,
+) -> Option<BLEAdvertisedDevice> {
+    log::info!("starting scan ...");
+
+    let matcher = aces::Matcher::from_config(&config.device)
+        .expect("the device is checked when the configuration is loaded");
+
+    let scan = adapter.get_scan();
+    let candidates = Arc::new(Mutex::new(Vec::new()));
+
+    let candidates0 = candidates.clone();
+    let matcher0 = matcher.clone();
+    scan.active_scan(true)
+        .interval(100)
+        .window(99)
+        .on_result(move |_scan, device| {
+            let adv = advertisement(device);
+            if matcher0.matches(&adv) {
+                candidates0.lock().push((device.clone(), adv));
+            }
+        });
+    scan.start((config.connection.scan_timeout * 1_000) as i32)
+        .await
+        .ok()?;
+
+    log::info!("finished scan");
+
+    let candidates = candidates.lock();
+    let Some(idx) = matcher.best(candidates.iter().map(|(_, adv)| adv)) else {
+        log::info!("ACES battery not found");
+        return None;
+    };
+    let (device, best) = &candidates[idx];
+    log::info!(
+        "found {:?} ({}, rssi {:?})",
+        best.name,
+        best.address,
+        best.rssi
+    );
+
+    Some(device.clone())
+}
+
+fn advertisement(device: &BLEAdvertisedDevice) -> aces::Advertisement {
+    let service_uuids = device
+        .get_service_uuids()
+        .filter_map(|uuid| match uuid {
+            Uuid16(uuid) => Some(aces::uuid_from_u16(*uuid)),
+            Uuid128(bytes) => Some(u128::from_le_bytes(*bytes)),
+            _ => None,
+        })
+        .collect();
+
+    // the first two bytes hold the company identifier
+    let mut manufacturer_data = HashMap::new();
+    if let Some(data) = device.get_manufacture_data() {
+        if data.len() >= 2 {
+            manufacturer_data.insert(u16::from_le_bytes([data[0], data[1]]), data[2..].to_vec());
+        }
+    }
+
+    aces::Advertisement {
+        name: Some(device.name().to_string()).filter(|name| !name.is_empty()),
+        address: device.addr().to_string(),
+        service_uuids,
+        manufacturer_data,
+        rssi: Some(device.rssi() as i16),
+    }
+}
+
+async fn connect_to_device(address: &BLEAddress, client: &mut BLEClient) -> Result<()> {
+    log::info!("connecting to device ...");
+    client.on_connect(|client| {
+        client.update_conn_params(120, 120, 0, 60).unwrap();
+    });
+    client.connect(address).await.map_err(ble_error)?;
+    log::info!("connected to device");
+    Ok(())
+}
+
+fn ble_error(err: BLEError) -> Box<dyn std::error::Error> {
+    format!("{:?}", err).into()
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Not found")]
+struct NotFound;
+
+use crate::notifications::Notifications;
+use aces::Result;
+use esp32_nimble::{
+    utilities::{
+        mutex::Mutex,
+        BleUuid::{Uuid128, Uuid16},
+    },
+    BLEAddress, BLEAdvertisedDevice, BLEClient, BLEDevice, BLEError,
+};
+use esp_idf_hal::timer::TimerDriver;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
@@ -6,40 +6,60 @@ mod channel;
 
 /// The advertised name of the device.
 const DEVICE_NAME: &str = "ACES-MOCK";
+/// The NVS key holding the scenario to replay (TOML), see `aces::Scenario`.
+const SCENARIO_KEY: &str = "scenario";
 
 esp_idf_sys::esp_app_desc!();
 
-enum StateMachine {
-    Disconnected,
-    Notify(::aces::Request),
-    ReadRequest(Vec<u8>),
-    WriteResponse(Vec<u8>),
-}
-
 #[no_mangle]
 fn app_main() {
     esp_idf_sys::link_patches();
     esp_idf_svc::log::EspLogger::initialize_default();
 
+    let partition = EspDefaultNvsPartition::take().ok();
+    let config =
+        aces::ConfigSource::load_stored(read_nvs(partition.clone(), aces::ConfigSource::NVS_KEY));
+
     let mut device = device::setup_ble_device(DEVICE_NAME);
 
-    let mut state = StateMachine::Disconnected;
+    let mut responder = aces::Responder::new(load_responses(partition));
+    let mut faults = match aces::FaultInjector::from_config(&config.faults) {
+        Ok(faults) => {
+            log::info!("running fault scenario {}", config.faults.scenario);
+            faults
+        }
+        Err(err) => {
+            log::error!("{}, running without faults", err);
+            aces::FaultInjector::new(Vec::new())
+        }
+    };
 
     let mut counter = 0;
 
     loop {
-        log::trace!("counter: {}, state: {:?}", counter, state);
+        log::trace!("counter: {}, state: {:?}", counter, responder.state());
 
-        if !device.is_connected() {
-            state = StateMachine::Disconnected;
+        if device.is_connected() {
+            responder.connected();
+        } else {
+            responder.disconnected();
         }
 
-        state = match state {
-            StateMachine::Disconnected => fsm::state_disconnected(&device),
-            StateMachine::Notify(req) => fsm::state_notify(&mut device, req),
-            StateMachine::ReadRequest(buff) => fsm::state_read_request(&mut device, buff),
-            StateMachine::WriteResponse(resp) => fsm::state_write_response(&mut device, resp),
+        let notifications = if *responder.state() == aces::ResponderState::Disconnected {
+            FreeRtos::delay_ms(10);
+            Vec::new()
+        } else {
+            match device.recv_timeout(aces::RESPONDER_TIMEOUT) {
+                Some(chunk) => responder.write(&chunk),
+                None => responder.idle(),
+            }
         };
+        for notification in faults.apply(notifications) {
+            if !notification.delay.is_zero() {
+                FreeRtos::delay_ms(notification.delay.as_millis() as u32);
+            }
+            device.set_response(&notification.value);
+        }
 
         counter += 1;
 
@@ -47,64 +67,42 @@ fn app_main() {
     }
 }
 
-mod fsm {
-    pub fn state_disconnected(device: &device::Device) -> StateMachine {
-        esp_idf_hal::delay::FreeRtos::delay_ms(10);
-
-        if device.is_connected() {
-            StateMachine::Notify(::aces::Request::BatteryDetail)
-        } else {
-            StateMachine::Disconnected
-        }
-    }
-
-    pub fn state_notify(device: &mut device::Device, req: ::aces::Request) -> StateMachine {
-        if let Some(chunk) = device.try_recv() {
-            // clear the response
-            device.set_response(&[]);
-
-            StateMachine::ReadRequest(chunk)
-        } else {
-            let resp = device::response_for_request(&req);
-            device.set_response(&resp);
-
-            esp_idf_hal::delay::FreeRtos::delay_ms(500);
-
-            let req = match req {
-                ::aces::Request::BatteryDetail => ::aces::Request::BatteryProtect,
-                ::aces::Request::BatteryProtect => ::aces::Request::BatteryVoltage,
-                ::aces::Request::BatteryVoltage => ::aces::Request::BatteryDetail,
-                _ => unreachable!(),
-            };
-            StateMachine::Notify(req)
-        }
-    }
-
-    pub fn state_read_request(device: &mut device::Device, mut buff: Vec<u8>) -> StateMachine {
-        if ::aces::Request::is_complete_request(&buff) {
-            if let Ok(req) = ::aces::Request::parse_request(&buff) {
-                let resp = device::response_for_request(&req);
-                StateMachine::WriteResponse(resp)
-            } else {
-                StateMachine::ReadRequest(Vec::new())
-            }
-        } else {
-            if let Some(mut chunk) = device.recv_timeout() {
-                buff.append(&mut chunk);
-                // clear the response
-                device.set_response(&[]);
+/// Replays the scenario stored in NVS, falling back to the static responses.
+fn load_responses(partition: Option<EspDefaultNvsPartition>) -> Box<dyn aces::ResponseSource> {
+    match read_nvs(partition, SCENARIO_KEY) {
+        Ok(Some(contents)) => match aces::Scenario::parse(&contents) {
+            Ok(scenario) => {
+                log::info!(
+                    "replaying scenario ({} keyframes)",
+                    scenario.keyframes.len()
+                );
+                return Box::new(aces::ScenarioResponses::new(
+                    scenario,
+                    aces::SystemClock::new(),
+                ));
             }
-            StateMachine::ReadRequest(buff)
-        }
-    }
-
-    pub fn state_write_response(device: &mut device::Device, resp: Vec<u8>) -> StateMachine {
-        device.set_response(&resp);
-
-        StateMachine::ReadRequest(Vec::new())
+            Err(err) => log::error!("invalid scenario: {}", err),
+        },
+        Ok(None) => log::info!("no scenario stored, using static responses"),
+        Err(err) => log::error!("failed to read scenario: {}", err),
     }
+    Box::new(aces::StaticResponses)
+}
 
-    use super::*;
+fn read_nvs(
+    partition: Option<EspDefaultNvsPartition>,
+    key: &str,
+) -> Result<Option<String>, EspError> {
+    let Some(partition) = partition else {
+        return Ok(None);
+    };
+    let nvs = EspNvs::new(partition, aces::ConfigSource::NVS_NAMESPACE, false)?;
+
+    let Some(len) = nvs.str_len(key)? else {
+        return Ok(None);
+    };
+    let mut buf = vec![0; len];
+    Ok(nvs.get_str(key, &mut buf)?.map(str::to_string))
 }
 
 mod device {
@@ -150,26 +148,6 @@ mod device {
         }
     }
 
-    pub fn response_for_request(req: &::aces::Request) -> Vec<u8> {
-        match req {
-            ::aces::Request::Clear => Vec::new(),
-            ::aces::Request::BatteryDetail => vec![
-                0xdd, 0x03, 0x00, 0x1d, 0x05, 0x38, 0x02, 0x83, 0x17, 0x5c, 0x27, 0xde, 0x00, 0x09,
-                0x2b, 0x94, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x3b, 0x03, 0x04, 0x03, 0x0b,
-                0x7f, 0x0b, 0x6c, 0x0b, 0x69, 0xfb, 0x07, 0x77,
-            ],
-            ::aces::Request::BatteryVoltage => vec![
-                0xdd, 0x04, 0x00, 0x08, 0x0d, 0xe2, 0x0d, 0xdc, 0x0d, 0xec, 0x0d, 0xed, 0xfc, 0x2d,
-                0x77,
-            ],
-            ::aces::Request::BatteryProtect => vec![
-                0xdd, 0xaa, 0x00, 0x16, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04,
-                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xe6,
-                0x77,
-            ],
-        }
-    }
-
     pub struct Device<'s> {
         server: &'s mut BLEServer,
         channel: channel::Channel,
@@ -185,14 +163,9 @@ mod device {
             self.channel.0.send(value);
         }
 
-        /// Tries to receive the next value immediately.
-        pub fn try_recv(&mut self) -> Option<Vec<u8>> {
-            self.channel.1.recv_timeout(Duration::from_millis(10))
-        }
-
-        /// Tries to receive the next value within a reasonable time.
-        pub fn recv_timeout(&mut self) -> Option<Vec<u8>> {
-            self.channel.1.recv_timeout(Duration::from_millis(500))
+        /// Tries to receive the next value within `timeout`.
+        pub fn recv_timeout(&mut self, timeout: Duration) -> Option<Vec<u8>> {
+            self.channel.1.recv_timeout(timeout)
         }
     }
 
@@ -201,17 +174,6 @@ mod device {
     use std::time::Duration;
 }
 
-impl fmt::Debug for StateMachine {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            StateMachine::Disconnected => write!(f, "Disconnected"),
-            StateMachine::Notify(req) => write!(f, "Notify({:?})", req),
-            StateMachine::ReadRequest(req) => write!(f, "ReadRequest({:x?})", req),
-            StateMachine::WriteResponse(resp) => write!(f, "WriteResponse({:x?})", resp),
-        }
-    }
-}
-
-use esp_idf_hal::task;
-use esp_idf_sys as _;
-use std::fmt;
+use esp_idf_hal::{delay::FreeRtos, task};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs};
+use esp_idf_sys::{self as _, EspError};
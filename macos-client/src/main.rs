@@ -1,8 +1,6 @@
 mod notifications;
-
-const SLEEP_DURATION: u64 = 30;
-/// The name of the target device (e.g. the device to connect to).
-const TARGET_DEVICE_NAME: &str = "AL12V100HFA0191";
+mod tools;
+mod transport;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
@@ -10,6 +8,51 @@ type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 async fn main() -> Result<()> {
     env_logger::init();
 
+    let mut args = std::env::args().skip(1).peekable();
+    let mode = match args.peek().map(String::as_str) {
+        Some("btsnoop") => return tools::btsnoop(args.skip(1)),
+        Some("dissect") => return tools::dissect(args.skip(1)),
+        Some("timeline") => return tools::timeline(args.skip(1)),
+        Some("history") => return tools::history(args.skip(1)),
+        Some("energy") => return tools::energy(args.skip(1)),
+        Some("resistance") => return tools::resistance(args.skip(1)),
+        Some("capacity") => return tools::capacity(args.skip(1)),
+        Some("cells") => return tools::cells(args.skip(1)),
+        Some("console") => {
+            args.next();
+            Mode::Console
+        }
+        Some("scan") => {
+            args.next();
+            Mode::Scan
+        }
+        Some("read-config") => {
+            args.next();
+            Mode::ReadConfig
+        }
+        Some("backup") => {
+            args.next();
+            Mode::Backup(file_argument(&mut args)?)
+        }
+        Some(command @ ("restore" | "clone")) => {
+            let clone = command == "clone";
+            args.next();
+            Mode::Restore {
+                file: file_argument(&mut args)?,
+                clone,
+            }
+        }
+        _ => Mode::Monitor,
+    };
+
+    let config = aces::ConfigSource::from_env_and_args(std::env::vars(), args)?.load()?;
+
+    if let Some(file) = &config.replay.file {
+        log::info!("replaying {}", file.display());
+        let transport = aces::ReplayTransport::new(aces::Capture::load(file)?, config.replay.speed);
+        return start(mode, transport, &config).await;
+    }
+
     let manager = Manager::new().await?;
     let adapters = manager.adapters().await?;
 
@@ -17,133 +60,337 @@ async fn main() -> Result<()> {
         eprintln!("no Bluetooth adapters found");
     }
 
-    let adapter = adapters.first().unwrap();
-    let peripheral = find_target_device(adapter).await?;
-    let (tx, rx) = connect_to_device(&peripheral).await?;
-
-    let mut notif = notifications::Notifications::subscribe(&peripheral, rx).await?;
+    let adapter = adapters.first().unwrap().clone();
+    let mut transport =
+        aces::CapturingTransport::new(transport::BleTransport::new(adapter, config.clone()));
+    if let Some(path) = &config.output.capture {
+        log::info!("capturing to {}", path.display());
+        transport = transport.with_capture(aces::CaptureWriter::create(path)?);
+    }
+    start(mode, transport, &config).await
+}
 
-    peripheral
-        .write(
-            &tx,
-            aces::Request::Clear.bytes(),
-            WriteType::WithoutResponse,
-        )
-        .await?;
+/// What to do with the connection.
+#[derive(Clone, Debug)]
+enum Mode {
+    /// Poll the battery, print and record its values and evaluate alerts.
+    Monitor,
+    /// Send frames typed on stdin, see `aces::Console`.
+    Console,
+    /// Read every register of `config.scan` and print the report, see
+    /// `aces::Scanner`.
+    Scan,
+    /// Read the configuration registers in factory mode and print them, see
+    /// `aces::BatteryConfig`.
+    ReadConfig,
+    /// Read the configuration registers and save them to a TOML or JSON file.
+    Backup(PathBuf),
+    /// Write the registers of a saved configuration that differ from the
+    /// battery, after confirmation. With `clone`, the pack specific registers
+    /// of the battery are kept, see `aces::BatteryConfig::cloned_onto`.
+    Restore { file: PathBuf, clone: bool },
+}
 
-    tokio::time::sleep(Duration::from_secs(1)).await;
-    // clear any stale notifications
-    notif.clear();
+/// The file following a subcommand.
+fn file_argument(args: &mut impl Iterator<Item = String>) -> Result<PathBuf> {
+    Ok(args.next().ok_or("missing configuration file")?.into())
+}
 
-    loop {
-        println!("local time: {}", chrono::Local::now().to_rfc3339());
-
-        peripheral
-            .write(
-                &tx,
-                aces::Request::BatteryVoltage.bytes(),
-                WriteType::WithoutResponse,
-            )
-            .await?;
-        let voltage = aces::read_voltage(&mut notif).await?;
-        println!("voltage: {:#?}", voltage);
-
-        peripheral
-            .write(
-                &tx,
-                aces::Request::BatteryDetail.bytes(),
-                WriteType::WithoutResponse,
-            )
-            .await?;
-        let detail = aces::read_detail(&mut notif).await?;
-        println!("detail: {:#?}", detail);
-
-        peripheral
-            .write(
-                &tx,
-                aces::Request::BatteryProtect.bytes(),
-                WriteType::WithoutResponse,
-            )
-            .await?;
-        let protect = aces::read_protect(&mut notif).await?;
-        println!("protect: {:#?}", protect);
-
-        log::info!("sleeping for {} seconds", SLEEP_DURATION);
-        tokio::time::sleep(Duration::from_secs(SLEEP_DURATION)).await;
-        println!();
+async fn start<T>(mode: Mode, transport: T, config: &aces::Config) -> Result<()>
+where
+    T: aces::Transport,
+{
+    let mut supervisor = aces::ConnectionSupervisor::new(
+        transport,
+        transport::TokioClock::new(),
+        &config.connection,
+    );
+    match mode {
+        Mode::Monitor => run(supervisor, config).await,
+        Mode::Console => console(supervisor, config).await,
+        Mode::Scan => {
+            let report = aces::Scanner::from_config(&config.scan)
+                .scan(&mut supervisor)
+                .await;
+            print!("{}", report);
+            Ok(())
+        }
+        Mode::ReadConfig => {
+            let timeout = Duration::from_secs(config.connection.response_timeout);
+            let writer = aces::SafeWriter::from_config(&config.write, timeout);
+            let battery = aces::read_battery_config(&mut supervisor, &writer).await?;
+            print!("{}", battery.to_toml()?);
+            Ok(())
+        }
+        Mode::Backup(file) => {
+            let timeout = Duration::from_secs(config.connection.response_timeout);
+            let writer = aces::SafeWriter::from_config(&config.write, timeout);
+            let battery = aces::read_battery_config(&mut supervisor, &writer).await?;
+            battery.save(&file)?;
+            println!("saved the configuration to {}", file.display());
+            Ok(())
+        }
+        Mode::Restore { file, clone } => restore(supervisor, config, &file, clone).await,
     }
 }
 
-async fn find_target_device(adapter: &Adapter) -> Result<Peripheral> {
-    log::info!("starting scan ...");
+async fn restore<T>(
+    mut supervisor: aces::ConnectionSupervisor<T, transport::TokioClock>,
+    config: &aces::Config,
+    file: &Path,
+    clone: bool,
+) -> Result<()>
+where
+    T: aces::Transport,
+{
+    let timeout = Duration::from_secs(config.connection.response_timeout);
+    let mut target = aces::BatteryConfig::load(file)?;
+    let writer = aces::SafeWriter::from_config(&config.write, timeout);
+    let live = aces::read_battery_config(&mut supervisor, &writer).await?;
+    if clone {
+        target = target.cloned_onto(&live)?;
+    }
 
-    adapter.start_scan(ScanFilter::default()).await?;
-    tokio::time::sleep(Duration::from_secs(3)).await;
-    let peripherals = adapter.peripherals().await?;
+    let violations = target.validate()?;
+    if !violations.is_empty() {
+        return Err(aces::SafeWriteError::Invalid(violations).into());
+    }
+    let changes = live.diff(&target)?;
+    if changes.is_empty() {
+        println!("the battery already has this configuration");
+        return Ok(());
+    }
+    for change in &changes {
+        println!("{}", change);
+    }
+
+    if !writer.is_dry_run() {
+        print!("write {} register(s)? [y/N] ", changes.len());
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        if answer.trim() != "y" {
+            println!("nothing written");
+            return Ok(());
+        }
+    }
+
+    let report = writer.write_config(&mut supervisor, &live, &target).await?;
+    if report.dry_run {
+        println!("dry run, the frames are not sent:");
+        for frame in &report.frames {
+            println!("> {}", aces::to_hex(&frame.bytes()));
+        }
+    } else {
+        println!("wrote and verified {} register(s)", report.changes.len());
+    }
+    Ok(())
+}
 
-    log::info!("finished scan");
+async fn console<T>(
+    mut supervisor: aces::ConnectionSupervisor<T, transport::TokioClock>,
+    config: &aces::Config,
+) -> Result<()>
+where
+    T: aces::Transport,
+{
+    let timeout = Duration::from_secs(config.console.timeout);
+    let mut console = aces::Console::from_config(&config.console)?
+        .writer(aces::SafeWriter::from_config(&config.write, timeout));
+    println!("type help for the commands, frames are read-only until unlock");
 
-    for peripheral in peripherals {
-        // filter peripherals
-        let properties = match peripheral.properties().await {
-            Ok(Some(properties)) => properties,
-            _ => continue,
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        print!("{}", console.prompt());
+        io::stdout().flush()?;
+        let Some(line) = lines.next() else {
+            return Ok(());
         };
-        if !properties
-            .local_name
-            .clone()
-            .unwrap_or_default()
-            .contains(TARGET_DEVICE_NAME)
-        {
-            continue;
+        match console.execute(&line?, &mut supervisor).await {
+            Ok(Some(out)) => print!("{}", out),
+            Ok(None) => return Ok(()),
+            Err(err) => eprintln!("{}", err),
         }
+    }
+}
 
-        return Ok(peripheral);
+async fn run<T>(
+    mut supervisor: aces::ConnectionSupervisor<T, transport::TokioClock>,
+    config: &aces::Config,
+) -> Result<()>
+where
+    T: aces::Transport,
+{
+    let mut sinks: Vec<Box<dyn aces::Sink>> = Vec::new();
+    let telemetry = &config.output.telemetry;
+    if telemetry.enabled {
+        sinks.push(Box::new(aces::TelemetryLogger::new(
+            &telemetry.directory,
+            telemetry.format,
+            telemetry.rotation,
+            telemetry.compress,
+        )));
     }
 
-    log::info!("ACES battery not found");
-    Err(NotFound.into())
-}
+    sinks.push(Box::new(aces::EnergyAccumulator::from_config(
+        &config.energy,
+    )?));
+    sinks.push(Box::new(aces::ResistanceEstimator::from_config(
+        &config.resistance,
+    )?));
+    sinks.push(Box::new(aces::ImbalanceTracker::from_config(
+        &config.imbalance,
+    )?));
+    if config.history.enabled {
+        log::info!("recording history to {}", config.history.path.display());
+        sinks.push(Box::new(aces::HistoryStore::from_config(&config.history)?));
+    }
+
+    log::info!("loaded {} alert rule(s)", config.alerts.rules.len());
+    let mut alerts = aces::AlertEngine::new(config.alerts.clone());
+    let mut protection = aces::ProtectionTracker::from_config(&config.protection)?;
+    let mut sessions = aces::SessionDetector::new(config.sessions.clone());
+    let mut charger = aces::ChargerClassifier::new(config.charger.clone());
+    let mut capacity = aces::CapacityTracker::from_config(&config.capacity)?;
+    let battery = config
+        .device
+        .address
+        .clone()
+        .unwrap_or_else(|| config.device.name.clone());
 
-async fn connect_to_device(peripheral: &Peripheral) -> Result<(Characteristic, Characteristic)> {
-    if !peripheral.is_connected().await? {
-        log::info!("connecting to device ...");
-        peripheral.connect().await?;
+    let connected = supervisor.connect().await;
+    for event in supervisor.take_events() {
+        emit(&mut sinks, event);
     }
+    connected?;
 
-    log::info!("connected to device");
+    let mut scheduler = aces::Scheduler::from_config(&config.poll);
+    let mut voltage = None;
+    let mut detail = None;
+    let mut protect = None;
 
-    log::info!("discovering services ...");
-    peripheral.discover_services().await?;
+    loop {
+        let due = scheduler.wait(supervisor.clock()).await;
 
-    let characteristics = peripheral.characteristics();
+        let now = chrono::Local::now();
+        if config.output.stdout {
+            println!("local time: {}", now.to_rfc3339());
+        }
 
-    let rx = match characteristics
-        .iter()
-        .find(|char| char.uuid == uuid_from_u16(0xff01))
-    {
-        Some(char) => char,
-        _ => return Err(NotFound.into()),
-    };
+        let mut failed = false;
+        let mut closed = false;
+        for register in due.iter().copied() {
+            let polled = match register {
+                aces::Register::Voltage => supervisor
+                    .request_voltage()
+                    .await
+                    .map(|value| voltage.insert(value) as &dyn std::fmt::Debug),
+                aces::Register::Detail => supervisor
+                    .request_detail()
+                    .await
+                    .map(|value| detail.insert(value) as &dyn std::fmt::Debug),
+                aces::Register::Protect => supervisor
+                    .request_protect()
+                    .await
+                    .map(|value| protect.insert(value) as &dyn std::fmt::Debug),
+            };
+            match polled {
+                Ok(value) if config.output.stdout => println!("{:?}: {:#?}", register, value),
+                Ok(_) => {}
+                Err(err) => {
+                    log::error!("failed to poll {:?}: {}", register, err);
+                    failed = true;
+                    closed |= err.is::<aces::TransportClosed>();
+                }
+            }
+        }
+        for event in supervisor.take_events() {
+            emit(&mut sinks, event);
+        }
+        // e.g. a replayed capture that has ended
+        if closed {
+            log::info!("the connection is closed, stopping");
+            return Ok(());
+        }
+        // the cached value of a failed register is stale, so the trackers
+        // would see a gap as a steady state
+        if failed {
+            continue;
+        }
 
-    let tx = match characteristics
-        .iter()
-        .find(|char| char.uuid == uuid_from_u16(0xff02))
-    {
-        Some(char) => char,
-        _ => return Err(NotFound.into()),
-    };
+        // alerts need every register, so wait for the first poll of each
+        let (Some(voltage), Some(detail), Some(protect)) = (&voltage, &detail, &protect) else {
+            continue;
+        };
+        let snapshot = aces::Snapshot {
+            timestamp: now.fixed_offset(),
+            voltage: voltage.clone(),
+            detail: detail.clone(),
+            protect: protect.clone(),
+            refreshed: due,
+        };
+        let mut fired = alerts.evaluate(&snapshot);
+        let phase = charger.phase();
+        fired.extend(charger.update(&snapshot));
+        if charger.phase() != phase {
+            println!("charger: {}", charger.phase());
+        }
+        match protection.update(&battery, &snapshot) {
+            Ok(events) => {
+                for event in events {
+                    println!("protection: {}", event);
+                    emit(&mut sinks, aces::Event::Protection(event));
+                }
+            }
+            Err(err) => log::error!("failed to track the protection counters: {}", err),
+        }
+        match capacity.update(&snapshot) {
+            Ok(Some(measurement)) => {
+                println!("capacity: {}", measurement);
+                if let Some(warning) = capacity.warning(&measurement) {
+                    log::warn!("{}", warning);
+                }
+            }
+            Ok(None) => {}
+            Err(err) => log::error!("failed to track the capacity: {}", err),
+        }
+        if let Some(session) = sessions.update(&snapshot) {
+            println!("session: {}", session);
+            emit(&mut sinks, aces::Event::Session(session));
+        }
+        emit(&mut sinks, aces::Event::Snapshot(snapshot));
+        for alert in fired {
+            println!(
+                "alert: {:?} {} ({:?}): {}",
+                alert.state, alert.rule, alert.severity, alert.message
+            );
+            if alert.state == aces::AlertState::Fired {
+                scheduler.fast_poll(
+                    supervisor.clock().now(),
+                    Duration::from_secs(config.poll.fast_duration),
+                );
+            }
+            emit(&mut sinks, aces::Event::Alert(alert));
+        }
 
-    Ok((tx.clone(), rx.clone()))
+        if config.output.stdout {
+            println!();
+        }
+    }
 }
 
-#[derive(thiserror::Error, Debug)]
-#[error("Not found")]
-struct NotFound;
+/// Sends the event to every sink, logging failures instead of stopping the runner.
+fn emit(sinks: &mut [Box<dyn aces::Sink>], event: aces::Event) {
+    for sink in sinks.iter_mut() {
+        if let Err(err) = sink.handle(&event) {
+            log::error!("failed to write to sink: {}", err);
+        }
+    }
+}
 
-use btleplug::api::bleuuid::uuid_from_u16;
-use btleplug::api::{
-    Central, Characteristic, Manager as _, Peripheral as _, ScanFilter, WriteType,
-};
-use btleplug::platform::{Adapter, Manager, Peripheral};
+use aces::Clock as _;
+use btleplug::api::Manager as _;
+use btleplug::platform::Manager;
+use std::io::{self, BufRead as _, Write as _};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
@@ -1,5 +1,7 @@
 pub struct Notifications {
-    rx: sync::Receiver<Vec<u8>>,
+    /// The notifications, with their arrival time.
+    rx: sync::Receiver<(Instant, Vec<u8>)>,
+    arrived: Option<Instant>,
 }
 
 impl Notifications {
@@ -13,29 +15,36 @@ impl Notifications {
         let (tx, rx) = sync::channel();
 
         tokio::task::spawn(async move {
-            loop {
-                if let Some(notif) = notifs.next().await {
-                    log::trace!("received notification item from stream");
-                    tx.send(notif.value);
-                } else {
-                    log::trace!("did not receive notification item from stream");
-                }
+            // the stream ends when the device disconnects
+            while let Some(notif) = notifs.next().await {
+                log::trace!("received notification item from stream");
+                tx.send((Instant::now(), notif.value));
                 tokio::task::yield_now().await;
             }
+            log::debug!("notification stream ended");
         });
 
-        Ok(Notifications { rx })
-    }
-
-    pub fn clear(&mut self) {
-        self.rx.clear();
+        Ok(Notifications { rx, arrived: None })
     }
 }
 
 impl aces::NotificationsReceiver for Notifications {
     fn next(&mut self) -> Vec<u8> {
         log::debug!("awaiting next notification");
-        self.rx.recv()
+        let (arrived, value) = self.rx.recv();
+        self.arrived = Some(arrived);
+        value
+    }
+
+    fn next_timeout(&mut self, timeout: Duration) -> Option<Vec<u8>> {
+        log::debug!("awaiting next notification ({:?})", timeout);
+        let (arrived, value) = self.rx.recv_timeout(timeout)?;
+        self.arrived = Some(arrived);
+        Some(value)
+    }
+
+    fn arrived(&self) -> Option<Instant> {
+        self.arrived
     }
 }
 
@@ -72,10 +81,18 @@ mod sync {
             val
         }
 
-        pub fn clear(&self) {
-            let mut locked = self.0 .0 .0.lock().unwrap();
-            locked.clear();
-            self.0 .0 .1.notify_one();
+        pub fn recv_timeout(&self, timeout: Duration) -> Option<T> {
+            let (queue, condvar) = &*self.0 .0;
+            let locked = queue.lock().unwrap();
+
+            // protect agains spurious wake-ups
+            let (mut locked, _) = condvar
+                .wait_timeout_while(locked, timeout, |queue| queue.is_empty())
+                .unwrap();
+
+            let val = locked.pop_front();
+            condvar.notify_one();
+            val
         }
     }
 
@@ -90,6 +107,7 @@ mod sync {
     use std::{
         collections::VecDeque,
         sync::{Arc, Condvar, Mutex},
+        time::Duration,
     };
 }
 
@@ -98,3 +116,4 @@ use btleplug::{
     platform::Peripheral,
 };
 use futures::StreamExt;
+use std::time::{Duration, Instant};
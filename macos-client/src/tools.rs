@@ -0,0 +1,430 @@
+//! Subcommands working on recorded data, without a device.
+
+/// `btsnoop <file> [--capture <path>]`: decodes the frames of a btsnoop file,
+/// optionally saving its chunks as a capture to replay.
+pub fn btsnoop<A>(args: A) -> Result<()>
+where
+    A: IntoIterator<Item = String>,
+{
+    let mut file = None;
+    let mut capture_path = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--capture" => capture_path = Some(args.next().ok_or("missing value for --capture")?),
+            _ if file.is_none() => file = Some(arg),
+            _ => return Err(format!("unknown argument {}", arg).into()),
+        }
+    }
+    let file = file.ok_or("usage: btsnoop <file> [--capture <path>]")?;
+
+    let capture = aces::Capture::load_btsnoop(&file)?;
+    if capture.records.is_empty() {
+        eprintln!("no ACES traffic found in {}", file);
+    }
+    if let Some(path) = capture_path {
+        let mut writer = aces::CaptureWriter::create(path)?;
+        for record in &capture.records {
+            writer.append(record)?;
+        }
+    }
+
+    for record in capture.frames() {
+        println!(
+            "{:.6}s {}",
+            record.t as f64 / 1e6,
+            format!("{:?}", record.direction).to_lowercase()
+        );
+        println!("{}", aces::dissect(&record.data));
+    }
+    Ok(())
+}
+
+/// `dissect [<hex>...]`: takes apart hex frames (e.g. `DDA50300FFFD77`) given
+/// as arguments, or one per line on stdin.
+pub fn dissect<A>(args: A) -> Result<()>
+where
+    A: IntoIterator<Item = String>,
+{
+    let mut args = args.into_iter().peekable();
+    if args.peek().is_some() {
+        args.for_each(|arg| dissect_hex(&arg));
+    } else {
+        for line in io::stdin().lock().lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                dissect_hex(&line);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `timeline [--battery <name>] [--counter <name>] [--since <time>] [--until
+/// <time>] [config arguments]`: lists the protection events recorded by the
+/// monitor (see `aces::ProtectionTracker`), then the total of each counter.
+/// Times are RFC 3339, e.g. `2026-10-01T00:00:00+02:00`.
+pub fn timeline<A>(args: A) -> Result<()>
+where
+    A: IntoIterator<Item = String>,
+{
+    let mut filter = aces::TimelineFilter::default();
+    let mut config_args = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--battery" => filter.battery = Some(value()?),
+            "--counter" => filter.counter = Some(value()?),
+            "--since" => filter.since = Some(DateTime::parse_from_rfc3339(&value()?)?),
+            "--until" => filter.until = Some(DateTime::parse_from_rfc3339(&value()?)?),
+            _ => config_args.push(arg),
+        }
+    }
+    let config = aces::ConfigSource::from_env_and_args(std::env::vars(), config_args)?.load()?;
+    let path = config
+        .protection
+        .timeline
+        .ok_or("no protection.timeline configured")?;
+    if !path.exists() {
+        println!("no protection events recorded in {}", path.display());
+        return Ok(());
+    }
+
+    let timeline = aces::ProtectionTimeline::load(&path)?;
+    for event in timeline.query(&filter) {
+        println!("{}", event);
+    }
+    println!();
+    for (counter, total) in timeline.totals(&filter) {
+        println!("{:<26}{}", counter, total);
+    }
+    Ok(())
+}
+
+/// `history <query|csv|stats> [--since <time>] [--until <time>] [--field
+/// <name>]... [--resolution raw|minute|hour] [config arguments]`: reads the
+/// history store (see `aces::HistoryStore`). `query` lists the samples, `csv`
+/// exports them and `stats` shows the minimum, maximum and average of each
+/// field. Times are RFC 3339 or an age such as `30m`, `12h` or `7d`; the
+/// range defaults to the last day and the resolution to the finest one still
+/// holding its start.
+pub fn history<A>(args: A) -> Result<()>
+where
+    A: IntoIterator<Item = String>,
+{
+    const USAGE: &str = "usage: history <query|csv|stats> [--since <time>] [--until <time>] \
+[--field <name>]... [--resolution raw|minute|hour]";
+    let mut args = args.into_iter();
+    let command = args.next().ok_or(USAGE)?;
+    let now = Utc::now().timestamp();
+    let mut since = now - 24 * 3600;
+    let mut until = now;
+    let mut fields = Vec::new();
+    let mut resolution = None;
+    let mut config_args = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--since" => since = parse_time(&value()?, now)?,
+            "--until" => until = parse_time(&value()?, now)?,
+            "--field" => fields.push(value()?),
+            "--resolution" => {
+                let name = value()?;
+                resolution = Some(
+                    aces::Resolution::from_name(&name)
+                        .ok_or(format!("unknown resolution {}", name))?,
+                );
+            }
+            _ => config_args.push(arg),
+        }
+    }
+    let config = aces::ConfigSource::from_env_and_args(std::env::vars(), config_args)?.load()?;
+    let store = aces::HistoryStore::from_config(&config.history)?;
+    let resolution = resolution.unwrap_or_else(|| store.resolution_for(since, now));
+
+    match command.as_str() {
+        "query" => {
+            println!(
+                "{:<26}{:<26}{:>10}{:>10}{:>10}{:>7}",
+                "time", "field", "min", "max", "avg", "count"
+            );
+            for sample in store.query(resolution, since..until, &fields)? {
+                println!(
+                    "{:<26}{:<26}{:>10}{:>10}{:>10.1}{:>7}",
+                    local_time(sample.timestamp),
+                    sample.field,
+                    sample.min,
+                    sample.max,
+                    sample.avg,
+                    sample.count
+                );
+            }
+        }
+        "csv" => {
+            let samples = store.query(resolution, since..until, &fields)?;
+            aces::write_samples_csv(&samples, io::stdout().lock())?;
+        }
+        "stats" => {
+            println!(
+                "# {} to {} ({})",
+                local_time(since),
+                local_time(until),
+                resolution.name()
+            );
+            println!(
+                "{:<26}{:>10}{:>10}{:>10}{:>9}",
+                "field", "min", "max", "avg", "count"
+            );
+            for stats in store.stats(resolution, since..until, &fields)? {
+                println!(
+                    "{:<26}{:>10}{:>10}{:>10.1}{:>9}",
+                    stats.field, stats.min, stats.max, stats.avg, stats.count
+                );
+            }
+        }
+        _ => return Err(USAGE.into()),
+    }
+    Ok(())
+}
+
+/// `energy [--period day|week|month] [config arguments]`: the energy charged
+/// and discharged per period, recorded by the monitor (see
+/// `aces::EnergyAccumulator`), then the totals.
+pub fn energy<A>(args: A) -> Result<()>
+where
+    A: IntoIterator<Item = String>,
+{
+    let mut period = aces::EnergyPeriod::Day;
+    let mut config_args = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--period" => {
+                let name = args.next().ok_or("missing value for --period")?;
+                period = aces::EnergyPeriod::from_name(&name)
+                    .ok_or(format!("unknown period {}", name))?;
+            }
+            _ => config_args.push(arg),
+        }
+    }
+    let config = aces::ConfigSource::from_env_and_args(std::env::vars(), config_args)?.load()?;
+    if !config
+        .energy
+        .state
+        .as_ref()
+        .is_some_and(|path| path.exists())
+    {
+        println!("no energy recorded yet");
+        return Ok(());
+    }
+    let energy = aces::EnergyAccumulator::from_config(&config.energy)?;
+
+    for report in energy.report(period) {
+        println!("{}", report);
+    }
+    println!("{}", energy.total());
+    if let Some(cycles) = energy.bms_cycles() {
+        println!("the BMS counts {} cycles", cycles);
+    }
+    Ok(())
+}
+
+/// `resistance [--daily] [config arguments]`: the trend of the internal
+/// resistance of the pack and each cell, estimated by the monitor (see
+/// `aces::ResistanceEstimator`), then the cells rising faster than the rest.
+pub fn resistance<A>(args: A) -> Result<()>
+where
+    A: IntoIterator<Item = String>,
+{
+    let mut daily = false;
+    let mut config_args = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--daily" => daily = true,
+            _ => config_args.push(arg),
+        }
+    }
+    let config = aces::ConfigSource::from_env_and_args(std::env::vars(), config_args)?.load()?;
+    if !config
+        .resistance
+        .state
+        .as_ref()
+        .is_some_and(|path| path.exists())
+    {
+        println!("no resistance estimated yet");
+        return Ok(());
+    }
+    let estimator = aces::ResistanceEstimator::from_config(&config.resistance)?;
+
+    let format = |value: Option<f64>| value.map_or("-".to_string(), |v| format!("{:.2}", v));
+    if daily {
+        for day in estimator.daily() {
+            let cells: Vec<String> = day.cells.iter().copied().map(format).collect();
+            println!(
+                "{}  {:>4} steps  pack {} mΩ  cells {} mΩ",
+                day.date,
+                day.estimates,
+                format(day.pack),
+                cells.join(" ")
+            );
+        }
+    }
+    for trend in estimator.trend() {
+        println!("{}", trend);
+    }
+    for warning in estimator.warnings() {
+        println!("warning: {}", warning);
+    }
+    Ok(())
+}
+
+/// `capacity [--csv] [config arguments]`: the capacity measured over each
+/// discharge (see `aces::CapacityTracker`), then the state of health and its
+/// fade. `--csv` prints the measurements to plot the fade instead.
+pub fn capacity<A>(args: A) -> Result<()>
+where
+    A: IntoIterator<Item = String>,
+{
+    let mut csv = false;
+    let mut config_args = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--csv" => csv = true,
+            _ => config_args.push(arg),
+        }
+    }
+    let config = aces::ConfigSource::from_env_and_args(std::env::vars(), config_args)?.load()?;
+    if !config
+        .capacity
+        .state
+        .as_ref()
+        .is_some_and(|path| path.exists())
+    {
+        println!("no capacity measured yet");
+        return Ok(());
+    }
+    let tracker = aces::CapacityTracker::from_config(&config.capacity)?;
+
+    if csv {
+        println!("time,kind,cycles,capacity_ah,soc_span,soh,bms_capacity_ah");
+        for m in tracker.measurements() {
+            let optional =
+                |value: Option<f64>| value.map_or(String::new(), |v| format!("{:.4}", v));
+            println!(
+                "{},{},{},{:.3},{},{},{}",
+                m.timestamp.to_rfc3339(),
+                m.kind,
+                m.cycles,
+                m.capacity,
+                m.soc_span,
+                optional(m.soh()),
+                optional(m.bms_capacity)
+            );
+        }
+        return Ok(());
+    }
+
+    for measurement in tracker.measurements() {
+        println!("{}", measurement);
+        if let Some(warning) = tracker.warning(measurement) {
+            println!("  warning: {}", warning);
+        }
+    }
+    if let Some(soh) = tracker.soh() {
+        println!("state of health {:.1} %", soh * 100.0);
+    }
+    let fade = tracker.fade();
+    if let Some(fade) = fade.per_100_cycles {
+        println!("fading {:.2} % per 100 cycles", fade * 100.0);
+    }
+    if let Some(fade) = fade.per_year {
+        println!("fading {:.2} % per year", fade * 100.0);
+    }
+    Ok(())
+}
+
+/// `cells [--weekly] [config arguments]`: the health of each cell from the
+/// imbalance at the ends of charge and discharge (see
+/// `aces::ImbalanceTracker`), explaining why a cell is weak.
+pub fn cells<A>(args: A) -> Result<()>
+where
+    A: IntoIterator<Item = String>,
+{
+    let mut weekly = false;
+    let mut config_args = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--weekly" => weekly = true,
+            _ => config_args.push(arg),
+        }
+    }
+    let config = aces::ConfigSource::from_env_and_args(std::env::vars(), config_args)?.load()?;
+    if !config
+        .imbalance
+        .state
+        .as_ref()
+        .is_some_and(|path| path.exists())
+    {
+        println!("no imbalance recorded yet");
+        return Ok(());
+    }
+    let tracker = aces::ImbalanceTracker::from_config(&config.imbalance)?;
+
+    if weekly {
+        for week in tracker.weeks() {
+            println!("{}", week);
+        }
+    }
+    if let Some(growth) = tracker.growth() {
+        println!("imbalance growing {:+.1} mV per week", growth);
+    }
+    for health in tracker.health() {
+        println!("{}", health);
+    }
+    for health in tracker.weak_cells() {
+        println!("weak: cell {}", health.cell);
+    }
+    Ok(())
+}
+
+/// Parses an RFC 3339 time or an age before `now` (`30s`, `30m`, `12h`,
+/// `7d`), returning seconds since the Unix epoch.
+fn parse_time(value: &str, now: i64) -> Result<i64> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.timestamp());
+    }
+    let unit = match value.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 3600,
+        Some('d') => 24 * 3600,
+        _ => return Err(format!("invalid time {}", value).into()),
+    };
+    let amount: i64 = value[..value.len() - 1]
+        .parse()
+        .map_err(|_| format!("invalid time {}", value))?;
+    Ok(now - amount * unit)
+}
+
+fn local_time(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0).map_or_else(
+        || timestamp.to_string(),
+        |time| {
+            time.with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        },
+    )
+}
+
+fn dissect_hex(hex: &str) {
+    match aces::from_hex(hex) {
+        Some(bytes) => println!("{}", aces::dissect(&bytes)),
+        None => eprintln!("invalid hex: {}\n", hex.trim()),
+    }
+}
+
+use crate::Result;
+use chrono::{DateTime, Local, Utc};
+use std::io::{self, BufRead as _};
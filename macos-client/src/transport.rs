@@ -0,0 +1,166 @@
+/// A connection to the battery over btleplug.
+pub struct BleTransport {
+    adapter: Adapter,
+    config: aces::Config,
+    connection: Option<(Peripheral, Characteristic)>,
+}
+
+/// A clock backed by the tokio timer.
+pub struct TokioClock(Instant);
+
+impl BleTransport {
+    pub fn new(adapter: Adapter, config: aces::Config) -> Self {
+        BleTransport {
+            adapter,
+            config,
+            connection: None,
+        }
+    }
+}
+
+impl TokioClock {
+    pub fn new() -> Self {
+        TokioClock(Instant::now())
+    }
+}
+
+impl aces::Transport for BleTransport {
+    type Receiver = Notifications;
+
+    async fn connect(&mut self) -> Result<Notifications> {
+        if let Some((peripheral, _)) = self.connection.take() {
+            let _ = peripheral.disconnect().await;
+        }
+
+        let peripheral = find_target_device(&self.adapter, &self.config).await?;
+        let (tx, rx) = tokio::time::timeout(
+            Duration::from_secs(self.config.connection.connect_timeout),
+            connect_to_device(&peripheral),
+        )
+        .await??;
+
+        let notif = Notifications::subscribe(&peripheral, rx).await?;
+        self.connection = Some((peripheral, tx));
+        Ok(notif)
+    }
+
+    async fn is_connected(&mut self) -> bool {
+        match &self.connection {
+            Some((peripheral, _)) => peripheral.is_connected().await.unwrap_or(false),
+            None => false,
+        }
+    }
+
+    async fn write(&mut self, value: &[u8]) -> Result<()> {
+        let (peripheral, tx) = self.connection.as_ref().ok_or(NotFound)?;
+        peripheral
+            .write(tx, value, WriteType::WithoutResponse)
+            .await?;
+        Ok(())
+    }
+}
+
+impl aces::Clock for TokioClock {
+    fn now(&self) -> Duration {
+        self.0.elapsed()
+    }
+
+    async fn sleep(&mut self, duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+}
+
+async fn find_target_device(adapter: &Adapter, config: &aces::Config) -> Result<Peripheral> {
+    log::info!("starting scan ...");
+
+    adapter.start_scan(ScanFilter::default()).await?;
+    tokio::time::sleep(Duration::from_secs(config.connection.scan_timeout)).await;
+    let peripherals = adapter.peripherals().await?;
+
+    log::info!("finished scan");
+
+    let matcher = aces::Matcher::from_config(&config.device)
+        .expect("the device is checked when the configuration is loaded");
+
+    let mut candidates = Vec::new();
+    for peripheral in peripherals {
+        let properties = match peripheral.properties().await {
+            Ok(Some(properties)) => properties,
+            _ => continue,
+        };
+        candidates.push((peripheral, advertisement(properties)));
+    }
+
+    if let Some(idx) = matcher.best(candidates.iter().map(|(_, adv)| adv)) {
+        let (peripheral, best) = candidates.swap_remove(idx);
+        log::info!(
+            "found {:?} ({}, rssi {:?})",
+            best.name,
+            best.address,
+            best.rssi
+        );
+        return Ok(peripheral);
+    }
+
+    log::info!("ACES battery not found");
+    Err(NotFound.into())
+}
+
+fn advertisement(properties: PeripheralProperties) -> aces::Advertisement {
+    aces::Advertisement {
+        name: properties.local_name,
+        address: properties.address.to_string(),
+        service_uuids: properties
+            .services
+            .iter()
+            .map(|uuid| uuid.as_u128())
+            .collect(),
+        manufacturer_data: properties.manufacturer_data,
+        rssi: properties.rssi,
+    }
+}
+
+async fn connect_to_device(peripheral: &Peripheral) -> Result<(Characteristic, Characteristic)> {
+    if !peripheral.is_connected().await? {
+        log::info!("connecting to device ...");
+        peripheral.connect().await?;
+    }
+
+    log::info!("connected to device");
+
+    log::info!("discovering services ...");
+    peripheral.discover_services().await?;
+
+    let characteristics = peripheral.characteristics();
+
+    let rx = match characteristics
+        .iter()
+        .find(|char| char.uuid == uuid_from_u16(aces::RX_UUID))
+    {
+        Some(char) => char,
+        _ => return Err(NotFound.into()),
+    };
+
+    let tx = match characteristics
+        .iter()
+        .find(|char| char.uuid == uuid_from_u16(aces::TX_UUID))
+    {
+        Some(char) => char,
+        _ => return Err(NotFound.into()),
+    };
+
+    Ok((tx.clone(), rx.clone()))
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Not found")]
+struct NotFound;
+
+use crate::notifications::Notifications;
+use aces::Result;
+use btleplug::api::bleuuid::uuid_from_u16;
+use btleplug::api::{
+    Central, Characteristic, Peripheral as _, PeripheralProperties, ScanFilter, WriteType,
+};
+use btleplug::platform::{Adapter, Peripheral};
+use std::time::{Duration, Instant};